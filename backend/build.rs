@@ -1,4 +1,5 @@
 use std::{
+    collections::BTreeSet,
     env,
     fs::{self, File},
     io::Write,
@@ -11,6 +12,8 @@ fn main() {
 
     writeln!(outfile, "keyboards![").unwrap();
 
+    let mut keyboards = BTreeSet::new();
+
     println!("cargo:rerun-if-changed=../layouts/system76");
     for i in fs::read_dir("../layouts/system76").unwrap() {
         let i = i.unwrap();
@@ -26,15 +29,73 @@ fn main() {
                 panic!("'keyboard:' not found in {}", path.display());
             });
 
+        let board = format!("system76/{}", file_name);
+        let default_board = meta
+            .get("default_board")
+            .and_then(|x| x.as_str())
+            .unwrap_or(&board);
+        let default_path = Path::new("../layouts")
+            .join(default_board)
+            .join("default.json");
+        if !default_path.exists() {
+            panic!("'{}' not found for {}", default_path.display(), board);
+        }
+
         writeln!(
             outfile,
-            "    (\"system76/{}\", \"{}\"),",
-            file_name, keyboard
+            "    (\"{}\", \"{}\", \"{}\"),",
+            board, keyboard, default_board
         )
         .unwrap();
 
         println!("cargo:rerun-if-changed=../layouts/system76/{}", file_name);
         println!("cargo:rerun-if-changed=../layouts/keyboards/{}", keyboard);
+        keyboards.insert(keyboard.to_string());
+    }
+
+    writeln!(outfile, "];").unwrap();
+
+    let mut outfile = File::create(Path::new(&out_dir).join("variants.rs")).unwrap();
+
+    writeln!(outfile, "variants![").unwrap();
+
+    for keyboard in keyboards {
+        let variants_dir = Path::new("../layouts/keyboards")
+            .join(&keyboard)
+            .join("variants");
+        let variants = match fs::read_dir(&variants_dir) {
+            Ok(variants) => variants,
+            Err(_) => continue,
+        };
+        println!("cargo:rerun-if-changed={}", variants_dir.display());
+
+        let mut variants = variants
+            .map(|i| i.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        variants.sort();
+        for variant in variants {
+            let variant_dir = variants_dir.join(&variant);
+            if !variant_dir.join("physical.json").exists() {
+                panic!("'physical.json' not found in {}", variant_dir.display());
+            }
+            for file in &[
+                "physical.json",
+                "layout.json",
+                "leds.json",
+                "labels.json",
+                "default.json",
+            ] {
+                if variant_dir.join(file).exists() {
+                    writeln!(
+                        outfile,
+                        "    (\"{}\", \"{}\", \"{}\"),",
+                        keyboard, variant, file
+                    )
+                    .unwrap();
+                }
+            }
+            println!("cargo:rerun-if-changed={}", variant_dir.display());
+        }
     }
 
     writeln!(outfile, "];").unwrap();
@@ -8,7 +8,7 @@ use once_cell::sync::Lazy;
 use std::{cell::RefCell, collections::HashMap, process, sync::Arc, time::Duration};
 
 use crate::daemon::*;
-use crate::{Board, DerefCell};
+use crate::{BackendError, Board, DerefCell};
 
 #[derive(Default)]
 #[doc(hidden)]
@@ -30,6 +30,12 @@ impl ObjectImpl for BackendInner {
             vec![
                 Signal::builder("board-loading", &[], glib::Type::UNIT.into()).build(),
                 Signal::builder("board-loading-done", &[], glib::Type::UNIT.into()).build(),
+                Signal::builder(
+                    "board-error",
+                    &[String::static_type().into()],
+                    glib::Type::UNIT.into(),
+                )
+                .build(),
                 Signal::builder(
                     "board-added",
                     &[Board::static_type().into()],
@@ -57,7 +63,7 @@ glib::wrapper! {
 }
 
 impl Backend {
-    fn new_internal<T: Daemon + 'static>(daemon: T) -> Result<Self, String> {
+    pub(crate) fn new_internal<T: Daemon + 'static>(daemon: T) -> Result<Self, String> {
         let self_ = glib::Object::new::<Self>(&[]).unwrap();
         let thread_client = ThreadClient::new(
             Box::new(daemon),
@@ -69,15 +75,49 @@ impl Backend {
                     ThreadResponse::BoardLoadingDone => {
                         self_.emit_by_name("board-loading-done", &[]).unwrap();
                     },
+                    ThreadResponse::BoardError(err) => {
+                        self_.emit_by_name("board-error", &[&err]).unwrap();
+                    },
                     ThreadResponse::BoardAdded(board) => {
                         self_.emit_by_name("board-added", &[&board]).unwrap();
                         self_.inner().boards.borrow_mut().insert(board.board(), board);
                     },
                     ThreadResponse::BoardRemoved(id) => {
-                        let boards = self_.inner().boards.borrow();
-                        let board = &boards[&id];
-                        self_.emit_by_name("board-removed", &[board]).unwrap();
-                        board.emit_by_name("removed", &[]).unwrap();
+                        let board = self_.inner().boards.borrow_mut().remove(&id);
+                        if let Some(board) = board {
+                            self_.emit_by_name("board-removed", &[&board]).unwrap();
+                            board.emit_by_name("removed", &[]).unwrap();
+                        }
+                    },
+                    ThreadResponse::BoardDisconnected(id) => {
+                        let board = self_.inner().boards.borrow().get(&id).cloned();
+                        if let Some(board) = board {
+                            board.emit_by_name("disconnected", &[]).unwrap();
+                        }
+                    },
+                    ThreadResponse::BoardReconnected(id) => {
+                        let board = self_.inner().boards.borrow().get(&id).cloned();
+                        if let Some(board) = board {
+                            board.emit_by_name("reconnected", &[]).unwrap();
+                        }
+                    },
+                    ThreadResponse::BrightnessChanged(id, index, brightness) => {
+                        let board = self_.inner().boards.borrow().get(&id).cloned();
+                        if let Some(board) = board {
+                            board.brightness_changed(index, brightness);
+                        }
+                    },
+                    ThreadResponse::ColorChanged(id, index, color) => {
+                        let board = self_.inner().boards.borrow().get(&id).cloned();
+                        if let Some(board) = board {
+                            board.color_changed(index, color);
+                        }
+                    },
+                    ThreadResponse::LedsRefreshed(id, leds) => {
+                        let board = self_.inner().boards.borrow().get(&id).cloned();
+                        if let Some(board) = board {
+                            board.leds_refreshed(&leds);
+                        }
                     },
                 }
             }),
@@ -95,8 +135,14 @@ impl Backend {
         Self::new_internal(DaemonS76Power::new()?)
     }
 
+    /// Connect to the system daemon's Unix socket
+    #[cfg(target_os = "linux")]
+    pub fn new_socket() -> Result<Self, String> {
+        Self::new_internal(DaemonClient::new_socket(SOCKET_PATH)?)
+    }
+
     pub fn new_pkexec() -> Result<Self, String> {
-        Self::new_internal(DaemonClient::new_pkexec())
+        Self::new_internal(DaemonClient::new_pkexec()?)
     }
 
     pub fn new() -> Result<Self, String> {
@@ -113,12 +159,33 @@ impl Backend {
     pub fn refresh(&self) {
         let self_ = self.clone();
         glib::MainContext::default().spawn_local(async move {
-            if let Err(err) = self_.inner().thread_client.refresh().await {
+            if let Err(err) = self_.refresh_future().await {
                 error!("Failed to refresh boards: {}", err);
             }
         });
     }
 
+    /// Like `refresh`, but resolves when the daemon has finished refreshing
+    ///
+    /// Signals for added/removed boards are emitted from the default main
+    /// context, so it must be iterated before they are seen.
+    pub async fn refresh_future(&self) -> Result<(), BackendError> {
+        self.inner().thread_client.refresh().await
+    }
+
+    /// Boards currently known to the backend, ordered by model
+    pub fn boards(&self) -> Vec<Board> {
+        let mut boards = self
+            .inner()
+            .boards
+            .borrow()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        boards.sort_by(|a, b| (a.model(), a.board()).cmp(&(b.model(), b.board())));
+        boards
+    }
+
     pub fn set_matrix_get_rate(&self, rate: Option<Duration>) {
         let self_ = self.clone();
         glib::MainContext::default().spawn_local(async move {
@@ -142,6 +209,15 @@ impl Backend {
         .unwrap()
     }
 
+    /// Called with the error when a connected board can't be added
+    pub fn connect_board_error<F: Fn(String) + 'static>(&self, cb: F) -> SignalHandlerId {
+        self.connect_local("board-error", false, move |values| {
+            cb(values[1].get::<String>().unwrap().unwrap());
+            None
+        })
+        .unwrap()
+    }
+
     pub fn connect_board_added<F: Fn(Board) + 'static>(&self, cb: F) -> SignalHandlerId {
         self.connect_local("board-added", false, move |values| {
             cb(values[1].get::<Board>().unwrap().unwrap());
@@ -164,3 +240,14 @@ pub fn run_daemon() -> ! {
     server.run().expect("Failed to run server");
     process::exit(0)
 }
+
+/// Run daemon as a service, on a Unix socket
+#[cfg(target_os = "linux")]
+pub fn run_daemon_socket() -> ! {
+    let listener = crate::daemon::listen_socket().expect("Failed to listen on socket");
+    let server = DaemonServer::new_socket().expect("Failed to create server");
+    SocketServer::new(server, listener)
+        .run()
+        .expect("Failed to run server");
+    process::exit(0)
+}
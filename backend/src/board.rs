@@ -5,12 +5,18 @@ use glib::{
     SignalHandlerId,
 };
 use once_cell::sync::Lazy;
-use std::{cell::Cell, collections::HashMap, sync::Arc};
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use crate::daemon::ThreadClient;
 use crate::{
-    Benchmark, BoardId, Daemon, DerefCell, Key, KeyMap, KeyMapLayer, Layer, Layout, Matrix, Nelson,
-    NelsonKind,
+    save_variant, saved_variant, BackendError, Benchmark, BoardCapabilities, BoardId, Daemon,
+    DerefCell, Hs, Key, KeyMap, KeyMapConversion, KeyMapDiff, KeyMapLayer, Layer, LayerLeds,
+    Layout, Matrix, Mode, Nelson, NelsonKind,
 };
 
 #[derive(Default)]
@@ -25,11 +31,9 @@ pub struct BoardInner {
     layers: DerefCell<Vec<Layer>>,
     max_brightness: DerefCell<i32>,
     leds_changed: Cell<bool>,
-    has_led_save: DerefCell<bool>,
+    capabilities: DerefCell<BoardCapabilities>,
     led_save_blocked: Cell<bool>,
-    has_matrix: DerefCell<bool>,
     is_fake: DerefCell<bool>,
-    has_keymap: DerefCell<bool>,
 }
 
 #[glib::object_subclass]
@@ -47,6 +51,11 @@ impl ObjectImpl for BoardInner {
                 Signal::builder("leds-changed", &[], glib::Type::UNIT.into()).build(),
                 Signal::builder("matrix-changed", &[], glib::Type::UNIT.into()).build(),
                 Signal::builder("removed", &[], glib::Type::UNIT.into()).build(),
+                Signal::builder("disconnected", &[], glib::Type::UNIT.into()).build(),
+                Signal::builder("reconnected", &[], glib::Type::UNIT.into()).build(),
+                Signal::builder("brightness-changed", &[], glib::Type::UNIT.into()).build(),
+                Signal::builder("color-changed", &[], glib::Type::UNIT.into()).build(),
+                Signal::builder("mode-changed", &[], glib::Type::UNIT.into()).build(),
             ]
         });
         SIGNALS.as_ref()
@@ -76,8 +85,32 @@ impl Board {
             error!("Error getting firmware version: {}", err);
             String::new()
         });
-        let layout = Layout::from_board(&model)
-            .ok_or_else(|| format!("Failed to locate layout for '{}'", model))?;
+        let variant = saved_variant(&model);
+        let layout = match Layout::from_board_variant(&model, variant.as_deref()) {
+            Ok(layout) => layout,
+            Err(err) => match &variant {
+                // Fall back to the base layout, rather than failing to add the board
+                Some(variant) => {
+                    error!(
+                        "Failed to load variant '{}' of '{}': {}",
+                        variant, model, err
+                    );
+                    Layout::from_board(&model)
+                        .map_err(|err| format!("Failed to load layout for '{}': {}", model, err))?
+                }
+                None => return Err(format!("Failed to load layout for '{}': {}", model, err)),
+            },
+        };
+
+        if let Err(issues) = layout.validate() {
+            for issue in issues {
+                warn!("Layout for '{}': {}", model, issue);
+            }
+        }
+        // Each key needs a scancode for every layer
+        if layout.meta.num_layers == 0 {
+            return Err(format!("Layout for '{}' has no layers", model));
+        }
 
         let max_brightness = daemon.max_brightness(board).unwrap_or_else(|err| {
             error!("Error getting max brightness: {}", err);
@@ -90,10 +123,13 @@ impl Board {
             1
         };
 
-        let has_led_save = daemon.led_save(board).is_ok();
-        let has_matrix = daemon.matrix_get(board).is_ok();
-        let logical = layout.layout.values().next().unwrap();
-        let has_keymap = daemon.keymap_get(board, 0, logical.0, logical.1).is_ok();
+        // Features the daemon doesn't support are disabled, rather than
+        // sending commands it can't handle
+        let capabilities = daemon.capabilities(board).unwrap_or_else(|err| {
+            error!("Error getting board capabilities: {}", err);
+            BoardCapabilities::default()
+        });
+        let has_keymap = capabilities.keymap;
 
         let self_ = glib::Object::new::<Board>(&[]).unwrap();
         self_.inner().thread_client.set(thread_client);
@@ -102,17 +138,57 @@ impl Board {
         self_.inner().version.set(version);
         self_.inner().layout.set(layout);
         self_.inner().max_brightness.set(max_brightness);
-        self_.inner().has_led_save.set(has_led_save);
-        self_.inner().has_matrix.set(has_matrix);
+        self_.inner().capabilities.set(capabilities);
         self_.inner().is_fake.set(daemon.is_fake());
-        self_.inner().has_keymap.set(has_keymap);
+
+        // Read the whole keymap in one request, rather than one per key and layer
+        let num_keymap_layers = self_.layout().meta.num_layers as usize;
+        let keymap_keys = self_
+            .layout()
+            .physical
+            .keys
+            .iter()
+            .flat_map(|i| {
+                let electrical = self_.layout().electrical(&i.logical_name());
+                (0..num_keymap_layers as u8).map(move |layer| (layer, electrical.0, electrical.1))
+            })
+            .collect::<Vec<_>>();
+        let num_scancodes = keymap_keys.len();
+        let scancodes = if has_keymap {
+            let scancodes = match daemon.keymap_get_all(board, keymap_keys.clone()) {
+                Ok(scancodes) => scancodes,
+                Err(err) => {
+                    // Retry one key at a time, rather than showing a keymap
+                    // that wasn't read from the board
+                    warn!("Failed to read keymap in one request: {}", err);
+                    keymap_keys
+                        .iter()
+                        .map(|&(layer, output, input)| {
+                            daemon.keymap_get(board, layer, output, input)
+                        })
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|err| format!("Failed to read keymap: {}", err))?
+                }
+            };
+            if scancodes.len() != num_scancodes {
+                return Err(format!(
+                    "Read {} scancodes from keymap, expected {}",
+                    scancodes.len(),
+                    num_scancodes
+                ));
+            }
+            scancodes
+        } else {
+            vec![0; num_scancodes]
+        };
 
         let keys = self_
             .layout()
             .physical
             .keys
             .iter()
-            .map(|i| Key::new(daemon, &self_, i))
+            .zip(scancodes.chunks(num_keymap_layers))
+            .map(|(i, scancodes)| Key::new(daemon, &self_, i, scancodes))
             .collect();
         self_.inner().keys.set(keys);
 
@@ -151,6 +227,24 @@ impl Board {
         .unwrap()
     }
 
+    /// Board was unplugged, and is removed unless it returns shortly
+    pub fn connect_disconnected<F: Fn() + 'static>(&self, cb: F) -> SignalHandlerId {
+        self.connect_local("disconnected", false, move |_| {
+            cb();
+            None
+        })
+        .unwrap()
+    }
+
+    /// Board that was disconnected has returned, keeping its state
+    pub fn connect_reconnected<F: Fn() + 'static>(&self, cb: F) -> SignalHandlerId {
+        self.connect_local("reconnected", false, move |_| {
+            cb();
+            None
+        })
+        .unwrap()
+    }
+
     pub fn connect_keymap_changed<F: Fn() + 'static>(&self, cb: F) -> SignalHandlerId {
         self.connect_local("keymap-changed", false, move |_| {
             cb();
@@ -172,6 +266,91 @@ impl Board {
         .unwrap()
     }
 
+    pub(crate) fn brightness_changed(&self, index: u8, brightness: i32) {
+        for layer in self.layers().iter().filter(|layer| layer.index() == index) {
+            layer.brightness_changed(brightness);
+        }
+        self.emit_by_name("brightness-changed", &[]).unwrap();
+    }
+
+    /// Brightness was changed outside the configurator, such as with Fn keys
+    pub fn connect_brightness_changed<F: Fn() + 'static>(&self, cb: F) -> SignalHandlerId {
+        self.connect_local("brightness-changed", false, move |_| {
+            cb();
+            None
+        })
+        .unwrap()
+    }
+
+    pub(crate) fn color_changed(&self, index: u8, color: (u8, u8, u8)) {
+        for layer in self.layers().iter().filter(|layer| layer.index() == index) {
+            layer.color_changed(color);
+        }
+        self.emit_by_name("color-changed", &[]).unwrap();
+    }
+
+    /// Color was changed outside the configurator
+    pub fn connect_color_changed<F: Fn() + 'static>(&self, cb: F) -> SignalHandlerId {
+        self.connect_local("color-changed", false, move |_| {
+            cb();
+            None
+        })
+        .unwrap()
+    }
+
+    /// Mode was changed outside the configurator, such as by the firmware
+    pub fn connect_mode_changed<F: Fn() + 'static>(&self, cb: F) -> SignalHandlerId {
+        self.connect_local("mode-changed", false, move |_| {
+            cb();
+            None
+        })
+        .unwrap()
+    }
+
+    /// Poll the board for LED settings changed by the firmware, such as with
+    /// `KBD_UP`, while `watch` is `true`. Changes are emitted as
+    /// `brightness-changed`, `color-changed`, and `mode-changed`.
+    ///
+    /// Polling takes requests to the board, so this should only be enabled
+    /// while a view of the settings is shown.
+    pub fn set_watch_leds(&self, watch: bool) {
+        let self_ = self.clone();
+        glib::MainContext::default().spawn_local(async move {
+            let thread_client = self_.thread_client();
+            if let Err(err) = thread_client.set_watch_leds(self_.board(), watch).await {
+                error!("Failed to watch LEDs: {}", err);
+            }
+        });
+    }
+
+    /// Update layers with LED settings read from the board again, which may
+    /// have been changed by the firmware, such as with `KBD_UP`
+    pub(crate) fn leds_refreshed(&self, leds: &[LayerLeds]) {
+        let (mut brightness_changed, mut color_changed, mut mode_changed) = (false, false, false);
+        for leds in leds {
+            if let Some(layer) = self.layers().get(leds.layer as usize) {
+                let (brightness, color, mode) = layer.leds_refreshed(leds);
+                brightness_changed |= brightness;
+                color_changed |= color;
+                mode_changed |= mode;
+            }
+        }
+
+        if brightness_changed {
+            self.emit_by_name("brightness-changed", &[]).unwrap();
+        }
+        if color_changed {
+            self.emit_by_name("color-changed", &[]).unwrap();
+        }
+        if mode_changed {
+            self.emit_by_name("mode-changed", &[]).unwrap();
+        }
+        if brightness_changed || color_changed || mode_changed {
+            // Not `set_leds_changed`, since the firmware already has these settings
+            self.emit_by_name("leds-changed", &[]).unwrap();
+        }
+    }
+
     pub fn board(&self) -> BoardId {
         *self.inner().board
     }
@@ -188,8 +367,13 @@ impl Board {
         &self.inner().version
     }
 
+    /// Features supported by the board and daemon
+    pub fn capabilities(&self) -> &BoardCapabilities {
+        &*self.inner().capabilities
+    }
+
     pub fn has_matrix(&self) -> bool {
-        *self.inner().has_matrix
+        self.capabilities().matrix
     }
 
     pub fn connect_matrix_changed<F: Fn() + 'static>(&self, cb: F) -> SignalHandlerId {
@@ -204,15 +388,15 @@ impl Board {
         *self.inner().max_brightness
     }
 
-    pub async fn benchmark(&self) -> Result<Benchmark, String> {
+    pub async fn benchmark(&self) -> Result<Benchmark, BackendError> {
         self.thread_client().benchmark(self.board()).await
     }
 
-    pub async fn nelson(&self, kind: NelsonKind) -> Result<Nelson, String> {
+    pub async fn nelson(&self, kind: NelsonKind) -> Result<Nelson, BackendError> {
         self.thread_client().nelson(self.board(), kind).await
     }
 
-    pub async fn led_save(&self) -> Result<(), String> {
+    pub async fn led_save(&self) -> Result<(), BackendError> {
         if self.inner().led_save_blocked.get() {
             return Ok(());
         }
@@ -224,6 +408,16 @@ impl Board {
         Ok(())
     }
 
+    /// Take advisory lock on the board, so other clients of a shared daemon
+    /// can't write to it until `unlock` is called
+    pub async fn lock(&self) -> Result<(), BackendError> {
+        self.thread_client().lock(self.board()).await
+    }
+
+    pub async fn unlock(&self) -> Result<(), BackendError> {
+        self.thread_client().unlock(self.board()).await
+    }
+
     pub fn block_led_save(&self) {
         self.inner().led_save_blocked.set(true);
     }
@@ -237,17 +431,42 @@ impl Board {
     }
 
     pub fn has_led_save(&self) -> bool {
-        *self.inner().has_led_save
+        self.capabilities().led_save
     }
 
     pub fn has_keymap(&self) -> bool {
-        *self.inner().has_keymap
+        self.capabilities().keymap
     }
 
     pub fn layout(&self) -> &Layout {
         &*self.inner().layout
     }
 
+    /// Switch to a variant of the layout, such as `iso`, or the base layout
+    /// for `None`
+    ///
+    /// The choice is saved for this model, and the board is removed and added
+    /// again with the new layout.
+    pub async fn set_variant(&self, variant: Option<&str>) -> Result<(), BackendError> {
+        if let Some(variant) = variant {
+            if !self.layout().variants.iter().any(|x| x == variant) {
+                return Err(BackendError::InvalidArgument(format!(
+                    "No layout variant '{}' for '{}'",
+                    variant,
+                    self.model()
+                )));
+            }
+        }
+        save_variant(self.model(), variant).map_err(BackendError::Io)?;
+        self.thread_client().reload(self.board()).await
+    }
+
+    /// Whether `keymap` is for this model and layout variant, so it can be
+    /// applied without converting it
+    pub fn matches_keymap(&self, keymap: &KeyMap) -> bool {
+        keymap.model == self.model() && keymap.variant == self.layout().variant
+    }
+
     pub fn layers(&self) -> &[Layer] {
         &*self.inner().layers
     }
@@ -257,32 +476,454 @@ impl Board {
     }
 
     pub fn export_keymap(&self) -> KeyMap {
+        self.state_keymap(&self.state())
+    }
+
+    /// Keymap with the settings of `state`
+    fn state_keymap(&self, state: &BoardState) -> KeyMap {
         let mut map = HashMap::new();
         let mut key_leds = HashMap::new();
-        for key in self.keys().iter() {
-            let scancodes = (0..self.layout().meta.num_layers as usize)
-                .map(|layer| key.get_scancode(layer).unwrap().1)
+        for (i, key) in self.keys().iter().enumerate() {
+            let scancodes = state.scancodes[i]
+                .iter()
+                .map(|scancode| {
+                    let name = self.layout().scancode_to_name(*scancode);
+                    name.unwrap_or_default().to_string()
+                })
                 .collect();
             map.insert(key.logical_name.clone(), scancodes);
             if !key.leds.is_empty() {
-                key_leds.insert(key.logical_name.clone(), key.color());
+                key_leds.insert(key.logical_name.clone(), state.key_leds[i]);
             }
         }
-        let layers = self
-            .layers()
+        let layers = state
+            .layers
             .iter()
-            .map(|layer| KeyMapLayer {
-                mode: layer.mode.get(),
-                brightness: layer.brightness(),
-                color: layer.color(),
+            .map(|(mode, brightness, color)| KeyMapLayer {
+                mode: *mode,
+                brightness: *brightness,
+                color: *color,
             })
             .collect();
         KeyMap {
             model: self.model().to_string(),
-            version: 1,
+            variant: self.layout().variant.clone(),
+            version: KeyMap::VERSION,
+            name: None,
+            description: None,
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|time| time.as_secs()),
+            firmware_version: Some(self.version().to_string()),
             map,
             key_leds,
             layers,
         }
     }
+
+    /// Convert `keymap` from another model or layout variant to this board,
+    /// matching keys by name and position
+    pub fn convert_keymap(&self, keymap: &KeyMap) -> Result<KeyMapConversion, BackendError> {
+        let from = Layout::from_board_variant(&keymap.model, keymap.variant.as_deref())
+            .map_err(|err| BackendError::InvalidArgument(err.to_string()))?;
+        let mut conversion = self.layout().convert_keymap(&from, keymap);
+        if conversion.placed == 0 {
+            return Err(BackendError::InvalidArgument(format!(
+                "No keys of '{}' match keys of '{}'",
+                keymap.model,
+                self.model()
+            )));
+        }
+        conversion.keymap.model = self.model().to_string();
+        conversion.keymap.variant = self.layout().variant.clone();
+        Ok(conversion)
+    }
+
+    /// Changes that applying `keymap` would make to the board
+    ///
+    /// Settings missing from `keymap`, such as keys not in a partial keymap,
+    /// are left alone by `apply_keymap`, so they aren't listed.
+    pub fn diff_keymap(&self, keymap: &KeyMap) -> Result<KeyMapDiff, BackendError> {
+        let new = self.keymap_state(keymap)?;
+        Ok(self.export_keymap().diff(&self.state_keymap(&new)))
+    }
+
+    /// Apply the scancodes, key LEDs, and layer settings of `keymap` to the board
+    ///
+    /// Only settings that differ are written, one at a time. Scancodes are read
+    /// back from the board to verify them. On the first failure, every setting
+    /// that differs is restored, continuing past any that fail, so the board
+    /// is left half-configured only if `rollback_errors` isn't empty.
+    pub async fn apply_keymap(&self, keymap: &KeyMap) -> ApplyReport {
+        let mut report = ApplyReport::default();
+
+        let old = self.state();
+        let new = match self.keymap_state(keymap) {
+            Ok(new) => new,
+            Err(err) => {
+                report.error = Some(err);
+                return report;
+            }
+        };
+        report.scancodes = old
+            .scancodes
+            .iter()
+            .zip(new.scancodes.iter())
+            .map(|(a, b)| a.iter().zip(b.iter()).filter(|(a, b)| a != b).count())
+            .sum();
+        report.key_leds = count_changed(&old.key_leds, &new.key_leds);
+        report.layers = count_changed(&old.layers, &new.layers);
+
+        if let Err(err) = self.write_state(&old, &new).await {
+            error!("Failed to apply keymap: {}", err);
+            report.error = Some(err);
+            // Settings may be partly written, so restore everything that changed
+            report.rollback_errors = self.restore_state(&new, &old).await;
+            for err in &report.rollback_errors {
+                error!("Failed to roll back keymap: {}", err);
+            }
+            report.rolled_back = report.rollback_errors.is_empty();
+        }
+
+        report
+    }
+
+    /// Current settings, as cached from the board
+    fn state(&self) -> BoardState {
+        let num_layers = self.layout().meta.num_layers as usize;
+        BoardState {
+            scancodes: self
+                .keys()
+                .iter()
+                .map(|key| {
+                    (0..num_layers)
+                        .map(|layer| key.get_scancode(layer).unwrap().0)
+                        .collect()
+                })
+                .collect(),
+            key_leds: self.keys().iter().map(Key::color).collect(),
+            layers: self
+                .layers()
+                .iter()
+                .map(|layer| (layer.mode.get(), layer.brightness(), layer.color()))
+                .collect(),
+        }
+    }
+
+    /// Current settings, with those in `keymap` replacing them
+    fn keymap_state(&self, keymap: &KeyMap) -> Result<BoardState, BackendError> {
+        if keymap.model != self.model() {
+            return Err(BackendError::InvalidArgument(format!(
+                "Keymap is for board '{}'",
+                keymap.model
+            )));
+        }
+        if keymap.variant != self.layout().variant {
+            return Err(BackendError::InvalidArgument(format!(
+                "Keymap is for layout variant '{}'",
+                keymap.variant.as_deref().unwrap_or("default")
+            )));
+        }
+
+        let mut state = self.state();
+        for (key, scancodes) in self.keys().iter().zip(state.scancodes.iter_mut()) {
+            if let Some(scancode_names) = keymap.map.get(&key.logical_name) {
+                for (scancode, scancode_name) in scancodes.iter_mut().zip(scancode_names.iter()) {
+                    *scancode =
+                        self.layout()
+                            .scancode_from_name(scancode_name)
+                            .ok_or_else(|| {
+                                BackendError::InvalidArgument(format!(
+                                    "Unable to find scancode '{}'",
+                                    scancode_name
+                                ))
+                            })?;
+                }
+            }
+        }
+        for (key, color) in self.keys().iter().zip(state.key_leds.iter_mut()) {
+            if let Some(hs) = keymap.key_leds.get(&key.logical_name) {
+                *color = *hs;
+            }
+        }
+        for (layer, keymap_layer) in state.layers.iter_mut().zip(keymap.layers.iter()) {
+            // Mode is only set on boards that support it
+            if let (Some(_), Some((mode, speed))) = (layer.0, keymap_layer.mode) {
+                mode_from_index(mode)?;
+                layer.0 = Some((mode, speed));
+            }
+            layer.1 = keymap_layer.brightness;
+            layer.2 = keymap_layer.color;
+        }
+        Ok(state)
+    }
+
+    /// Scancodes in `new` that differ from `old`, as `(key, layer, scancode)`
+    fn changed_scancodes(&self, old: &BoardState, new: &BoardState) -> Vec<(&Key, usize, u16)> {
+        let mut scancodes = Vec::new();
+        for (key, (old, new)) in self
+            .keys()
+            .iter()
+            .zip(old.scancodes.iter().zip(new.scancodes.iter()))
+        {
+            for (layer, (old, new)) in old.iter().zip(new.iter()).enumerate() {
+                if old != new {
+                    scancodes.push((key, layer, *new));
+                }
+            }
+        }
+        scancodes
+    }
+
+    /// Write settings in `new` that differ from `old`, stopping at the first error
+    async fn write_state(&self, old: &BoardState, new: &BoardState) -> Result<(), BackendError> {
+        let scancodes = self.changed_scancodes(old, new);
+        self.set_scancodes(scancodes.clone()).await?;
+        self.verify_scancodes(&scancodes).await?;
+
+        for (key, (old, new)) in self
+            .keys()
+            .iter()
+            .zip(old.key_leds.iter().zip(new.key_leds.iter()))
+        {
+            if old != new {
+                key.set_color(*new).await?;
+            }
+        }
+
+        for (layer, (old, new)) in self
+            .layers()
+            .iter()
+            .zip(old.layers.iter().zip(new.layers.iter()))
+        {
+            if old.0 != new.0 {
+                if let Some((mode, speed)) = new.0 {
+                    layer.set_mode(mode_from_index(mode)?, speed).await?;
+                }
+            }
+            if old.1 != new.1 {
+                layer.set_brightness(new.1).await?;
+            }
+            if old.2 != new.2 {
+                layer.set_color(new.2).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Like `write_state`, but tries every setting rather than stopping at the
+    /// first error, returning all the errors
+    async fn restore_state(&self, old: &BoardState, new: &BoardState) -> Vec<BackendError> {
+        let mut errors = Vec::new();
+
+        let scancodes = self.changed_scancodes(old, new);
+        if let Err(err) = self.set_scancodes(scancodes.clone()).await {
+            // Retry one at a time, so one bad key doesn't stop the rest
+            warn!("Failed to restore scancodes in one request: {}", err);
+            for scancode in &scancodes {
+                if let Err(err) = self.set_scancodes(vec![*scancode]).await {
+                    errors.push(err);
+                }
+            }
+        }
+        if errors.is_empty() {
+            if let Err(err) = self.verify_scancodes(&scancodes).await {
+                errors.push(err);
+            }
+        }
+
+        for (key, (old, new)) in self
+            .keys()
+            .iter()
+            .zip(old.key_leds.iter().zip(new.key_leds.iter()))
+        {
+            if old != new {
+                if let Err(err) = key.set_color(*new).await {
+                    errors.push(err);
+                }
+            }
+        }
+
+        for (layer, (old, new)) in self
+            .layers()
+            .iter()
+            .zip(old.layers.iter().zip(new.layers.iter()))
+        {
+            if old.0 != new.0 {
+                if let Some((mode, speed)) = new.0 {
+                    let res = match mode_from_index(mode) {
+                        Ok(mode) => layer.set_mode(mode, speed).await,
+                        Err(err) => Err(err),
+                    };
+                    if let Err(err) = res {
+                        errors.push(err);
+                    }
+                }
+            }
+            if old.1 != new.1 {
+                if let Err(err) = layer.set_brightness(new.1).await {
+                    errors.push(err);
+                }
+            }
+            if old.2 != new.2 {
+                if let Err(err) = layer.set_color(new.2).await {
+                    errors.push(err);
+                }
+            }
+        }
+
+        errors
+    }
+
+    /// Read scancodes back from the board, to check they were written
+    async fn verify_scancodes(&self, scancodes: &[(&Key, usize, u16)]) -> Result<(), BackendError> {
+        if scancodes.is_empty() {
+            return Ok(());
+        }
+
+        let keys = scancodes
+            .iter()
+            .map(|(key, layer, _)| (*layer as u8, key.electrical.0, key.electrical.1))
+            .collect();
+        let values = self
+            .thread_client()
+            .keymap_get_all(self.board(), keys)
+            .await?;
+        for ((key, layer, scancode), value) in scancodes.iter().zip(values) {
+            if *scancode != value {
+                return Err(BackendError::Protocol(format!(
+                    "Scancode of {} on layer {} is {:#06x} rather than {:#06x}",
+                    key.logical_name, layer, value, scancode
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the scancodes of many `(key, layer, scancode)` with one request
+    async fn set_scancodes(&self, scancodes: Vec<(&Key, usize, u16)>) -> Result<(), BackendError> {
+        if scancodes.is_empty() {
+            return Ok(());
+        }
+
+        let keys = scancodes
+            .iter()
+            .map(|(key, layer, scancode)| {
+                (*layer as u8, key.electrical.0, key.electrical.1, *scancode)
+            })
+            .collect();
+        self.thread_client()
+            .keymap_set_many(self.board(), keys)
+            .await?;
+        for (key, layer, scancode) in scancodes {
+            key.scancode_changed(layer, scancode);
+        }
+        self.emit_by_name("keymap-changed", &[]).unwrap();
+        Ok(())
+    }
+}
+
+/// Settings of a board that `Board::apply_keymap` changes, in the order of
+/// `Board::keys` and `Board::layers`
+struct BoardState {
+    scancodes: Vec<Vec<u16>>,
+    key_leds: Vec<Option<Hs>>,
+    /// Mode, brightness, and color
+    layers: Vec<(Option<(u8, u8)>, i32, Hs)>,
+}
+
+/// Mode with the index `mode`, which may come from a keymap file
+fn mode_from_index(mode: u8) -> Result<&'static Mode, BackendError> {
+    Mode::from_index(mode)
+        .ok_or_else(|| BackendError::InvalidArgument(format!("Unknown mode {}", mode)))
+}
+
+fn count_changed<T: PartialEq>(old: &[T], new: &[T]) -> usize {
+    old.iter().zip(new.iter()).filter(|(a, b)| a != b).count()
+}
+
+/// Result of `Board::apply_keymap`
+#[derive(Clone, Debug, Default)]
+pub struct ApplyReport {
+    /// Number of scancodes that differed from the keymap
+    pub scancodes: usize,
+    /// Number of key LED colors that differed from the keymap
+    pub key_leds: usize,
+    /// Number of layers with LED settings that differed from the keymap
+    pub layers: usize,
+    /// Error that stopped the keymap from being applied
+    pub error: Option<BackendError>,
+    /// Every change was reverted after `error`
+    pub rolled_back: bool,
+    /// Errors while reverting, which leave the board partly configured
+    pub rollback_errors: Vec<BackendError>,
+}
+
+impl ApplyReport {
+    /// `Ok` if the whole keymap was applied. Errors while rolling back are
+    /// only in `rollback_errors`.
+    pub fn result(&self) -> Result<(), BackendError> {
+        match &self.error {
+            None => Ok(()),
+            Some(err) => Err(err.clone()),
+        }
+    }
+
+    /// Description of `error`, and of `rollback_errors` if there are any
+    pub fn error_message(&self) -> Option<String> {
+        let err = self.error.as_ref()?;
+        if self.rollback_errors.is_empty() {
+            Some(err.to_string())
+        } else {
+            let rollback_errors = self
+                .rollback_errors
+                .iter()
+                .map(|err| err.to_string())
+                .collect::<Vec<_>>();
+            Some(format!(
+                "{}; failed to roll back: {}",
+                err,
+                rollback_errors.join("; ")
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{daemon::DaemonDummy, Backend};
+
+    #[test]
+    fn apply_keymap_rollback() {
+        // LEDs of K01 and K02
+        let daemon =
+            DaemonDummy::new(vec!["system76/launch_1".to_string()]).fail_set_color(&[70, 71]);
+        let backend = Backend::new_internal(daemon).unwrap();
+        let context = glib::MainContext::default();
+        context.block_on(backend.refresh_future()).unwrap();
+        while context.iteration(false) {}
+        let board = backend.boards().remove(0);
+        let old = board.export_keymap();
+
+        let mut keymap = old.clone();
+        keymap.map = HashMap::new();
+        keymap
+            .map
+            .insert("K00".to_string(), vec!["A".to_string(); 4]);
+        let red = Some(Hs::new(0., 1.));
+        for key in &["K00", "K01", "K02"] {
+            keymap.key_leds.insert(key.to_string(), red);
+        }
+        keymap.layers[0].brightness = 100;
+
+        let report = context.block_on(board.apply_keymap(&keymap));
+        assert!(report.error.is_some());
+        // Both failing LEDs are tried, and the layer after them is still restored
+        assert_eq!(report.rollback_errors.len(), 2);
+        assert!(!report.rolled_back);
+        assert!(board.export_keymap().diff(&old).is_empty());
+    }
 }
@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{BackendError, BoardId, Daemon, Layout};
+
+/// Features of a board that the daemon driving it supports
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct BoardCapabilities {
+    /// Scancodes can be read and set
+    pub keymap: bool,
+    /// Pressed keys can be read
+    pub matrix: bool,
+    /// Keys have LEDs with their own color
+    pub per_key_led: bool,
+    /// LEDs support animated modes, such as `SOLID_COLOR` or `BREATHE`
+    pub modes: bool,
+    /// LED settings can be saved, so they are kept after a reboot
+    pub led_save: bool,
+    /// Nelson test fixture is connected
+    pub nelson: bool,
+    /// USB benchmark can be run
+    pub benchmark: bool,
+    /// Number of keymap layers
+    pub num_layers: u8,
+    /// LED changes made outside of clients, such as with Fn keys, are sent
+    /// as `DaemonEvent`s, so they don't need to be polled
+    #[serde(default)]
+    pub led_events: bool,
+}
+
+impl BoardCapabilities {
+    /// Find capabilities of a board by trying the commands that need them,
+    /// for daemons that don't know them in advance
+    ///
+    /// This saves the LED settings, if that's supported.
+    pub(crate) fn probe<D: Daemon + ?Sized>(
+        daemon: &D,
+        board: BoardId,
+    ) -> Result<Self, BackendError> {
+        let model = daemon.model(board)?;
+        let layout = Layout::from_board(&model).map_err(|err| {
+            BackendError::Other(format!("Failed to load layout for '{}': {}", model, err))
+        })?;
+
+        let keymap = match layout.layout.values().next() {
+            Some(logical) => {
+                daemon.supports_command("keymap_get")
+                    && daemon.keymap_get(board, 0, logical.0, logical.1).is_ok()
+            }
+            None => false,
+        };
+        let modes = layout.meta.has_mode
+            && daemon.supports_command("mode")
+            && daemon.mode(board, 0).is_ok();
+
+        Ok(Self {
+            keymap,
+            matrix: daemon.supports_command("matrix_get") && daemon.matrix_get(board).is_ok(),
+            per_key_led: modes && layout.leds.values().any(|leds| !leds.is_empty()),
+            modes,
+            led_save: daemon.supports_command("led_save") && daemon.led_save(board).is_ok(),
+            nelson: false,
+            benchmark: false,
+            num_layers: layout.meta.num_layers,
+            led_events: false,
+        })
+    }
+}
@@ -1,21 +1,64 @@
 use std::{
-    cell::RefCell,
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
     env,
     io::{BufRead, BufReader, Write},
     path::PathBuf,
-    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    process::{Child, Command, Stdio},
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
+#[cfg(target_os = "linux")]
+use std::{os::unix::net::UnixStream, path::Path};
 
-use super::{err_str, Daemon, DaemonClientTrait, DaemonCommand, DaemonResponse};
+use super::{
+    err_str, BackendError, Daemon, DaemonClientTrait, DaemonCommand, DaemonEvent, DaemonHello,
+    DaemonMessage, DaemonRequest, DaemonResponse, PROTOCOL_VERSION,
+};
+
+type EventHandler = Box<dyn Fn(DaemonEvent) + Send>;
+
+/// Time to wait for a reply before assuming the daemon is stuck
+const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+/// Time to wait for a reply to a command that runs a test on the board
+const TEST_REPLY_TIMEOUT: Duration = Duration::from_secs(300);
+
+fn reply_timeout(command: &DaemonCommand) -> Duration {
+    match command {
+        DaemonCommand::benchmark { .. } | DaemonCommand::nelson { .. } => TEST_REPLY_TIMEOUT,
+        _ => REPLY_TIMEOUT,
+    }
+}
+
+#[derive(Default)]
+struct Replies {
+    replies: HashMap<u64, Result<DaemonResponse, BackendError>>,
+    /// Requests that timed out, whose replies are dropped if they arrive
+    abandoned: HashSet<u64>,
+    closed: bool,
+}
+
+/// State shared with the thread reading from the daemon
+#[derive(Default)]
+struct Shared {
+    replies: Mutex<Replies>,
+    condvar: Condvar,
+    event_handler: Mutex<Option<EventHandler>>,
+}
 
 pub struct DaemonClient {
-    child: Child,
-    read: RefCell<BufReader<ChildStdout>>,
-    write: RefCell<ChildStdin>,
+    /// Daemon process, if spawned by the client
+    child: Option<Child>,
+    write: RefCell<Box<dyn Write + Send>>,
+    commands: HashSet<String>,
+    next_id: Cell<u64>,
+    shared: Arc<Shared>,
+    reader: Option<JoinHandle<()>>,
 }
 
 impl DaemonClient {
-    pub fn new_pkexec() -> Self {
+    pub fn new_pkexec() -> Result<Self, String> {
         // Use canonicalized command name
         let command_path = if cfg!(feature = "appimage") {
             PathBuf::from(env::var("APPIMAGE").expect("Failed to get executable path"))
@@ -29,43 +72,182 @@ impl DaemonClient {
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .spawn()
-            .expect("Failed to spawn daemon");
+            .map_err(|err| format!("Failed to spawn daemon: {}", err))?;
 
         let stdin = child.stdin.take().unwrap();
-        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let stdout = BufReader::new(child.stdout.take().unwrap());
 
-        // Check if daemon has started
-        let mut line = String::new();
-        if let Ok(count) = stdout.read_line(&mut line) {
-            // pkexec terminated returning EOF
-            if count == 0 {
-                panic!("Failed to start daemon with pkexec");
+        match Self::new(Box::new(stdout), Box::new(stdin)) {
+            Ok(mut client) => {
+                client.child = Some(child);
+                Ok(client)
+            }
+            Err(err) => {
+                // Stdin was closed when the client was dropped, making the
+                // daemon exit
+                let _ = child.wait();
+                Err(err)
+            }
+        }
+    }
+
+    /// Connect to a daemon listening on the Unix socket at `path`
+    #[cfg(target_os = "linux")]
+    pub fn new_socket<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let stream = UnixStream::connect(path.as_ref()).map_err(|err| {
+            format!(
+                "Failed to connect to '{}': {}",
+                path.as_ref().display(),
+                err
+            )
+        })?;
+        let write = stream.try_clone().map_err(err_str)?;
+        Self::new(Box::new(BufReader::new(stream)), Box::new(write))
+    }
+
+    fn new(
+        mut read: Box<dyn BufRead + Send>,
+        write: Box<dyn Write + Send>,
+    ) -> Result<Self, String> {
+        let hello = Self::handshake(&mut read)?;
+        let shared = Arc::new(Shared::default());
+        let reader = {
+            let shared = shared.clone();
+            thread::spawn(move || Self::read_messages(read, &shared))
+        };
+        Ok(Self {
+            child: None,
+            write: RefCell::new(write),
+            commands: hello.commands.into_iter().collect(),
+            next_id: Cell::new(0),
+            shared,
+            reader: Some(reader),
+        })
+    }
+
+    /// Read replies and events until the daemon closes its end
+    ///
+    /// Events can arrive at any time, so this runs in its own thread.
+    fn read_messages(mut read: Box<dyn BufRead + Send>, shared: &Shared) {
+        loop {
+            let mut message_json = String::new();
+            match read.read_line(&mut message_json) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(err) => {
+                    error!("Failed to read from daemon: {}", err);
+                    break;
+                }
+            }
+
+            match serde_json::from_str::<DaemonMessage>(&message_json) {
+                Ok(DaemonMessage::Reply(reply)) => {
+                    let mut replies = shared.replies.lock().unwrap();
+                    if !replies.abandoned.remove(&reply.id) {
+                        replies.replies.insert(reply.id, reply.response);
+                    }
+                    shared.condvar.notify_all();
+                }
+                Ok(DaemonMessage::Event(event)) => {
+                    if let Some(handler) = &*shared.event_handler.lock().unwrap() {
+                        handler(event);
+                    }
+                }
+                Err(err) => error!("Failed to parse message from daemon: {}", err),
             }
         }
 
-        Self {
-            child,
-            read: RefCell::new(stdout),
-            write: RefCell::new(stdin),
+        shared.replies.lock().unwrap().closed = true;
+        shared.condvar.notify_all();
+    }
+
+    /// Read the server's `DaemonHello`, and check that its version matches ours
+    fn handshake(read: &mut dyn BufRead) -> Result<DaemonHello, String> {
+        let mut line = String::new();
+        let count = read.read_line(&mut line).map_err(err_str)?;
+        // pkexec terminated, or socket connection was rejected, returning EOF
+        if count == 0 {
+            return Err("Failed to start daemon".to_string());
+        }
+
+        let hello = serde_json::from_str::<DaemonHello>(&line).map_err(|_| {
+            "Daemon does not support protocol handshake; is it from an older version?".to_string()
+        })?;
+        if hello.version != PROTOCOL_VERSION {
+            return Err(format!(
+                "Daemon protocol version {} does not match client version {}",
+                hello.version, PROTOCOL_VERSION
+            ));
+        }
+        info!("Daemon supports commands: {:?}", hello.commands);
+        Ok(hello)
+    }
+
+    fn write_request(&self, command: DaemonCommand) -> Result<u64, BackendError> {
+        let id = self.next_id.get();
+        self.next_id.set(id.wrapping_add(1));
+
+        let mut request_json = serde_json::to_string(&DaemonRequest { id, command })
+            .map_err(|err| BackendError::Protocol(err.to_string()))?;
+        request_json.push('\n');
+        self.write.borrow_mut().write_all(request_json.as_bytes())?;
+        Ok(id)
+    }
+
+    fn read_reply(&self, id: u64, timeout: Duration) -> Result<DaemonResponse, BackendError> {
+        let deadline = Instant::now() + timeout;
+        let mut replies = self.shared.replies.lock().unwrap();
+        loop {
+            if let Some(response) = replies.replies.remove(&id) {
+                return response;
+            } else if replies.closed {
+                return Err(BackendError::Io("Daemon exited".to_string()));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                error!("Timed out waiting for reply {} from daemon", id);
+                replies.abandoned.insert(id);
+                return Err(BackendError::Timeout);
+            }
+            replies = self
+                .shared
+                .condvar
+                .wait_timeout(replies, deadline - now)
+                .unwrap()
+                .0;
         }
     }
 }
 
 impl DaemonClientTrait for DaemonClient {
-    fn send_command(&self, command: DaemonCommand) -> Result<DaemonResponse, String> {
-        let mut command_json = serde_json::to_string(&command).map_err(err_str)?;
-        command_json.push('\n');
-        self.write
-            .borrow_mut()
-            .write_all(command_json.as_bytes())
-            .map_err(err_str)?;
-
-        let mut response_json = String::new();
-        self.read
-            .borrow_mut()
-            .read_line(&mut response_json)
-            .map_err(err_str)?;
-        serde_json::from_str(&response_json).map_err(err_str)?
+    fn send_command(&self, command: DaemonCommand) -> Result<DaemonResponse, BackendError> {
+        let timeout = reply_timeout(&command);
+        let id = self.write_request(command)?;
+        self.read_reply(id, timeout)
+    }
+
+    fn send_commands(
+        &self,
+        commands: Vec<DaemonCommand>,
+    ) -> Vec<Result<DaemonResponse, BackendError>> {
+        let ids = commands
+            .into_iter()
+            .map(|command| {
+                let timeout = reply_timeout(&command);
+                self.write_request(command).map(|id| (id, timeout))
+            })
+            .collect::<Vec<_>>();
+        ids.into_iter()
+            .map(|id| id.and_then(|(id, timeout)| self.read_reply(id, timeout)))
+            .collect()
+    }
+
+    fn supports_command(&self, name: &str) -> bool {
+        self.commands.contains(name)
+    }
+
+    fn set_event_handler(&self, handler: Box<dyn Fn(DaemonEvent) + Send>) {
+        *self.shared.event_handler.lock().unwrap() = Some(handler);
     }
 }
 
@@ -73,9 +255,41 @@ impl Drop for DaemonClient {
     fn drop(&mut self) {
         let _ = self.exit();
 
-        let status = self.child.wait().expect("Failed to wait for daemon");
-        if !status.success() {
-            panic!("Failed to run daemon with exit status {:?}", status);
+        if let Some(child) = &mut self.child {
+            let status = child.wait().expect("Failed to wait for daemon");
+            if !status.success() {
+                panic!("Failed to run daemon with exit status {:?}", status);
+            }
         }
+
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_reply_timeout() {
+        let (stream, mut server) = UnixStream::pair().unwrap();
+        let hello = DaemonHello {
+            version: PROTOCOL_VERSION,
+            commands: Vec::new(),
+        };
+        writeln!(server, "{}", serde_json::to_string(&hello).unwrap()).unwrap();
+
+        let write = stream.try_clone().unwrap();
+        let client = DaemonClient::new(Box::new(BufReader::new(stream)), Box::new(write)).unwrap();
+        // The server never replies
+        assert!(matches!(
+            client.read_reply(0, Duration::from_millis(10)),
+            Err(BackendError::Timeout)
+        ));
+
+        drop(server);
+        drop(client);
     }
 }
@@ -10,16 +10,22 @@ use glib::clone;
 use std::{
     cell::{Cell, RefCell},
     cmp::PartialEq,
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     hash::{Hash, Hasher},
+    mem,
     rc::Rc,
     sync::{Arc, Mutex, Weak},
     thread::{self, JoinHandle},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use super::{Benchmark, BoardId, Daemon, Matrix, Nelson, NelsonKind};
-use crate::Board;
+#[cfg(target_os = "linux")]
+use super::HotplugMonitor;
+use super::{
+    Benchmark, BoardId, Daemon, DaemonCommand, DaemonEvent, DaemonResponse, Matrix, Nelson,
+    NelsonKind,
+};
+use crate::{BackendError, Board};
 
 #[derive(Clone, Debug)]
 struct Item<K: Hash + Eq, V> {
@@ -50,30 +56,82 @@ impl<K: Hash + Eq, V> Eq for Item<K, V> {}
 #[derive(Clone, Hash, Eq, PartialEq, Debug)]
 enum SetEnum {
     KeyMap(Item<(BoardId, u8, u8, u8), u16>),
+    KeyMapMany(BoardId, Vec<(u8, u8, u8, u16)>),
+    KeyMapGetAll(BoardId, Vec<(u8, u8, u8)>),
     Color(Item<(BoardId, u8), (u8, u8, u8)>),
     Brightness(Item<(BoardId, u8), i32>),
     Mode(Item<(BoardId, u8), (u8, u8)>),
     Benchmark(BoardId),
     Nelson(BoardId, NelsonKind),
     LedSave(BoardId),
+    Lock(BoardId),
+    Unlock(BoardId),
     MatrixGetRate(Item<(), Option<Duration>>),
+    /// Poll a board for LED changes made by the firmware, or stop
+    WatchLeds(Item<BoardId, bool>),
     Refresh,
+    /// Remove a board and add it again, such as after changing its layout variant
+    Reload(BoardId),
+    /// Sent by the daemon, rather than the `ThreadClient`
+    Event(DaemonEvent),
+    /// A board may have been plugged in or removed
+    Hotplug,
     Exit,
 }
 
 impl SetEnum {
     fn is_cancelable(&self) -> bool {
         match self {
-            Self::Nelson(_, _) | Self::Benchmark(_) => false,
+            Self::Nelson(_, _)
+            | Self::Benchmark(_)
+            | Self::KeyMapMany(_, _)
+            | Self::KeyMapGetAll(_, _)
+            | Self::Lock(_)
+            | Self::Unlock(_)
+            | Self::Reload(_) => false,
             _ => true,
         }
     }
+
+    /// Daemon command for sets that can be sent in a batch
+    fn command(&self) -> Option<DaemonCommand> {
+        match self {
+            Self::KeyMap(Item { key, value }) => Some(DaemonCommand::keymap_set {
+                board: key.0,
+                layer: key.1,
+                output: key.2,
+                input: key.3,
+                value: *value,
+            }),
+            Self::KeyMapMany(board, keys) => Some(DaemonCommand::keymap_set_many {
+                board: *board,
+                keys: keys.clone(),
+            }),
+            Self::Color(Item { key, value }) => Some(DaemonCommand::set_color {
+                board: key.0,
+                index: key.1,
+                color: *value,
+            }),
+            Self::Brightness(Item { key, value }) => Some(DaemonCommand::set_brightness {
+                board: key.0,
+                index: key.1,
+                brightness: *value,
+            }),
+            Self::Mode(Item { key, value }) => Some(DaemonCommand::set_mode {
+                board: key.0,
+                layer: key.1,
+                mode: value.0,
+                speed: value.1,
+            }),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
 struct Set {
     inner: SetEnum,
-    oneshot: oneshot::Sender<Result<Response, String>>,
+    oneshot: oneshot::Sender<Result<Response, BackendError>>,
 }
 
 #[derive(Debug)]
@@ -81,6 +139,7 @@ enum Response {
     Benchmark(Benchmark),
     Canceled,
     Empty,
+    KeyMap(Vec<u16>),
     Nelson(Box<Nelson>),
 }
 
@@ -96,6 +155,12 @@ impl Into<Response> for () {
     }
 }
 
+impl Into<Response> for Vec<u16> {
+    fn into(self) -> Response {
+        Response::KeyMap(self)
+    }
+}
+
 impl Into<Response> for Nelson {
     fn into(self) -> Response {
         Response::Nelson(Box::new(self))
@@ -103,7 +168,15 @@ impl Into<Response> for Nelson {
 }
 
 impl Set {
-    fn reply<T: Into<Response>>(self, resp: Result<T, String>) {
+    /// The `ThreadClient` is no longer waiting for a reply
+    fn is_canceled(&self) -> bool {
+        match self.inner {
+            SetEnum::Exit | SetEnum::Event(_) | SetEnum::Hotplug => false,
+            _ => self.oneshot.is_canceled(),
+        }
+    }
+
+    fn reply<T: Into<Response>>(self, resp: Result<T, BackendError>) {
         let _ = self.oneshot.send(resp.map(|x| x.into()));
     }
 }
@@ -117,6 +190,19 @@ pub struct ThreadClient {
 impl ThreadClient {
     pub fn new<F: Fn(ThreadResponse) + 'static>(daemon: Box<dyn Daemon>, cb: F) -> Arc<Self> {
         let (sender, reciever) = async_mpsc::unbounded();
+
+        // Events from a shared daemon are handled on the thread
+        let event_sender = sender.clone();
+        daemon.set_event_handler(Box::new(move |event| {
+            let (oneshot, _) = oneshot::channel();
+            let _ = event_sender.unbounded_send(Set {
+                inner: SetEnum::Event(event),
+                oneshot,
+            });
+        }));
+
+        let has_hotplug = Self::watch_hotplug(sender.clone());
+
         let client = Arc::new(Self {
             cancels: Mutex::new(HashMap::new()),
             channel: sender,
@@ -129,12 +215,53 @@ impl ThreadClient {
             }
         });
 
-        let join_handle = Thread::new(daemon, client.clone(), response_sender).spawn(reciever);
+        let join_handle =
+            Thread::new(daemon, client.clone(), response_sender, !has_hotplug).spawn(reciever);
         *client.join_handle.lock().unwrap() = Some(join_handle);
         client
     }
 
-    async fn send(&self, set_enum: SetEnum) -> Result<Response, String> {
+    /// Send `SetEnum::Hotplug` to the thread when a board is added or removed.
+    /// Returns `false` if not supported, so boards have to be polled instead.
+    #[cfg(target_os = "linux")]
+    fn watch_hotplug(sender: async_mpsc::UnboundedSender<Set>) -> bool {
+        let monitor = match HotplugMonitor::new() {
+            Ok(monitor) => monitor,
+            Err(err) => {
+                error!("Failed to monitor hotplug events: {}", err);
+                return false;
+            }
+        };
+
+        thread::spawn(move || {
+            // Stops once the thread exits and the receiver is dropped
+            while !sender.is_closed() {
+                match monitor.wait() {
+                    Ok(true) => {
+                        let (oneshot, _) = oneshot::channel();
+                        let _ = sender.unbounded_send(Set {
+                            inner: SetEnum::Hotplug,
+                            oneshot,
+                        });
+                    }
+                    Ok(false) => {}
+                    Err(err) => {
+                        error!("Failed to read hotplug event: {}", err);
+                        break;
+                    }
+                }
+            }
+        });
+
+        true
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn watch_hotplug(_sender: async_mpsc::UnboundedSender<Set>) -> bool {
+        false
+    }
+
+    async fn send(&self, set_enum: SetEnum) -> Result<Response, BackendError> {
         let mut cancels = self.cancels.lock().unwrap();
 
         if set_enum.is_cancelable() {
@@ -158,11 +285,11 @@ impl ThreadClient {
         }
     }
 
-    async fn send_noresp(&self, set_enum: SetEnum) -> Result<(), String> {
+    async fn send_noresp(&self, set_enum: SetEnum) -> Result<(), BackendError> {
         self.send(set_enum).await.and(Ok(()))
     }
 
-    pub async fn refresh(&self) -> Result<(), String> {
+    pub async fn refresh(&self) -> Result<(), BackendError> {
         self.send_noresp(SetEnum::Refresh).await
     }
 
@@ -173,7 +300,7 @@ impl ThreadClient {
         output: u8,
         input: u8,
         value: u16,
-    ) -> Result<(), String> {
+    ) -> Result<(), BackendError> {
         self.send_noresp(SetEnum::KeyMap(Item::new(
             (board, layer, output, input),
             value,
@@ -181,12 +308,34 @@ impl ThreadClient {
         .await
     }
 
+    pub async fn keymap_set_many(
+        &self,
+        board: BoardId,
+        keys: Vec<(u8, u8, u8, u16)>,
+    ) -> Result<(), BackendError> {
+        self.send_noresp(SetEnum::KeyMapMany(board, keys)).await
+    }
+
+    /// Read scancodes from the board, rather than the cached values
+    pub async fn keymap_get_all(
+        &self,
+        board: BoardId,
+        keys: Vec<(u8, u8, u8)>,
+    ) -> Result<Vec<u16>, BackendError> {
+        let resp = self.send(SetEnum::KeyMapGetAll(board, keys)).await?;
+        if let Response::KeyMap(scancodes) = resp {
+            Ok(scancodes)
+        } else {
+            panic!(format!("'{:?}' unexpected", resp));
+        }
+    }
+
     pub async fn set_color(
         &self,
         board: BoardId,
         index: u8,
         color: (u8, u8, u8),
-    ) -> Result<(), String> {
+    ) -> Result<(), BackendError> {
         self.send_noresp(SetEnum::Color(Item::new((board, index), color)))
             .await
     }
@@ -196,7 +345,7 @@ impl ThreadClient {
         board: BoardId,
         index: u8,
         brightness: i32,
-    ) -> Result<(), String> {
+    ) -> Result<(), BackendError> {
         self.send_noresp(SetEnum::Brightness(Item::new((board, index), brightness)))
             .await
     }
@@ -207,17 +356,22 @@ impl ThreadClient {
         layer: u8,
         mode: u8,
         speed: u8,
-    ) -> Result<(), String> {
+    ) -> Result<(), BackendError> {
         self.send_noresp(SetEnum::Mode(Item::new((board, layer), (mode, speed))))
             .await
     }
 
-    pub async fn set_matrix_get_rate(&self, rate: Option<Duration>) -> Result<(), String> {
+    pub async fn set_matrix_get_rate(&self, rate: Option<Duration>) -> Result<(), BackendError> {
         self.send_noresp(SetEnum::MatrixGetRate(Item::new((), rate)))
             .await
     }
 
-    pub async fn benchmark(&self, board: BoardId) -> Result<Benchmark, String> {
+    pub async fn set_watch_leds(&self, board: BoardId, watch: bool) -> Result<(), BackendError> {
+        self.send_noresp(SetEnum::WatchLeds(Item::new(board, watch)))
+            .await
+    }
+
+    pub async fn benchmark(&self, board: BoardId) -> Result<Benchmark, BackendError> {
         let resp = self.send(SetEnum::Benchmark(board)).await?;
         if let Response::Benchmark(benchmark) = resp {
             Ok(benchmark)
@@ -226,7 +380,7 @@ impl ThreadClient {
         }
     }
 
-    pub async fn nelson(&self, board: BoardId, kind: NelsonKind) -> Result<Nelson, String> {
+    pub async fn nelson(&self, board: BoardId, kind: NelsonKind) -> Result<Nelson, BackendError> {
         let resp = self.send(SetEnum::Nelson(board, kind)).await?;
         if let Response::Nelson(nelson) = resp {
             Ok(*nelson)
@@ -235,10 +389,22 @@ impl ThreadClient {
         }
     }
 
-    pub async fn led_save(&self, board: BoardId) -> Result<(), String> {
+    pub async fn led_save(&self, board: BoardId) -> Result<(), BackendError> {
         self.send_noresp(SetEnum::LedSave(board)).await
     }
 
+    pub async fn lock(&self, board: BoardId) -> Result<(), BackendError> {
+        self.send_noresp(SetEnum::Lock(board)).await
+    }
+
+    pub async fn reload(&self, board: BoardId) -> Result<(), BackendError> {
+        self.send_noresp(SetEnum::Reload(board)).await
+    }
+
+    pub async fn unlock(&self, board: BoardId) -> Result<(), BackendError> {
+        self.send_noresp(SetEnum::Unlock(board)).await
+    }
+
     pub fn close(&self) {
         let join_handle = match self.join_handle.lock().unwrap().take() {
             Some(join_handle) => join_handle,
@@ -259,25 +425,71 @@ impl ThreadClient {
     }
 }
 
+/// How long a board that has been unplugged is kept, in case it returns
+const DISCONNECT_GRACE: Duration = Duration::from_secs(10);
+
 pub enum ThreadResponse {
     BoardLoading,
     BoardLoadingDone,
     BoardAdded(Board),
     BoardRemoved(BoardId),
+    /// Board is gone, but may be reconnected with the same id
+    BoardDisconnected(BoardId),
+    BoardReconnected(BoardId),
+    /// Board could not be added, such as due to an invalid layout
+    BoardError(String),
+    /// LED brightness was changed outside the configurator
+    BrightnessChanged(BoardId, u8, i32),
+    /// LED color was changed outside the configurator
+    ColorChanged(BoardId, u8, (u8, u8, u8)),
+    /// LED settings read from the board again differ from the last read
+    LedsRefreshed(BoardId, Vec<LayerLeds>),
+}
+
+/// LED settings of a layer, in the format used by the daemon
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerLeds {
+    pub(crate) layer: u8,
+    pub(crate) brightness: i32,
+    pub(crate) color: (u8, u8, u8),
+    pub(crate) mode: Option<(u8, u8)>,
 }
 
 struct ThreadBoard {
     matrix: Matrix,
     matrix_channel: async_mpsc::UnboundedSender<Matrix>,
     has_matrix: bool,
+    /// LED index of each layer, if the board has a backlight that needs to
+    /// be polled for changes
+    led_indices: Vec<u8>,
+    has_color: bool,
+    has_mode: bool,
+    /// Poll LED settings, while a view of them is shown
+    watch_leds: bool,
+    /// LED settings as last read by `leds_refresh_all`
+    leds: Option<Vec<LayerLeds>>,
+    disconnected: Option<Instant>,
 }
 
 impl ThreadBoard {
-    fn new(matrix_channel: async_mpsc::UnboundedSender<Matrix>, has_matrix: bool) -> Self {
+    fn new(matrix_channel: async_mpsc::UnboundedSender<Matrix>, board: &Board) -> Self {
+        let meta = &board.layout().meta;
+        // Changes are already sent as events by daemons that support it
+        let led_indices = if meta.has_brightness && !board.capabilities().led_events {
+            board.layers().iter().map(|layer| layer.index()).collect()
+        } else {
+            Vec::new()
+        };
         Self {
             matrix: Matrix::default(),
             matrix_channel,
-            has_matrix,
+            has_matrix: board.has_matrix(),
+            led_indices,
+            has_color: meta.has_color,
+            has_mode: board.capabilities().modes,
+            watch_leds: false,
+            leds: None,
+            disconnected: None,
         }
     }
 }
@@ -288,6 +500,10 @@ struct Thread {
     client: Weak<ThreadClient>,
     response_channel: async_mpsc::UnboundedSender<ThreadResponse>,
     matrix_get_rate: Cell<Option<Duration>>,
+    /// Refresh periodically, if hotplug events aren't available
+    poll_refresh: bool,
+    /// Boards that failed to be added, which aren't retried until reconnected
+    failed_boards: RefCell<HashSet<BoardId>>,
 }
 
 impl Thread {
@@ -295,6 +511,7 @@ impl Thread {
         daemon: Box<dyn Daemon>,
         client: Arc<ThreadClient>,
         response_channel: async_mpsc::UnboundedSender<ThreadResponse>,
+        poll_refresh: bool,
     ) -> Self {
         Self {
             daemon,
@@ -302,6 +519,8 @@ impl Thread {
             response_channel,
             boards: RefCell::new(HashMap::new()),
             matrix_get_rate: Cell::new(None),
+            poll_refresh,
+            failed_boards: RefCell::new(HashSet::new()),
         }
     }
 
@@ -325,9 +544,30 @@ impl Thread {
                 }))
                 .unwrap();
 
+            spawner
+                .spawn_local(clone!(@strong self_ => async move {
+                    loop {
+                        Delay::new(Duration::from_secs(1)).await;
+                        if self_.poll_refresh {
+                            if let Err(err) = self_.refresh() {
+                                error!("Failed to refresh boards: {}", err);
+                            }
+                        }
+                        self_.remove_expired(&mut self_.boards.borrow_mut());
+                        // Firmware can change LEDs itself, with keys like `KBD_UP`
+                        self_.leds_refresh_all();
+                    }
+                }))
+                .unwrap();
+
             pool.run_until(async move {
                 while let Some(set) = channel.next().await {
-                    if !self_.handle_set(set) {
+                    // Take everything that is queued, so it can be pipelined
+                    let mut sets = vec![set];
+                    while let Ok(Some(set)) = channel.try_next() {
+                        sets.push(set);
+                    }
+                    if !self_.handle_sets(sets) {
                         break;
                     }
                 }
@@ -335,8 +575,41 @@ impl Thread {
         })
     }
 
+    fn handle_sets(&self, sets: Vec<Set>) -> bool {
+        let mut batch = Vec::new();
+        for set in sets {
+            if set.is_canceled() {
+                continue;
+            }
+
+            match set.inner.command() {
+                Some(command) => batch.push((set, command)),
+                None => {
+                    self.handle_batch(mem::take(&mut batch));
+                    if !self.handle_set(set) {
+                        return false;
+                    }
+                }
+            }
+        }
+        self.handle_batch(batch);
+        true
+    }
+
+    fn handle_batch(&self, batch: Vec<(Set, DaemonCommand)>) {
+        if batch.is_empty() {
+            return;
+        }
+
+        let (sets, commands): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+        let responses = self.daemon.dispatch_commands(commands);
+        for (set, response) in sets.into_iter().zip(responses) {
+            set.reply(response.map(|_| ()));
+        }
+    }
+
     fn handle_set(&self, set: Set) -> bool {
-        if set.oneshot.is_canceled() && set.inner != SetEnum::Exit {
+        if set.is_canceled() {
             return true;
         }
 
@@ -344,6 +617,14 @@ impl Thread {
             SetEnum::KeyMap(Item { key, value }) => {
                 set.reply(self.daemon.keymap_set(key.0, key.1, key.2, key.3, value))
             }
+            SetEnum::KeyMapMany(board, ref keys) => {
+                let res = self.daemon.keymap_set_many(board, keys.clone());
+                set.reply(res)
+            }
+            SetEnum::KeyMapGetAll(board, ref keys) => {
+                let res = self.daemon.keymap_get_all(board, keys.clone());
+                set.reply(res)
+            }
             SetEnum::Color(Item { key, value }) => {
                 set.reply(self.daemon.set_color(key.0, key.1, value))
             }
@@ -356,11 +637,65 @@ impl Thread {
             SetEnum::Benchmark(board) => set.reply(self.daemon.benchmark(board)),
             SetEnum::Nelson(board, kind) => set.reply(self.daemon.nelson(board, kind)),
             SetEnum::LedSave(board) => set.reply(self.daemon.led_save(board)),
+            SetEnum::Lock(board) => set.reply(self.daemon.lock(board)),
+            SetEnum::Unlock(board) => set.reply(self.daemon.unlock(board)),
             SetEnum::MatrixGetRate(Item { value, .. }) => {
                 self.matrix_get_rate.set(value);
                 set.reply(Ok(()))
             }
+            SetEnum::WatchLeds(Item { key, value }) => {
+                if let Some(board) = self.boards.borrow_mut().get_mut(&key) {
+                    board.watch_leds = value;
+                    // Send the first read, which may differ from settings
+                    // cached before watching stopped
+                    board.leds = None;
+                }
+                set.reply(Ok(()))
+            }
             SetEnum::Refresh => set.reply(self.refresh()),
+            SetEnum::Reload(board) => {
+                if self.boards.borrow_mut().remove(&board).is_some() {
+                    let _ = self
+                        .response_channel
+                        .unbounded_send(ThreadResponse::BoardRemoved(board));
+                }
+                self.failed_boards.borrow_mut().remove(&board);
+                set.reply(self.refresh())
+            }
+            SetEnum::Event(event) => {
+                debug!("Daemon event: {:?}", event);
+                match event {
+                    DaemonEvent::BoardAdded(_) | DaemonEvent::BoardRemoved(_) => {
+                        if let Err(err) = self.refresh() {
+                            error!("Failed to refresh boards: {}", err);
+                        }
+                    }
+                    DaemonEvent::BrightnessChanged {
+                        board,
+                        index,
+                        brightness,
+                    } => {
+                        let _ = self.response_channel.unbounded_send(
+                            ThreadResponse::BrightnessChanged(board, index, brightness),
+                        );
+                    }
+                    DaemonEvent::ColorChanged {
+                        board,
+                        index,
+                        color,
+                    } => {
+                        let _ = self
+                            .response_channel
+                            .unbounded_send(ThreadResponse::ColorChanged(board, index, color));
+                    }
+                }
+            }
+            SetEnum::Hotplug => {
+                debug!("Hotplug event");
+                if let Err(err) = self.refresh() {
+                    error!("Failed to refresh boards: {}", err);
+                }
+            }
             SetEnum::Exit => return false,
         }
 
@@ -369,7 +704,7 @@ impl Thread {
 
     fn matrix_refresh_all(&self) {
         for (k, v) in self.boards.borrow_mut().iter_mut() {
-            if !v.has_matrix {
+            if !v.has_matrix || v.disconnected.is_some() {
                 continue;
             }
             let matrix = match self.daemon.matrix_get(*k) {
@@ -386,27 +721,118 @@ impl Thread {
         }
     }
 
-    fn refresh(&self) -> Result<(), String> {
+    /// Read LED settings of every layer, in one batch of commands
+    fn read_leds(&self, id: BoardId, board: &ThreadBoard) -> Result<Vec<LayerLeds>, BackendError> {
+        let mut commands = Vec::new();
+        for (layer, index) in board.led_indices.iter().enumerate() {
+            commands.push(DaemonCommand::brightness {
+                board: id,
+                index: *index,
+            });
+            if board.has_color {
+                commands.push(DaemonCommand::color {
+                    board: id,
+                    index: *index,
+                });
+            }
+            if board.has_mode {
+                commands.push(DaemonCommand::mode {
+                    board: id,
+                    layer: layer as u8,
+                });
+            }
+        }
+
+        let mut responses = self.daemon.dispatch_commands(commands).into_iter();
+        let mut next = || {
+            responses
+                .next()
+                .unwrap_or_else(|| Err(BackendError::Protocol("Missing response".to_string())))
+        };
+        let unexpected = || BackendError::Protocol("Unexpected response".to_string());
+        let mut leds = Vec::new();
+        for layer in 0..board.led_indices.len() {
+            let brightness = match next()? {
+                DaemonResponse::brightness(brightness) => brightness,
+                _ => return Err(unexpected()),
+            };
+            let color = if board.has_color {
+                match next()? {
+                    DaemonResponse::color(color) => color,
+                    _ => return Err(unexpected()),
+                }
+            } else {
+                (0, 0, 0)
+            };
+            let mode = if board.has_mode {
+                match next()? {
+                    DaemonResponse::mode(mode) => Some(mode),
+                    _ => return Err(unexpected()),
+                }
+            } else {
+                None
+            };
+            leds.push(LayerLeds {
+                layer: layer as u8,
+                brightness,
+                color,
+                mode,
+            });
+        }
+        Ok(leds)
+    }
+
+    fn leds_refresh_all(&self) {
+        for (k, v) in self.boards.borrow_mut().iter_mut() {
+            if !v.watch_leds || v.led_indices.is_empty() || v.disconnected.is_some() {
+                continue;
+            }
+            let leds = match self.read_leds(*k, v) {
+                Ok(leds) => leds,
+                Err(err) => {
+                    error!("Failed to get LED settings: {}", err);
+                    continue;
+                }
+            };
+            if v.leds.as_ref() != Some(&leds) {
+                let _ = self
+                    .response_channel
+                    .unbounded_send(ThreadResponse::LedsRefreshed(*k, leds.clone()));
+                v.leds = Some(leds);
+            }
+        }
+    }
+
+    fn refresh(&self) -> Result<(), BackendError> {
         let mut boards = self.boards.borrow_mut();
 
         self.daemon.refresh()?;
 
         let new_ids = self.daemon.boards()?;
 
-        // Removed boards
-        let response_channel = &self.response_channel;
-        boards.retain(|id, _| {
-            if new_ids.iter().find(|i| *i == id).is_none() {
-                let _ = response_channel.unbounded_send(ThreadResponse::BoardRemoved(*id));
-                return false;
+        // Disconnected and reconnected boards
+        for (id, board) in boards.iter_mut() {
+            let connected = new_ids.contains(id);
+            if !connected && board.disconnected.is_none() {
+                board.disconnected = Some(Instant::now());
+                let _ = self
+                    .response_channel
+                    .unbounded_send(ThreadResponse::BoardDisconnected(*id));
+            } else if connected && board.disconnected.is_some() {
+                board.disconnected = None;
+                let _ = self
+                    .response_channel
+                    .unbounded_send(ThreadResponse::BoardReconnected(*id));
             }
-            true
-        });
+        }
+        self.remove_expired(&mut boards);
 
         // Added boards
+        let mut failed_boards = self.failed_boards.borrow_mut();
+        failed_boards.retain(|id| new_ids.contains(id));
         let mut have_new_board = false;
         for i in &new_ids {
-            if boards.contains_key(i) {
+            if boards.contains_key(i) || failed_boards.contains(i) {
                 continue;
             }
 
@@ -425,12 +851,18 @@ impl Thread {
                 matrix_reciever,
             ) {
                 Ok(board) => {
-                    boards.insert(*i, ThreadBoard::new(matrix_sender, board.has_matrix()));
+                    boards.insert(*i, ThreadBoard::new(matrix_sender, &board));
                     let _ = self
                         .response_channel
                         .unbounded_send(ThreadResponse::BoardAdded(board));
                 }
-                Err(err) => error!("Failed to add board: {}", err),
+                Err(err) => {
+                    error!("Failed to add board: {}", err);
+                    failed_boards.insert(*i);
+                    let _ = self
+                        .response_channel
+                        .unbounded_send(ThreadResponse::BoardError(err));
+                }
             }
         }
 
@@ -442,4 +874,16 @@ impl Thread {
 
         Ok(())
     }
+
+    /// Remove boards that have been disconnected for longer than `DISCONNECT_GRACE`
+    fn remove_expired(&self, boards: &mut HashMap<BoardId, ThreadBoard>) {
+        let response_channel = &self.response_channel;
+        boards.retain(|id, board| match board.disconnected {
+            Some(time) if time.elapsed() >= DISCONNECT_GRACE => {
+                let _ = response_channel.unbounded_send(ThreadResponse::BoardRemoved(*id));
+                false
+            }
+            _ => true,
+        });
+    }
 }
@@ -1,7 +1,7 @@
 use std::{cell::RefCell, collections::HashMap};
 
-use super::{BoardId, Daemon};
-use crate::{fl, Benchmark, Layout, Matrix, Nelson, NelsonKind};
+use super::{BackendError, BoardId, Daemon};
+use crate::{Benchmark, BoardCapabilities, Layout, Matrix, Nelson, NelsonKind};
 
 struct BoardDummy {
     name: String,
@@ -33,6 +33,8 @@ impl BoardDummy {
 
 pub struct DaemonDummy {
     boards: Vec<BoardDummy>,
+    /// LED indices that `set_color` fails for
+    failing_leds: Vec<u8>,
 }
 
 impl DaemonDummy {
@@ -48,34 +50,65 @@ impl DaemonDummy {
                 modes: Default::default(),
             })
             .collect();
-        Self { boards }
+        Self {
+            boards,
+            failing_leds: Vec::new(),
+        }
+    }
+
+    /// Make `set_color` fail for the LEDs `indices`, to test error handling
+    #[cfg(test)]
+    pub(crate) fn fail_set_color(mut self, indices: &[u8]) -> Self {
+        self.failing_leds = indices.to_vec();
+        self
     }
 
-    fn board(&self, board: BoardId) -> Result<&BoardDummy, String> {
+    fn board(&self, board: BoardId) -> Result<&BoardDummy, BackendError> {
         self.boards
             .get(board.0 as usize)
-            .ok_or_else(|| fl!("no-board"))
+            .ok_or(BackendError::Disconnected)
     }
 }
 
 impl Daemon for DaemonDummy {
-    fn boards(&self) -> Result<Vec<BoardId>, String> {
+    fn boards(&self) -> Result<Vec<BoardId>, BackendError> {
         Ok((0..self.boards.len() as u128).map(BoardId).collect())
     }
 
-    fn model(&self, board: BoardId) -> Result<String, String> {
+    fn model(&self, board: BoardId) -> Result<String, BackendError> {
         Ok(self.board(board)?.name.clone())
     }
 
-    fn version(&self, _board: BoardId) -> Result<String, String> {
+    fn version(&self, _board: BoardId) -> Result<String, BackendError> {
         Ok("1970-01-01-deadbee".to_string())
     }
 
+    fn capabilities(&self, board: BoardId) -> Result<BoardCapabilities, BackendError> {
+        let layout = &self.board(board)?.layout;
+        Ok(BoardCapabilities {
+            keymap: true,
+            matrix: true,
+            per_key_led: layout.meta.has_mode && layout.leds.values().any(|leds| !leds.is_empty()),
+            modes: layout.meta.has_mode,
+            led_save: true,
+            nelson: false,
+            benchmark: false,
+            num_layers: layout.meta.num_layers,
+            led_events: false,
+        })
+    }
+
     fn is_fake(&self) -> bool {
         true
     }
 
-    fn keymap_get(&self, board: BoardId, layer: u8, output: u8, input: u8) -> Result<u16, String> {
+    fn keymap_get(
+        &self,
+        board: BoardId,
+        layer: u8,
+        output: u8,
+        input: u8,
+    ) -> Result<u16, BackendError> {
         let keymap = self.board(board)?.keymap.borrow();
         Ok(keymap.get(&(layer, output, input)).copied().unwrap_or(0))
     }
@@ -87,91 +120,104 @@ impl Daemon for DaemonDummy {
         output: u8,
         input: u8,
         value: u16,
-    ) -> Result<(), String> {
+    ) -> Result<(), BackendError> {
         let mut keymap = self.board(board)?.keymap.borrow_mut();
         keymap.insert((layer, output, input), value);
         Ok(())
     }
 
-    fn matrix_get(&self, _board: BoardId) -> Result<Matrix, String> {
+    fn matrix_get(&self, _board: BoardId) -> Result<Matrix, BackendError> {
         Ok(Matrix::new(0, 0, Vec::new().into_boxed_slice()))
     }
 
-    fn benchmark(&self, _board: BoardId) -> Result<Benchmark, String> {
-        Err("Unimplemented".to_string())
+    fn benchmark(&self, _board: BoardId) -> Result<Benchmark, BackendError> {
+        Err(BackendError::Unsupported("benchmark".to_string()))
     }
 
-    fn nelson(&self, _board: BoardId, _kind: NelsonKind) -> Result<Nelson, String> {
-        Err("Unimplemented".to_string())
+    fn nelson(&self, _board: BoardId, _kind: NelsonKind) -> Result<Nelson, BackendError> {
+        Err(BackendError::Unsupported("nelson".to_string()))
     }
 
-    fn color(&self, board: BoardId, index: u8) -> Result<(u8, u8, u8), String> {
+    fn color(&self, board: BoardId, index: u8) -> Result<(u8, u8, u8), BackendError> {
         let board = self.board(board)?;
         if !board.valid_index(index, true) {
-            return Err(format!("Can't get color index {} {}", index, board.name));
+            return Err(BackendError::InvalidIndex(index));
         }
         Ok(*board.colors.borrow_mut().entry(index).or_default())
     }
 
-    fn set_color(&self, board: BoardId, index: u8, color: (u8, u8, u8)) -> Result<(), String> {
+    fn set_color(
+        &self,
+        board: BoardId,
+        index: u8,
+        color: (u8, u8, u8),
+    ) -> Result<(), BackendError> {
         let board = self.board(board)?;
         if !board.valid_index(index, true) {
-            return Err(format!("Can't set color index {}", index));
+            return Err(BackendError::InvalidIndex(index));
+        }
+        if self.failing_leds.contains(&index) {
+            return Err(BackendError::Io(format!("Failed to set LED {}", index)));
         }
         board.colors.borrow_mut().insert(index, color);
         Ok(())
     }
 
-    fn max_brightness(&self, _board: BoardId) -> Result<i32, String> {
+    fn max_brightness(&self, _board: BoardId) -> Result<i32, BackendError> {
         Ok(100)
     }
 
-    fn brightness(&self, board: BoardId, index: u8) -> Result<i32, String> {
+    fn brightness(&self, board: BoardId, index: u8) -> Result<i32, BackendError> {
         let board = self.board(board)?;
         if !board.valid_index(index, false) {
-            return Err(format!("Can't get brightness index {}", index));
+            return Err(BackendError::InvalidIndex(index));
         }
         Ok(*board.brightnesses.borrow_mut().entry(index).or_default())
     }
 
-    fn set_brightness(&self, board: BoardId, index: u8, brightness: i32) -> Result<(), String> {
+    fn set_brightness(
+        &self,
+        board: BoardId,
+        index: u8,
+        brightness: i32,
+    ) -> Result<(), BackendError> {
         let board = self.board(board)?;
         if !board.valid_index(index, false) {
-            return Err(format!("Can't set brightness index {}", index));
+            return Err(BackendError::InvalidIndex(index));
         }
         board.brightnesses.borrow_mut().insert(index, brightness);
         Ok(())
     }
 
-    fn mode(&self, board: BoardId, layer: u8) -> Result<(u8, u8), String> {
+    fn mode(&self, board: BoardId, layer: u8) -> Result<(u8, u8), BackendError> {
         let index = layer + 0xf0;
         let board = self.board(board)?;
         if !board.valid_index(index, false) {
-            return Err(format!("Can't get mode index {}", index));
+            return Err(BackendError::InvalidIndex(index));
         }
         Ok(*board.modes.borrow_mut().entry(index).or_default())
     }
 
-    fn set_mode(&self, board: BoardId, layer: u8, mode: u8, speed: u8) -> Result<(), String> {
+    fn set_mode(&self, board: BoardId, layer: u8, mode: u8, speed: u8) -> Result<(), BackendError> {
         let index = layer + 0xf0;
         let board = self.board(board)?;
         if !board.valid_index(index, false) {
-            return Err(format!("Can't get mode index {}", index));
+            return Err(BackendError::InvalidIndex(index));
         }
         board.modes.borrow_mut().insert(index, (mode, speed));
         Ok(())
     }
 
-    fn led_save(&self, board: BoardId) -> Result<(), String> {
+    fn led_save(&self, board: BoardId) -> Result<(), BackendError> {
         self.board(board)?;
         Ok(())
     }
 
-    fn refresh(&self) -> Result<(), String> {
+    fn refresh(&self) -> Result<(), BackendError> {
         Ok(())
     }
 
-    fn exit(&self) -> Result<(), String> {
+    fn exit(&self) -> Result<(), BackendError> {
         Ok(())
     }
 }
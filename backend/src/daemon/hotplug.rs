@@ -0,0 +1,133 @@
+use std::{fs, io, mem, os::unix::io::RawFd};
+
+/// Kernel uevent multicast group
+const UEVENT_GROUP_KERNEL: u32 = 1;
+
+/// Watches kernel uevents for System76 hidraw devices being added or removed
+pub struct HotplugMonitor {
+    fd: RawFd,
+}
+
+impl HotplugMonitor {
+    pub fn new() -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                libc::NETLINK_KOBJECT_UEVENT,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Closes `fd` on error
+        let monitor = Self { fd };
+
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = UEVENT_GROUP_KERNEL;
+        let res = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Wake periodically, so the monitoring thread can notice it should exit
+        let timeout = libc::timeval {
+            tv_sec: 1,
+            tv_usec: 0,
+        };
+        let res = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &timeout as *const libc::timeval as *const libc::c_void,
+                mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(monitor)
+    }
+
+    /// Wait up to a second for a uevent, returning `true` if it may have
+    /// added or removed a board
+    pub fn wait(&self) -> io::Result<bool> {
+        let mut buf = [0u8; 8192];
+        let len =
+            unsafe { libc::recv(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+        if len < 0 {
+            let err = io::Error::last_os_error();
+            return match err.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => Ok(false),
+                _ => Err(err),
+            };
+        }
+
+        Ok(match parse_uevent(&buf[..len as usize]) {
+            Some(("add", "hidraw", devpath)) => is_system76_hidraw(devpath),
+            // Device is already gone from sysfs, so it can't be checked
+            Some(("remove", "hidraw", _)) => true,
+            _ => false,
+        })
+    }
+}
+
+impl Drop for HotplugMonitor {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// Parse action, subsystem, and devpath from kernel uevent
+fn parse_uevent(data: &[u8]) -> Option<(&str, &str, &str)> {
+    let mut action = None;
+    let mut subsystem = None;
+    let mut devpath = None;
+    // First field is `action@devpath`, followed by `KEY=value` fields
+    for field in data.split(|b| *b == 0).skip(1) {
+        let field = std::str::from_utf8(field).ok()?;
+        let mut parts = field.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some("ACTION"), Some(value)) => action = Some(value),
+            (Some("SUBSYSTEM"), Some(value)) => subsystem = Some(value),
+            (Some("DEVPATH"), Some(value)) => devpath = Some(value),
+            _ => {}
+        }
+    }
+    Some((action?, subsystem?, devpath?))
+}
+
+/// Check the `HID_ID` of the device a hidraw node belongs to
+fn is_system76_hidraw(devpath: &str) -> bool {
+    match fs::read_to_string(format!("/sys{}/device/uevent", devpath)) {
+        Ok(uevent) => uevent
+            .lines()
+            .any(|line| line.starts_with("HID_ID=") && line.contains(":00003384:")),
+        // Refresh anyway, if it isn't known
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uevent_parse() {
+        let data = b"add@/devices/pci0000:00/hidraw/hidraw3\0ACTION=add\0DEVPATH=/devices/pci0000:00/hidraw/hidraw3\0SUBSYSTEM=hidraw\0DEVNAME=hidraw3\0SEQNUM=4242\0";
+        assert_eq!(
+            parse_uevent(data),
+            Some(("add", "hidraw", "/devices/pci0000:00/hidraw/hidraw3"))
+        );
+        assert_eq!(parse_uevent(b"libudev\0ACTION=add\0"), None);
+    }
+}
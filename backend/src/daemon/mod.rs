@@ -1,39 +1,193 @@
 use serde::{Deserialize, Serialize};
 
-use crate::{Benchmark, Matrix, Nelson, NelsonKind};
+use crate::{BackendError, Benchmark, BoardCapabilities, Matrix, Nelson, NelsonKind};
 
 mod client;
 mod daemon_thread;
 mod dummy;
 mod server;
 
+#[cfg(target_os = "linux")]
+mod hotplug;
+#[cfg(target_os = "linux")]
+use self::hotplug::HotplugMonitor;
 #[cfg(target_os = "linux")]
 mod s76power;
 #[cfg(target_os = "linux")]
 pub use self::s76power::*;
+#[cfg(target_os = "linux")]
+mod socket;
+#[cfg(target_os = "linux")]
+pub use self::socket::{listen as listen_socket, SocketServer, SOCKET_PATH};
 
 pub use self::{client::*, daemon_thread::*, dummy::*, server::*};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
 pub struct BoardId(u128);
 
+impl BoardId {
+    /// Derive id from a property of the board that is stable across reconnects,
+    /// so the same board has the same id when it is plugged in again
+    pub(crate) fn from_identity(identity: &str) -> Self {
+        // 128-bit FNV-1a
+        let mut hash = 0x6c62272e07bb014262b821756295c58d_u128;
+        for byte in identity.bytes() {
+            hash ^= u128::from(byte);
+            hash = hash.wrapping_mul(0x0000000001000000000000000000013b);
+        }
+        Self(hash)
+    }
+}
+
+/// Version of the protocol spoken between `DaemonClient` and `DaemonServer`
+///
+/// This must be incremented when the encoding of an existing command changes.
+/// Adding a command does not require a new version, since supported commands
+/// are advertised in `DaemonHello`.
+pub const PROTOCOL_VERSION: u32 = 4;
+
+/// Handshake sent by the server before handling any commands
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DaemonHello {
+    pub version: u32,
+    pub commands: Vec<String>,
+}
+
+impl DaemonHello {
+    /// Handshake for this build of the daemon
+    pub fn current() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            commands: DaemonCommand::names()
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+        }
+    }
+}
+
+/// Command sent to the server, with an id identifying its `DaemonReply`
+#[derive(Deserialize, Serialize)]
+pub struct DaemonRequest {
+    pub id: u64,
+    pub command: DaemonCommand,
+}
+
+/// Response to the `DaemonRequest` with the same id
+///
+/// Replies are not necessarily sent in the order requests were received.
+#[derive(Deserialize, Serialize)]
+pub struct DaemonReply {
+    pub id: u64,
+    pub response: Result<DaemonResponse, BackendError>,
+}
+
+/// Change in the daemon's state, sent to clients without a request
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum DaemonEvent {
+    BoardAdded(BoardId),
+    BoardRemoved(BoardId),
+    /// LED brightness was changed outside of this client, such as with Fn keys
+    BrightnessChanged {
+        board: BoardId,
+        index: u8,
+        brightness: i32,
+    },
+    /// LED color was changed outside of this client
+    ColorChanged {
+        board: BoardId,
+        index: u8,
+        color: (u8, u8, u8),
+    },
+}
+
+/// Line sent by the server, after `DaemonHello`
+#[derive(Deserialize, Serialize)]
+pub enum DaemonMessage {
+    Reply(DaemonReply),
+    Event(DaemonEvent),
+}
+
 pub trait DaemonClientTrait: Send + 'static {
-    fn send_command(&self, command: DaemonCommand) -> Result<DaemonResponse, String>;
+    fn send_command(&self, command: DaemonCommand) -> Result<DaemonResponse, BackendError>;
+
+    /// Send multiple commands without waiting for each response, returning
+    /// responses in the same order as `commands`
+    fn send_commands(
+        &self,
+        commands: Vec<DaemonCommand>,
+    ) -> Vec<Result<DaemonResponse, BackendError>> {
+        commands
+            .into_iter()
+            .map(|command| self.send_command(command))
+            .collect()
+    }
+
+    /// Check if the command named `name` is handled by the other end
+    fn supports_command(&self, _name: &str) -> bool {
+        true
+    }
+
+    /// Set callback for events the other end sends without a request
+    fn set_event_handler(&self, _handler: Box<dyn Fn(DaemonEvent) + Send>) {}
 }
 
 // Define Daemon trait, DaemonCommand enum, and DaemonResponse enum
+//
+// A command may name a fallback function after `=`, which is used as the
+// default implementation, and when a client's daemon doesn't support it.
 macro_rules! commands {
-    ( $( fn $func:ident(&self $(,)? $( $arg:ident: $type:ty ),*) -> Result<$ret:ty, String>; )* ) => {
+    (@trait_fn $func:ident ($( $arg:ident: $type:ty ),*) $ret:ty) => {
+        fn $func(&self, $( $arg: $type ),*) -> Result<$ret, BackendError>;
+    };
+    (@trait_fn $func:ident ($( $arg:ident: $type:ty ),*) $ret:ty, $fallback:path) => {
+        fn $func(&self, $( $arg: $type ),*) -> Result<$ret, BackendError> {
+            $fallback(self, $( $arg ),*)
+        }
+    };
+    (@client_fn $func:ident ($( $arg:ident: $type:ty ),*) $ret:ty) => {
+        fn $func(&self, $( $arg: $type ),*) -> Result<$ret, BackendError> {
+            if !DaemonClientTrait::supports_command(self, stringify!($func)) {
+                return Err(BackendError::Unsupported(stringify!($func).to_string()));
+            }
+            commands!(@client_send self $func ($( $arg ),*))
+        }
+    };
+    (@client_fn $func:ident ($( $arg:ident: $type:ty ),*) $ret:ty, $fallback:path) => {
+        fn $func(&self, $( $arg: $type ),*) -> Result<$ret, BackendError> {
+            if !DaemonClientTrait::supports_command(self, stringify!($func)) {
+                return $fallback(self, $( $arg ),*);
+            }
+            commands!(@client_send self $func ($( $arg ),*))
+        }
+    };
+    (@client_send $self:ident $func:ident ($( $arg:ident ),*)) => {{
+        let res = $self.send_command(DaemonCommand::$func{$( $arg ),*});
+        match res {
+            Ok(DaemonResponse::$func(ret)) => Ok(ret),
+            Ok(_) => unreachable!(),
+            Err(err) => Err(err),
+        }
+    }};
+    ( $( fn $func:ident(&self $(,)? $( $arg:ident: $type:ty ),*) -> Result<$ret:ty, BackendError> $( = $fallback:path )?; )* ) => {
         pub trait Daemon: Send + 'static {
         $(
-            fn $func(&self, $( $arg: $type ),*) -> Result<$ret, String>;
+            commands!(@trait_fn $func ($( $arg: $type ),*) $ret $(, $fallback )?);
         )*
 
             fn is_fake(&self) -> bool {
                 false
             }
 
-            fn dispatch_command_to_method(&self, command: DaemonCommand) -> Result<DaemonResponse, String> {
+            /// Check if the command named `name` is implemented
+            fn supports_command(&self, _name: &str) -> bool {
+                true
+            }
+
+            /// Set callback for events from other clients of a shared daemon
+            fn set_event_handler(&self, _handler: Box<dyn Fn(DaemonEvent) + Send>) {}
+
+            fn dispatch_command_to_method(&self, command: DaemonCommand) -> Result<DaemonResponse, BackendError> {
                 match command {
                 $(
                     DaemonCommand::$func{$( $arg ),*} => {
@@ -42,6 +196,14 @@ macro_rules! commands {
                 )*
                 }
             }
+
+            /// Run multiple commands, returning responses in the same order
+            fn dispatch_commands(&self, commands: Vec<DaemonCommand>) -> Vec<Result<DaemonResponse, BackendError>> {
+                commands
+                    .into_iter()
+                    .map(|command| self.dispatch_command_to_method(command))
+                    .collect()
+            }
         }
 
         #[allow(non_camel_case_types)]
@@ -53,6 +215,13 @@ macro_rules! commands {
         ),*
         }
 
+        impl DaemonCommand {
+            /// Names of all commands, as used in serialization
+            pub fn names() -> &'static [&'static str] {
+                &[$( stringify!($func) ),*]
+            }
+        }
+
         #[allow(non_camel_case_types)]
         #[derive(Deserialize, Serialize)]
         #[serde(tag = "t", content = "c")]
@@ -63,41 +232,132 @@ macro_rules! commands {
         }
 
         impl<T: DaemonClientTrait> Daemon for T {
-        $(
-            fn $func(&self, $( $arg: $type ),*) -> Result<$ret, String> {
-                let res = self.send_command(DaemonCommand::$func{$( $arg ),*});
-                match res {
-                    Ok(DaemonResponse::$func(ret)) => Ok(ret),
-                    Ok(_) => unreachable!(),
-                    Err(err) => Err(err),
-                }
+            fn supports_command(&self, name: &str) -> bool {
+                DaemonClientTrait::supports_command(self, name)
+            }
+
+            fn set_event_handler(&self, handler: Box<dyn Fn(DaemonEvent) + Send>) {
+                DaemonClientTrait::set_event_handler(self, handler)
+            }
+
+            fn dispatch_commands(&self, commands: Vec<DaemonCommand>) -> Vec<Result<DaemonResponse, BackendError>> {
+                self.send_commands(commands)
             }
+
+        $(
+            commands!(@client_fn $func ($( $arg: $type ),*) $ret $(, $fallback )?);
         )*
         }
     };
 }
 
 commands! {
-    fn boards(&self) -> Result<Vec<BoardId>, String>;
-    fn model(&self, board: BoardId) -> Result<String, String>;
-    fn version(&self, board: BoardId) -> Result<String, String>;
-    fn refresh(&self) -> Result<(), String>;
-    fn keymap_get(&self, board: BoardId, layer: u8, output: u8, input: u8) -> Result<u16, String>;
-    fn keymap_set(&self, board: BoardId, layer: u8, output: u8, input: u8, value: u16) -> Result<(), String>;
-    fn matrix_get(&self, board: BoardId) -> Result<Matrix, String>;
-    fn benchmark(&self, board: BoardId) -> Result<Benchmark, String>;
-    fn nelson(&self, board: BoardId, kind: NelsonKind) -> Result<Nelson, String>;
-    fn color(&self, board: BoardId, index: u8) -> Result<(u8, u8, u8), String>;
-    fn set_color(&self, board: BoardId, index: u8, color: (u8, u8, u8)) -> Result<(), String>;
-    fn max_brightness(&self, board: BoardId) -> Result<i32, String>;
-    fn brightness(&self, board: BoardId, index: u8) -> Result<i32, String>;
-    fn set_brightness(&self, board: BoardId, index: u8, brightness: i32) -> Result<(), String>;
-    fn mode(&self, board: BoardId, layer: u8) -> Result<(u8, u8), String>;
-    fn set_mode(&self, board: BoardId, layer: u8, mode: u8, speed: u8) -> Result<(), String>;
-    fn led_save(&self, board: BoardId) -> Result<(), String>;
-    fn exit(&self) -> Result<(), String>;
+    fn boards(&self) -> Result<Vec<BoardId>, BackendError>;
+    fn model(&self, board: BoardId) -> Result<String, BackendError>;
+    fn version(&self, board: BoardId) -> Result<String, BackendError>;
+    fn capabilities(&self, board: BoardId) -> Result<BoardCapabilities, BackendError> = BoardCapabilities::probe;
+    fn refresh(&self) -> Result<(), BackendError>;
+    fn keymap_get(&self, board: BoardId, layer: u8, output: u8, input: u8) -> Result<u16, BackendError>;
+    fn keymap_set(&self, board: BoardId, layer: u8, output: u8, input: u8, value: u16) -> Result<(), BackendError>;
+    fn keymap_get_all(&self, board: BoardId, keys: Vec<(u8, u8, u8)>) -> Result<Vec<u16>, BackendError> = keymap_get_all_fallback;
+    fn keymap_set_many(&self, board: BoardId, keys: Vec<(u8, u8, u8, u16)>) -> Result<(), BackendError> = keymap_set_many_fallback;
+    fn matrix_get(&self, board: BoardId) -> Result<Matrix, BackendError>;
+    fn benchmark(&self, board: BoardId) -> Result<Benchmark, BackendError>;
+    fn nelson(&self, board: BoardId, kind: NelsonKind) -> Result<Nelson, BackendError>;
+    fn color(&self, board: BoardId, index: u8) -> Result<(u8, u8, u8), BackendError>;
+    fn set_color(&self, board: BoardId, index: u8, color: (u8, u8, u8)) -> Result<(), BackendError>;
+    fn max_brightness(&self, board: BoardId) -> Result<i32, BackendError>;
+    fn brightness(&self, board: BoardId, index: u8) -> Result<i32, BackendError>;
+    fn set_brightness(&self, board: BoardId, index: u8, brightness: i32) -> Result<(), BackendError>;
+    fn mode(&self, board: BoardId, layer: u8) -> Result<(u8, u8), BackendError>;
+    fn set_mode(&self, board: BoardId, layer: u8, mode: u8, speed: u8) -> Result<(), BackendError>;
+    fn led_save(&self, board: BoardId) -> Result<(), BackendError>;
+    fn lock(&self, board: BoardId) -> Result<(), BackendError> = lock_noop;
+    fn unlock(&self, board: BoardId) -> Result<(), BackendError> = lock_noop;
+    fn exit(&self) -> Result<(), BackendError>;
+}
+
+impl DaemonCommand {
+    /// Board written to by the command, which requires holding its lock if
+    /// another client has locked it
+    pub fn board_written(&self) -> Option<BoardId> {
+        match self {
+            Self::keymap_set { board, .. }
+            | Self::keymap_set_many { board, .. }
+            | Self::nelson { board, .. }
+            | Self::set_color { board, .. }
+            | Self::set_brightness { board, .. }
+            | Self::set_mode { board, .. }
+            | Self::led_save { board } => Some(*board),
+            _ => None,
+        }
+    }
+}
+
+/// Locks only matter to daemons shared by multiple clients
+fn lock_noop<D: Daemon + ?Sized>(_daemon: &D, _board: BoardId) -> Result<(), BackendError> {
+    Ok(())
+}
+
+/// Read scancodes of `(layer, output, input)` keys with one `keymap_get` each
+fn keymap_get_all_fallback<D: Daemon + ?Sized>(
+    daemon: &D,
+    board: BoardId,
+    keys: Vec<(u8, u8, u8)>,
+) -> Result<Vec<u16>, BackendError> {
+    keys.into_iter()
+        .map(|(layer, output, input)| daemon.keymap_get(board, layer, output, input))
+        .collect()
+}
+
+/// Set scancodes of `(layer, output, input, value)` keys with one `keymap_set` each
+fn keymap_set_many_fallback<D: Daemon + ?Sized>(
+    daemon: &D,
+    board: BoardId,
+    keys: Vec<(u8, u8, u8, u16)>,
+) -> Result<(), BackendError> {
+    for (layer, output, input, value) in keys {
+        daemon.keymap_set(board, layer, output, input, value)?;
+    }
+    Ok(())
 }
 
 fn err_str<E: std::fmt::Debug>(err: E) -> String {
     format!("{:?}", err)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hello_lists_commands() {
+        let json = serde_json::to_string(&DaemonHello::current()).unwrap();
+        let hello = serde_json::from_str::<DaemonHello>(&json).unwrap();
+        assert_eq!(hello.version, PROTOCOL_VERSION);
+        assert_eq!(hello.commands.len(), DaemonCommand::names().len());
+        assert!(hello.commands.iter().any(|i| i == "keymap_get"));
+    }
+
+    #[test]
+    fn capabilities_probe() {
+        let daemon = DaemonDummy::new(vec!["system76/launch_1".to_string()]);
+        let board = daemon.boards().unwrap()[0];
+        let capabilities = daemon.capabilities(board).unwrap();
+        let probed = BoardCapabilities::probe(&daemon, board).unwrap();
+        assert_eq!(probed, capabilities);
+        assert!(capabilities.keymap && capabilities.per_key_led);
+    }
+
+    #[test]
+    fn board_id_stable() {
+        assert_eq!(
+            BoardId::from_identity("hid:3384:0001:port:1-2:1.1"),
+            BoardId::from_identity("hid:3384:0001:port:1-2:1.1")
+        );
+        assert_ne!(
+            BoardId::from_identity("hid:3384:0001:port:1-2:1.1"),
+            BoardId::from_identity("hid:3384:0001:port:1-3:1.1")
+        );
+    }
+}
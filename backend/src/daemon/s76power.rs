@@ -1,14 +1,35 @@
 // Note: Linux only
-// Need to watch properties of each object?
-// TODO: Hotplug detection support
 
-use std::iter::Iterator;
-use zbus::{dbus_proxy, fdo::ObjectManagerProxy, Connection};
-
-use super::{err_str, BoardId, Daemon, Matrix};
-use crate::{fl, Benchmark, Nelson, NelsonKind, Rgb};
+use std::{
+    cell::{Ref, RefCell},
+    collections::HashMap,
+    fs,
+    iter::Iterator,
+    sync::{Arc, Mutex},
+    thread,
+};
+use zbus::{
+    dbus_proxy,
+    fdo::{DBusProxy, ObjectManagerProxy},
+    Connection, Message, MessageType,
+};
+use zvariant::{OwnedObjectPath, OwnedValue};
+
+use super::{err_str, BackendError, BoardId, Daemon, DaemonEvent, Matrix};
+use crate::{layouts, runtime_layouts, Benchmark, BoardCapabilities, Nelson, NelsonKind, Rgb};
+
+type EventHandler = Box<dyn Fn(DaemonEvent) + Send>;
 
 const DBUS_NAME: &str = "com.system76.PowerDaemon";
+const DBUS_PATH: &str = "/com/system76/PowerDaemon";
+const KEYBOARD_PATH: &str = "/com/system76/PowerDaemon/keyboard";
+
+/// Model of System76 laptops, such as `darp6`
+const DMI_PRODUCT_VERSION: &str = "/sys/class/dmi/id/product_version";
+
+/// Layout for laptops that don't have one of their own, using the `15in_102`
+/// keyboard that most models share
+const GENERIC_MODEL: &str = "system76/generic";
 
 #[dbus_proxy(interface = "com.system76.PowerDaemon.Keyboard")]
 trait Keyboard {
@@ -34,59 +55,227 @@ struct Keyboard {
 }
 
 impl Keyboard {
-    fn new(path: &str) -> Result<Self, String> {
-        let connection = Connection::new_system().map_err(err_str)?;
-        let proxy =
-            KeyboardProxy::new_for_owned(connection, DBUS_NAME.to_string(), path.to_string())
-                .map_err(err_str)?;
+    fn new(connection: &Connection, path: &str) -> zbus::Result<Self> {
+        let proxy = KeyboardProxy::new_for_owned(
+            connection.clone(),
+            DBUS_NAME.to_string(),
+            path.to_string(),
+        )?;
         Ok(Self { proxy })
     }
 }
 
+fn dbus_error<E: ToString>(err: E) -> BackendError {
+    BackendError::Io(err.to_string())
+}
+
+/// Object paths of the keyboards exposed by the power daemon
+fn keyboard_paths(connection: &Connection) -> Result<Vec<String>, BackendError> {
+    let proxy =
+        ObjectManagerProxy::new_for(connection, DBUS_NAME, DBUS_PATH).map_err(dbus_error)?;
+    let objects = proxy.get_managed_objects().map_err(dbus_error)?;
+    Ok(objects
+        .keys()
+        .map(|path| path.as_str().to_string())
+        .filter(|path| path.starts_with(KEYBOARD_PATH))
+        .collect())
+}
+
+/// Events for a signal from the power daemon, if it's one we're interested in
+fn signal_events(message: &Message) -> zbus::Result<Vec<DaemonEvent>> {
+    let header = message.header()?;
+    if header.message_type()? != MessageType::Signal {
+        return Ok(Vec::new());
+    }
+    let path = match header.path()? {
+        Some(path) => path.as_str().to_string(),
+        None => return Ok(Vec::new()),
+    };
+
+    let events = match (header.interface()?, header.member()?) {
+        (Some("org.freedesktop.DBus.Properties"), Some("PropertiesChanged"))
+            if path.starts_with(KEYBOARD_PATH) =>
+        {
+            let board = BoardId::from_identity(&path);
+            let (_interface, changed, _invalidated): (
+                String,
+                HashMap<String, OwnedValue>,
+                Vec<String>,
+            ) = message.body()?;
+            let mut events = Vec::new();
+            if let Some(brightness) = changed
+                .get("brightness")
+                .and_then(|value| value.downcast_ref::<i32>())
+            {
+                events.push(DaemonEvent::BrightnessChanged {
+                    board,
+                    index: 0xff,
+                    brightness: *brightness,
+                });
+            }
+            if let Some(rgb) = changed
+                .get("color")
+                .and_then(|value| value.downcast_ref::<str>())
+                .and_then(Rgb::parse)
+            {
+                events.push(DaemonEvent::ColorChanged {
+                    board,
+                    index: 0xff,
+                    color: (rgb.r, rgb.g, rgb.b),
+                });
+            }
+            events
+        }
+        (Some("org.freedesktop.DBus.ObjectManager"), Some("InterfacesAdded")) => {
+            let (object, _interfaces): (
+                OwnedObjectPath,
+                HashMap<String, HashMap<String, OwnedValue>>,
+            ) = message.body()?;
+            if object.as_str().starts_with(KEYBOARD_PATH) {
+                vec![DaemonEvent::BoardAdded(BoardId::from_identity(
+                    object.as_str(),
+                ))]
+            } else {
+                Vec::new()
+            }
+        }
+        (Some("org.freedesktop.DBus.ObjectManager"), Some("InterfacesRemoved")) => {
+            let (object, _interfaces): (OwnedObjectPath, Vec<String>) = message.body()?;
+            if object.as_str().starts_with(KEYBOARD_PATH) {
+                vec![DaemonEvent::BoardRemoved(BoardId::from_identity(
+                    object.as_str(),
+                ))]
+            } else {
+                Vec::new()
+            }
+        }
+        _ => Vec::new(),
+    };
+    Ok(events)
+}
+
+/// Pass signals from the power daemon to the event handler, until the
+/// connection fails
+fn watch_signals(
+    connection: Connection,
+    event_handler: Arc<Mutex<Option<EventHandler>>>,
+) -> Result<(), String> {
+    DBusProxy::new(&connection)
+        .map_err(err_str)?
+        .add_match(&format!("type='signal',sender='{}'", DBUS_NAME))
+        .map_err(err_str)?;
+
+    loop {
+        let message = connection.receive_message().map_err(err_str)?;
+        let events = match signal_events(&message) {
+            Ok(events) => events,
+            Err(err) => {
+                error!("Failed to parse power daemon signal: {}", err);
+                continue;
+            }
+        };
+        if let Some(handler) = &*event_handler.lock().unwrap() {
+            for event in events {
+                handler(event);
+            }
+        }
+    }
+}
+
+/// Layout for the laptop with the DMI product version `product_version`
+fn model_for_product_version(product_version: &str) -> String {
+    let model = format!("system76/{}", product_version.trim().to_lowercase());
+    if layouts().contains(&model.as_str()) || runtime_layouts().contains(&model) {
+        model
+    } else {
+        warn!(
+            "No layout for '{}', using '{}'",
+            product_version.trim(),
+            GENERIC_MODEL
+        );
+        GENERIC_MODEL.to_string()
+    }
+}
+
+/// Layout for this laptop, from its DMI product version
+fn dmi_model() -> String {
+    match fs::read_to_string(DMI_PRODUCT_VERSION) {
+        Ok(product_version) => model_for_product_version(&product_version),
+        Err(err) => {
+            error!("Failed to read {}: {}", DMI_PRODUCT_VERSION, err);
+            GENERIC_MODEL.to_string()
+        }
+    }
+}
+
 pub struct DaemonS76Power {
-    boards: Vec<Keyboard>,
+    connection: Connection,
+    boards: RefCell<Vec<(BoardId, Keyboard)>>,
+    model: String,
+    event_handler: Arc<Mutex<Option<EventHandler>>>,
 }
 
 impl DaemonS76Power {
-    fn board(&self, board: BoardId) -> Result<&Keyboard, String> {
-        self.boards
-            .get(board.0 as usize)
-            .ok_or_else(|| fl!("no-board"))
+    fn board(&self, board: BoardId) -> Result<Ref<Keyboard>, BackendError> {
+        let boards = self.boards.borrow();
+        let index = boards
+            .iter()
+            .position(|(id, _)| *id == board)
+            .ok_or(BackendError::Disconnected)?;
+        Ok(Ref::map(boards, |boards| &boards[index].1))
     }
 }
 
 impl DaemonS76Power {
     pub fn new() -> Result<Self, String> {
-        let mut boards = Vec::new();
-
         let connection = Connection::new_system().map_err(err_str)?;
-        let proxy =
-            ObjectManagerProxy::new_for(&connection, DBUS_NAME, "/com/system76/PowerDaemon")
-                .map_err(err_str)?;
-        let objects = proxy.get_managed_objects().map_err(err_str)?;
-
-        for path in objects.keys() {
-            if path.starts_with("/com/system76/PowerDaemon/keyboard") {
-                boards.push(Keyboard::new(&path)?);
+        let event_handler = Arc::new(Mutex::new(None));
+
+        // Signals are read on a connection of their own, so they aren't
+        // consumed while waiting for replies to method calls
+        let signal_connection = Connection::new_system().map_err(err_str)?;
+        let signal_event_handler = event_handler.clone();
+        thread::spawn(move || {
+            if let Err(err) = watch_signals(signal_connection, signal_event_handler) {
+                error!("Failed to watch power daemon signals: {}", err);
             }
-        }
-
-        Ok(Self { boards })
+        });
+
+        let daemon = Self {
+            connection,
+            boards: RefCell::new(Vec::new()),
+            model: dmi_model(),
+            event_handler,
+        };
+        daemon.refresh().map_err(|err| err.to_string())?;
+        Ok(daemon)
     }
 }
 
 impl Daemon for DaemonS76Power {
-    fn boards(&self) -> Result<Vec<BoardId>, String> {
-        Ok((0..self.boards.len() as u128).map(BoardId).collect())
+    fn boards(&self) -> Result<Vec<BoardId>, BackendError> {
+        Ok(self.boards.borrow().iter().map(|(id, _)| *id).collect())
     }
 
-    fn model(&self, _board: BoardId) -> Result<String, String> {
-        // XXX
-        Ok("system76/darp6".to_string())
+    fn model(&self, board: BoardId) -> Result<String, BackendError> {
+        // Keyboards of the power daemon are built into the laptop
+        self.board(board)?;
+        Ok(self.model.clone())
     }
 
-    fn version(&self, _board: BoardId) -> Result<String, String> {
-        Err("Unimplemented".to_string())
+    fn version(&self, _board: BoardId) -> Result<String, BackendError> {
+        Err(BackendError::Unsupported("version".to_string()))
+    }
+
+    fn capabilities(&self, board: BoardId) -> Result<BoardCapabilities, BackendError> {
+        self.board(board)?;
+        // Only brightness and color of the whole keyboard are exposed, as one
+        // layer, and changes to them are sent by `watch_signals`
+        Ok(BoardCapabilities {
+            num_layers: 1,
+            led_events: true,
+            ..BoardCapabilities::default()
+        })
     }
 
     fn keymap_get(
@@ -95,8 +284,8 @@ impl Daemon for DaemonS76Power {
         _layer: u8,
         _output: u8,
         _input: u8,
-    ) -> Result<u16, String> {
-        Err("Unimplemented".to_string())
+    ) -> Result<u16, BackendError> {
+        Err(BackendError::Unsupported("keymap_get".to_string()))
     }
 
     fn keymap_set(
@@ -106,78 +295,128 @@ impl Daemon for DaemonS76Power {
         _output: u8,
         _input: u8,
         _value: u16,
-    ) -> Result<(), String> {
-        Err("Unimplemented".to_string())
+    ) -> Result<(), BackendError> {
+        Err(BackendError::Unsupported("keymap_set".to_string()))
     }
 
-    fn matrix_get(&self, _board: BoardId) -> Result<Matrix, String> {
-        Err("Unimplemented".to_string())
+    fn matrix_get(&self, _board: BoardId) -> Result<Matrix, BackendError> {
+        Err(BackendError::Unsupported("matrix_get".to_string()))
     }
 
-    fn benchmark(&self, _board: BoardId) -> Result<Benchmark, String> {
-        Err("Unimplemented".to_string())
+    fn benchmark(&self, _board: BoardId) -> Result<Benchmark, BackendError> {
+        Err(BackendError::Unsupported("benchmark".to_string()))
     }
 
-    fn nelson(&self, _board: BoardId, _kind: NelsonKind) -> Result<Nelson, String> {
-        Err("Unimplemented".to_string())
+    fn nelson(&self, _board: BoardId, _kind: NelsonKind) -> Result<Nelson, BackendError> {
+        Err(BackendError::Unsupported("nelson".to_string()))
     }
 
-    fn color(&self, board: BoardId, index: u8) -> Result<(u8, u8, u8), String> {
+    fn color(&self, board: BoardId, index: u8) -> Result<(u8, u8, u8), BackendError> {
         if index != 0xFF {
-            return Err(format!("Can't set color index {}", index));
+            return Err(BackendError::InvalidIndex(index));
         }
-        let color = self.board(board)?.proxy.color().map_err(err_str)?;
+        let color = self.board(board)?.proxy.color().map_err(dbus_error)?;
         Ok(Rgb::parse(&color).map_or((0, 0, 0), |rgb| (rgb.r, rgb.g, rgb.b)))
     }
 
-    fn set_color(&self, board: BoardId, index: u8, color: (u8, u8, u8)) -> Result<(), String> {
+    fn set_color(
+        &self,
+        board: BoardId,
+        index: u8,
+        color: (u8, u8, u8),
+    ) -> Result<(), BackendError> {
         if index != 0xFF {
-            return Err(format!("Can't set color index {}", index));
+            return Err(BackendError::InvalidIndex(index));
         }
         self.board(board)?
             .proxy
             .set_color(&Rgb::new(color.0, color.1, color.2).to_string())
-            .map_err(err_str)
+            .map_err(dbus_error)
     }
 
-    fn max_brightness(&self, board: BoardId) -> Result<i32, String> {
-        Ok(self.board(board)?.proxy.max_brightness().map_err(err_str)?)
+    fn max_brightness(&self, board: BoardId) -> Result<i32, BackendError> {
+        Ok(self
+            .board(board)?
+            .proxy
+            .max_brightness()
+            .map_err(dbus_error)?)
     }
 
-    fn brightness(&self, board: BoardId, index: u8) -> Result<i32, String> {
+    fn brightness(&self, board: BoardId, index: u8) -> Result<i32, BackendError> {
         if index != 0xFF {
-            return Err(format!("Can't set brightness index {}", index));
+            return Err(BackendError::InvalidIndex(index));
         }
-        Ok(self.board(board)?.proxy.brightness().map_err(err_str)?)
+        Ok(self.board(board)?.proxy.brightness().map_err(dbus_error)?)
     }
 
-    fn set_brightness(&self, board: BoardId, index: u8, brightness: i32) -> Result<(), String> {
+    fn set_brightness(
+        &self,
+        board: BoardId,
+        index: u8,
+        brightness: i32,
+    ) -> Result<(), BackendError> {
         if index != 0xFF {
-            return Err(format!("Can't set brightness index {}", index));
+            return Err(BackendError::InvalidIndex(index));
         }
         self.board(board)?
             .proxy
             .set_brightness(brightness)
-            .map_err(err_str)
+            .map_err(dbus_error)
     }
 
-    fn mode(&self, _board: BoardId, _layer: u8) -> Result<(u8, u8), String> {
-        Err("Unimplemented".to_string())
+    fn mode(&self, _board: BoardId, _layer: u8) -> Result<(u8, u8), BackendError> {
+        Err(BackendError::Unsupported("mode".to_string()))
     }
 
-    fn set_mode(&self, _board: BoardId, _layer: u8, _mode: u8, _speed: u8) -> Result<(), String> {
-        Err("Unimplemented".to_string())
+    fn set_mode(
+        &self,
+        _board: BoardId,
+        _layer: u8,
+        _mode: u8,
+        _speed: u8,
+    ) -> Result<(), BackendError> {
+        Err(BackendError::Unsupported("set_mode".to_string()))
     }
 
-    fn led_save(&self, _board: BoardId) -> Result<(), String> {
-        Err("Unimplemented".to_string())
+    fn led_save(&self, _board: BoardId) -> Result<(), BackendError> {
+        Err(BackendError::Unsupported("led_save".to_string()))
     }
 
-    fn refresh(&self) -> Result<(), String> {
+    fn refresh(&self) -> Result<(), BackendError> {
+        let paths = keyboard_paths(&self.connection)?;
+        let mut boards = self.boards.borrow_mut();
+
+        boards.retain(|(id, _)| paths.iter().any(|path| BoardId::from_identity(path) == *id));
+
+        for path in paths {
+            let id = BoardId::from_identity(&path);
+            if !boards.iter().any(|(i, _)| *i == id) {
+                let keyboard = Keyboard::new(&self.connection, &path).map_err(dbus_error)?;
+                boards.push((id, keyboard));
+            }
+        }
+
         Ok(())
     }
 
-    fn exit(&self) -> Result<(), String> {
+    fn set_event_handler(&self, handler: EventHandler) {
+        *self.event_handler.lock().unwrap() = Some(handler);
+    }
+
+    fn exit(&self) -> Result<(), BackendError> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn model_from_dmi() {
+        assert_eq!(model_for_product_version("darp6\n"), "system76/darp6");
+        assert_eq!(model_for_product_version("GALP5"), "system76/galp5");
+        assert_eq!(model_for_product_version("unknown1"), GENERIC_MODEL);
+        assert!(layouts().contains(&GENERIC_MODEL));
+    }
+}
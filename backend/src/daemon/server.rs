@@ -2,6 +2,7 @@
 use ectool::AccessLpcLinux;
 use ectool::{Access, AccessHid, Ec};
 use hidapi::{DeviceInfo, HidApi};
+use serde::Serialize;
 use std::{
     cell::{Cell, RefCell, RefMut},
     collections::HashMap,
@@ -10,16 +11,83 @@ use std::{
     thread::sleep,
     time::Duration,
 };
-use uuid::Uuid;
+#[cfg(target_os = "linux")]
+use std::{ffi::CStr, fs, path::Path};
+
+use super::{
+    BackendError, BoardId, Daemon, DaemonCommand, DaemonHello, DaemonMessage, DaemonReply,
+};
+use crate::{Benchmark, BoardCapabilities, Matrix, Nelson, NelsonKind};
+
+/// Write `value` as a line of JSON
+pub(super) fn write_line<W: Write, T: Serialize>(write: &mut W, value: &T) -> io::Result<()> {
+    //TODO: what to do if we fail to serialize result?
+    let mut json = serde_json::to_string(value).expect("failed to serialize result");
+    json.push('\n');
+    write.write_all(json.as_bytes())?;
+    write.flush()
+}
+
+/// Parse a `DaemonRequest` line, returning its id and command
+///
+/// A command this build doesn't know about is returned as an error, so the
+/// client gets a reply rather than the daemon exiting.
+pub(super) fn parse_request(line: &str) -> Option<(u64, Result<DaemonCommand, BackendError>)> {
+    let mut request = match serde_json::from_str::<serde_json::Value>(line) {
+        Ok(request) => request,
+        Err(err) => {
+            error!("Failed to parse request: {}", err);
+            return None;
+        }
+    };
+    let id = match request.get("id").and_then(|id| id.as_u64()) {
+        Some(id) => id,
+        None => {
+            error!("Request without id: {}", line.trim_end());
+            return None;
+        }
+    };
+    let command = serde_json::from_value::<DaemonCommand>(request["command"].take())
+        .map_err(|err| BackendError::Protocol(format!("Unsupported command: {}", err)));
+    Some((id, command))
+}
+
+/// Identity of a USB HID board that is the same when it's reconnected
+fn hid_identity(info: &DeviceInfo) -> String {
+    let prefix = format!("hid:{:04x}:{:04x}", info.vendor_id(), info.product_id());
+
+    if let Some(serial) = info.serial_number() {
+        if !serial.is_empty() {
+            return format!("{}:serial:{}", prefix, serial);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(port) = usb_port(info.path()) {
+            return format!("{}:port:{}", prefix, port);
+        }
+    }
 
-use super::{err_str, BoardId, Daemon, DaemonCommand};
-use crate::{Benchmark, Matrix, Nelson, NelsonKind};
+    format!("{}:path:{}", prefix, info.path().to_string_lossy())
+}
+
+/// USB interface a hidraw device belongs to, such as `1-2:1.1`, which depends
+/// only on the port it is plugged into
+#[cfg(target_os = "linux")]
+fn usb_port(path: &CStr) -> Option<String> {
+    let path = path.to_str().ok()?;
+    let name = Path::new(path).file_name()?.to_str()?;
+    // Links to the HID device, which is numbered in order of connection
+    let device = fs::canonicalize(format!("/sys/class/hidraw/{}/device", name)).ok()?;
+    Some(device.parent()?.file_name()?.to_str()?.to_string())
+}
 
 pub struct DaemonServer<R: Read + Send + 'static, W: Write + Send + 'static> {
     hidapi: RefCell<Option<HidApi>>,
     running: Cell<bool>,
-    read: BufReader<R>,
-    write: W,
+    read: RefCell<BufReader<R>>,
+    write: RefCell<W>,
     boards: RefCell<HashMap<BoardId, (Ec<Box<dyn Access>>, Option<DeviceInfo>)>>,
     board_ids: RefCell<Vec<BoardId>>,
     nelson: RefCell<Option<Ec<AccessHid>>>,
@@ -31,6 +99,14 @@ impl DaemonServer<io::Stdin, io::Stdout> {
     }
 }
 
+#[cfg(target_os = "linux")]
+impl DaemonServer<io::Empty, io::Sink> {
+    /// Create server for use with `SocketServer`, which handles connections
+    pub fn new_socket() -> Result<Self, String> {
+        Self::new(io::empty(), io::sink())
+    }
+}
+
 impl<R: Read + Send + 'static, W: Write + Send + 'static> DaemonServer<R, W> {
     pub fn new(read: R, write: W) -> Result<Self, String> {
         let mut boards = HashMap::new();
@@ -41,7 +117,7 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> DaemonServer<R, W> {
             Ok(access) => match unsafe { Ec::new(access) } {
                 Ok(ec) => {
                     info!("Adding LPC EC");
-                    let id = BoardId(Uuid::new_v4().as_u128());
+                    let id = BoardId::from_identity("lpc");
                     boards.insert(id, (ec.into_dyn(), None));
                     board_ids.push(id);
                 }
@@ -66,14 +142,26 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> DaemonServer<R, W> {
         Ok(Self {
             hidapi: RefCell::new(hidapi),
             running: Cell::new(true),
-            read: BufReader::new(read),
-            write,
+            read: RefCell::new(BufReader::new(read)),
+            write: RefCell::new(write),
             boards: RefCell::new(boards),
             board_ids: RefCell::new(board_ids),
             nelson: RefCell::new(None),
         })
     }
 
+    /// Id for `identity`, which is unique even if two boards share an identity
+    fn unused_id(&self, identity: &str) -> BoardId {
+        let boards = self.boards.borrow();
+        let mut id = BoardId::from_identity(identity);
+        let mut i = 1;
+        while boards.contains_key(&id) {
+            id = BoardId::from_identity(&format!("{}#{}", identity, i));
+            i += 1;
+        }
+        id
+    }
+
     fn have_device(&self, info: &DeviceInfo) -> bool {
         for (_, i) in self.boards.borrow().values() {
             if let Some(i) = i {
@@ -87,63 +175,86 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> DaemonServer<R, W> {
         false
     }
 
-    pub fn run(mut self) -> io::Result<()> {
-        println!("Daemon started");
+    pub fn run(self) -> io::Result<()> {
+        self.serve(&mut *self.read.borrow_mut(), &mut *self.write.borrow_mut())
+    }
+
+    /// Handle commands from `read` until `exit` is called, or EOF
+    fn serve<R2: BufRead, W2: Write>(&self, read: &mut R2, write: &mut W2) -> io::Result<()> {
+        write_line(write, &DaemonHello::current())?;
 
         while self.running.get() {
             let mut command_json = String::new();
-            self.read.read_line(&mut command_json)?;
-
-            let command = serde_json::from_str::<DaemonCommand>(&command_json)
-                .expect("failed to deserialize command");
-            let response = self.dispatch_command_to_method(command);
+            if read.read_line(&mut command_json)? == 0 {
+                // Client closed its end
+                break;
+            }
 
-            //TODO: what to do if we fail to serialize result?
-            let mut result_json =
-                serde_json::to_string(&response).expect("failed to serialize result");
-            result_json.push('\n');
-            self.write.write_all(result_json.as_bytes())?;
+            let (id, command) = match parse_request(&command_json) {
+                Some(request) => request,
+                None => continue,
+            };
+            let response = command.and_then(|command| self.dispatch_command_to_method(command));
+            write_line(write, &DaemonMessage::Reply(DaemonReply { id, response }))?;
         }
 
         Ok(())
     }
 
-    fn board(&self, board: BoardId) -> Result<RefMut<Ec<Box<dyn Access>>>, String> {
+    fn board(&self, board: BoardId) -> Result<RefMut<Ec<Box<dyn Access>>>, BackendError> {
         let mut boards = self.boards.borrow_mut();
         if boards.get_mut(&board).is_some() {
             Ok(RefMut::map(boards, |x| &mut x.get_mut(&board).unwrap().0))
         } else {
-            Err("failed to find board".to_string())
+            Err(BackendError::Disconnected)
         }
     }
 }
 
 impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServer<R, W> {
-    fn boards(&self) -> Result<Vec<BoardId>, String> {
+    fn boards(&self) -> Result<Vec<BoardId>, BackendError> {
         Ok(self.board_ids.borrow().clone())
     }
 
-    fn model(&self, board: BoardId) -> Result<String, String> {
+    fn model(&self, board: BoardId) -> Result<String, BackendError> {
         let mut ec = self.board(board)?;
         let data_size = unsafe { ec.access().data_size() };
         let mut data = vec![0; data_size];
-        let len = unsafe { ec.board(&mut data).map_err(err_str)? };
-        let board = str::from_utf8(&data[..len]).map_err(err_str)?;
+        let len = unsafe { ec.board(&mut data)? };
+        let board =
+            str::from_utf8(&data[..len]).map_err(|err| BackendError::Protocol(err.to_string()))?;
         Ok(board.to_string())
     }
 
-    fn version(&self, board: BoardId) -> Result<String, String> {
+    fn version(&self, board: BoardId) -> Result<String, BackendError> {
         let mut ec = self.board(board)?;
         let data_size = unsafe { ec.access().data_size() };
         let mut data = vec![0; data_size];
-        let len = unsafe { ec.version(&mut data).map_err(err_str)? };
-        let version = str::from_utf8(&data[..len]).map_err(err_str)?;
+        let len = unsafe { ec.version(&mut data)? };
+        let version =
+            str::from_utf8(&data[..len]).map_err(|err| BackendError::Protocol(err.to_string()))?;
         Ok(version.to_string())
     }
 
-    fn keymap_get(&self, board: BoardId, layer: u8, output: u8, input: u8) -> Result<u16, String> {
+    fn capabilities(&self, board: BoardId) -> Result<BoardCapabilities, BackendError> {
+        let mut capabilities = BoardCapabilities::probe(self, board)?;
+        capabilities.nelson = self.nelson.borrow().is_some();
+        capabilities.benchmark = true;
+        Ok(capabilities)
+    }
+
+    fn keymap_get(
+        &self,
+        board: BoardId,
+        layer: u8,
+        output: u8,
+        input: u8,
+    ) -> Result<u16, BackendError> {
         let mut ec = self.board(board)?;
-        unsafe { ec.keymap_get(layer, output, input).map_err(err_str) }
+        unsafe {
+            ec.keymap_get(layer, output, input)
+                .map_err(BackendError::from)
+        }
     }
 
     fn keymap_set(
@@ -153,44 +264,47 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServe
         output: u8,
         input: u8,
         value: u16,
-    ) -> Result<(), String> {
+    ) -> Result<(), BackendError> {
         let mut ec = self.board(board)?;
-        unsafe { ec.keymap_set(layer, output, input, value).map_err(err_str) }
+        unsafe {
+            ec.keymap_set(layer, output, input, value)
+                .map_err(BackendError::from)
+        }
     }
 
-    fn matrix_get(&self, board: BoardId) -> Result<Matrix, String> {
+    fn matrix_get(&self, board: BoardId) -> Result<Matrix, BackendError> {
         let mut ec = self.board(board)?;
 
         let data_size = unsafe { ec.access().data_size() };
         let mut data = vec![0; data_size];
-        unsafe { ec.matrix_get(&mut data).map_err(err_str)? };
+        unsafe { ec.matrix_get(&mut data)? };
 
         let rows = data.remove(0) as usize;
         let cols = data.remove(0) as usize;
         Ok(Matrix::new(rows, cols, data.into_boxed_slice()))
     }
 
-    fn benchmark(&self, _board: BoardId) -> Result<Benchmark, String> {
-        Benchmark::new().map_err(err_str)
+    fn benchmark(&self, _board: BoardId) -> Result<Benchmark, BackendError> {
+        Ok(Benchmark::new()?)
     }
 
-    fn nelson(&self, board: BoardId, kind: NelsonKind) -> Result<Nelson, String> {
+    fn nelson(&self, board: BoardId, kind: NelsonKind) -> Result<Nelson, BackendError> {
         if let Some(nelson) = &mut *self.nelson.borrow_mut() {
             let delay_ms = 300;
             info!("Nelson delay is {} ms", delay_ms);
             let delay = Duration::from_millis(delay_ms);
 
             // Check if Nelson is already closed
-            if unsafe { nelson.led_get_value(0).map_err(err_str)?.0 > 0 } {
+            if unsafe { nelson.led_get_value(0)?.0 > 0 } {
                 info!("Open Nelson");
-                unsafe { nelson.led_set_value(0, 0).map_err(err_str)? };
+                unsafe { nelson.led_set_value(0, 0)? };
 
                 info!("Sleep");
                 sleep(delay);
             }
 
             info!("Close Nelson");
-            unsafe { nelson.led_set_value(0, 1).map_err(err_str)? };
+            unsafe { nelson.led_set_value(0, 1)? };
 
             info!("Sleep");
             sleep(delay);
@@ -213,7 +327,7 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServe
             }
 
             info!("Open Nelson");
-            unsafe { nelson.led_set_value(0, 0).map_err(err_str)? };
+            unsafe { nelson.led_set_value(0, 0)? };
 
             info!("Sleep");
             sleep(delay);
@@ -227,24 +341,29 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServe
                 sticking,
             })
         } else {
-            Err(format!("failed to find Nelson"))
+            Err(BackendError::Other("Failed to find Nelson".to_string()))
         }
     }
 
-    fn color(&self, board: BoardId, index: u8) -> Result<(u8, u8, u8), String> {
+    fn color(&self, board: BoardId, index: u8) -> Result<(u8, u8, u8), BackendError> {
         let mut ec = self.board(board)?;
-        unsafe { ec.led_get_color(index) }.map_err(err_str)
+        unsafe { ec.led_get_color(index) }.map_err(BackendError::from)
     }
 
-    fn set_color(&self, board: BoardId, index: u8, color: (u8, u8, u8)) -> Result<(), String> {
+    fn set_color(
+        &self,
+        board: BoardId,
+        index: u8,
+        color: (u8, u8, u8),
+    ) -> Result<(), BackendError> {
         let mut ec = self.board(board)?;
         unsafe {
             ec.led_set_color(index, color.0, color.1, color.2)
-                .map_err(err_str)
+                .map_err(BackendError::from)
         }
     }
 
-    fn max_brightness(&self, board: BoardId) -> Result<i32, String> {
+    fn max_brightness(&self, board: BoardId) -> Result<i32, BackendError> {
         let mut ec = self.board(board)?;
         let index = if unsafe { ec.access().is::<AccessHid>() } {
             0xf0
@@ -253,35 +372,50 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServe
         };
         unsafe { ec.led_get_value(index) }
             .map(|x| x.1 as i32)
-            .map_err(err_str)
+            .map_err(BackendError::from)
     }
 
-    fn brightness(&self, board: BoardId, index: u8) -> Result<i32, String> {
+    fn brightness(&self, board: BoardId, index: u8) -> Result<i32, BackendError> {
         let mut ec = self.board(board)?;
-        unsafe { ec.led_get_value(index).map(|x| x.0 as i32).map_err(err_str) }
+        unsafe {
+            ec.led_get_value(index)
+                .map(|x| x.0 as i32)
+                .map_err(BackendError::from)
+        }
     }
 
-    fn set_brightness(&self, board: BoardId, index: u8, brightness: i32) -> Result<(), String> {
+    fn set_brightness(
+        &self,
+        board: BoardId,
+        index: u8,
+        brightness: i32,
+    ) -> Result<(), BackendError> {
         let mut ec = self.board(board)?;
-        unsafe { ec.led_set_value(index, brightness as u8).map_err(err_str) }
+        unsafe {
+            ec.led_set_value(index, brightness as u8)
+                .map_err(BackendError::from)
+        }
     }
 
-    fn mode(&self, board: BoardId, layer: u8) -> Result<(u8, u8), String> {
+    fn mode(&self, board: BoardId, layer: u8) -> Result<(u8, u8), BackendError> {
         let mut ec = self.board(board)?;
-        unsafe { ec.led_get_mode(layer).map_err(err_str) }
+        unsafe { ec.led_get_mode(layer).map_err(BackendError::from) }
     }
 
-    fn set_mode(&self, board: BoardId, layer: u8, mode: u8, speed: u8) -> Result<(), String> {
+    fn set_mode(&self, board: BoardId, layer: u8, mode: u8, speed: u8) -> Result<(), BackendError> {
         let mut ec = self.board(board)?;
-        unsafe { ec.led_set_mode(layer, mode, speed).map_err(err_str) }
+        unsafe {
+            ec.led_set_mode(layer, mode, speed)
+                .map_err(BackendError::from)
+        }
     }
 
-    fn led_save(&self, board: BoardId) -> Result<(), String> {
+    fn led_save(&self, board: BoardId) -> Result<(), BackendError> {
         let mut ec = self.board(board)?;
-        unsafe { ec.led_save().map_err(err_str) }
+        unsafe { ec.led_save().map_err(BackendError::from) }
     }
 
-    fn refresh(&self) -> Result<(), String> {
+    fn refresh(&self) -> Result<(), BackendError> {
         if let Some(api) = &mut *self.hidapi.borrow_mut() {
             // Remove USB boards that are no longer attached
             {
@@ -313,7 +447,7 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServe
                                 Ok(access) => match unsafe { Ec::new(access) } {
                                     Ok(ec) => {
                                         info!("Adding USB HID EC at {:?}", info.path());
-                                        let id = BoardId(Uuid::new_v4().as_u128());
+                                        let id = self.unused_id(&hid_identity(&info));
                                         self.boards
                                             .borrow_mut()
                                             .insert(id, (ec.into_dyn(), Some(info.clone())));
@@ -374,7 +508,7 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServe
         Ok(())
     }
 
-    fn exit(&self) -> Result<(), String> {
+    fn exit(&self) -> Result<(), BackendError> {
         self.running.set(false);
         Ok(())
     }
@@ -0,0 +1,484 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{self, Read, Write},
+    mem,
+    os::unix::{
+        fs::{MetadataExt, PermissionsExt},
+        io::{AsRawFd, FromRawFd},
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+};
+
+use serde::Serialize;
+
+use super::server::{parse_request, write_line};
+use super::{
+    BackendError, BoardId, Daemon, DaemonCommand, DaemonEvent, DaemonHello, DaemonMessage,
+    DaemonReply, DaemonResponse,
+};
+
+/// Path of the socket the system service listens on
+pub const SOCKET_PATH: &str = "/run/system76-keyboard-configurator.sock";
+
+/// First file descriptor passed with systemd socket activation
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Most output queued for a client before it's assumed to have stopped
+/// reading, and is disconnected
+const MAX_OUTPUT: usize = 1024 * 1024;
+
+/// Get socket passed by systemd, or bind `SOCKET_PATH`
+pub fn listen() -> io::Result<UnixListener> {
+    let pid = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|x| x.parse::<u32>().ok());
+    let fds = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|x| x.parse::<i32>().ok());
+    if pid == Some(std::process::id()) && fds.unwrap_or(0) >= 1 {
+        info!("Using socket from systemd");
+        return Ok(unsafe { UnixListener::from_raw_fd(SD_LISTEN_FDS_START) });
+    }
+
+    info!("Listening on {}", SOCKET_PATH);
+    let path = Path::new(SOCKET_PATH);
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    // Access is checked with `peer_allowed`
+    fs::set_permissions(path, fs::Permissions::from_mode(0o666))?;
+    Ok(listener)
+}
+
+fn peer_cred(stream: &UnixStream) -> io::Result<libc::ucred> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let res = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if res == 0 {
+        Ok(cred)
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Supplementary groups of a process
+fn process_groups(pid: libc::pid_t) -> io::Result<Vec<libc::gid_t>> {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid))?;
+    Ok(status
+        .lines()
+        .find(|line| line.starts_with("Groups:"))
+        .map_or(Vec::new(), |line| {
+            line["Groups:".len()..]
+                .split_whitespace()
+                .filter_map(|gid| gid.parse().ok())
+                .collect()
+        }))
+}
+
+/// Check if the process connected to `stream` may use the daemon
+///
+/// Root, the daemon's own user, and members of the group owning the socket
+/// file (as configured by `SocketGroup=` in the systemd unit) are allowed.
+pub fn peer_allowed(stream: &UnixStream) -> io::Result<bool> {
+    let cred = peer_cred(stream)?;
+    if cred.uid == 0 || cred.uid == unsafe { libc::geteuid() } {
+        return Ok(true);
+    }
+
+    let gid = fs::metadata(SOCKET_PATH)?.gid();
+    if gid == 0 {
+        // Socket wasn't given to a group, so only root may connect
+        return Ok(false);
+    }
+    Ok(cred.gid == gid || process_groups(cred.pid)?.contains(&gid))
+}
+
+struct Connection {
+    id: u64,
+    /// Non-blocking, so a client that stops reading can't stall the server
+    stream: UnixStream,
+    /// Data read that doesn't yet form a complete line
+    buffer: Vec<u8>,
+    /// Data not yet written, since the socket would block
+    output: Vec<u8>,
+    /// Set when a write fails, so the connection is closed by `run`
+    closed: bool,
+}
+
+impl Connection {
+    /// Queue `value` as a line, and write as much output as possible. Marks
+    /// the connection closed if that fails.
+    fn send<T: Serialize>(&mut self, value: &T) {
+        // Can't fail when writing to a `Vec`
+        let _ = write_line(&mut self.output, value);
+        self.flush();
+    }
+
+    /// Write queued output until the socket would block
+    fn flush(&mut self) {
+        while !self.output.is_empty() {
+            match (&self.stream).write(&self.output) {
+                Ok(0) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(count) => {
+                    self.output.drain(..count);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    error!("Failed to write to client {}: {}", self.id, err);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+
+        if self.output.len() > MAX_OUTPUT {
+            error!("Client {} is not reading replies", self.id);
+            self.closed = true;
+        }
+    }
+}
+
+/// Serves a `Daemon` to any number of clients connected to a Unix socket
+///
+/// Connections are handled in a single thread, with `poll`, since the
+/// daemon's boards can't be shared between threads.
+pub struct SocketServer<D: Daemon> {
+    daemon: D,
+    listener: UnixListener,
+    connections: Vec<Connection>,
+    next_id: u64,
+    /// Connection holding the advisory lock on each board
+    locks: HashMap<BoardId, u64>,
+}
+
+impl<D: Daemon> SocketServer<D> {
+    pub fn new(daemon: D, listener: UnixListener) -> Self {
+        Self {
+            daemon,
+            listener,
+            connections: Vec::new(),
+            next_id: 0,
+            locks: HashMap::new(),
+        }
+    }
+
+    pub fn run(mut self) -> io::Result<()> {
+        loop {
+            let listener_fd = libc::pollfd {
+                fd: self.listener.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let mut fds = Some(listener_fd)
+                .into_iter()
+                .chain(self.connections.iter().map(|i| libc::pollfd {
+                    fd: i.stream.as_raw_fd(),
+                    // Wait to write queued output, if there is any
+                    events: if i.output.is_empty() {
+                        libc::POLLIN
+                    } else {
+                        libc::POLLIN | libc::POLLOUT
+                    },
+                    revents: 0,
+                }))
+                .collect::<Vec<_>>();
+            if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+
+            for index in 0..self.connections.len() {
+                let revents = fds[index + 1].revents;
+                if revents & libc::POLLOUT != 0 {
+                    self.connections[index].flush();
+                }
+                if revents & !libc::POLLOUT != 0
+                    && !self.connections[index].closed
+                    && !self.read_connection(index)
+                {
+                    self.connections[index].closed = true;
+                }
+            }
+
+            // In reverse, so removing a connection doesn't change later indices.
+            // A broadcast may have closed a connection other than the one read.
+            for index in (0..self.connections.len()).rev() {
+                if self.connections[index].closed {
+                    self.close(index);
+                }
+            }
+
+            if fds[0].revents != 0 {
+                self.accept();
+            }
+        }
+    }
+
+    fn accept(&mut self) {
+        let stream = match self.listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                error!("Failed to accept connection: {}", err);
+                return;
+            }
+        };
+
+        match peer_allowed(&stream) {
+            Ok(true) => {}
+            Ok(false) => {
+                info!("Rejected connection from unauthorized peer");
+                return;
+            }
+            Err(err) => {
+                error!("Failed to check peer credentials: {}", err);
+                return;
+            }
+        }
+
+        if let Err(err) = stream.set_nonblocking(true) {
+            error!("Failed to make client socket non-blocking: {}", err);
+            return;
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        info!("Client {} connected", id);
+        let mut connection = Connection {
+            id,
+            stream,
+            buffer: Vec::new(),
+            output: Vec::new(),
+            closed: false,
+        };
+        connection.send(&DaemonHello::current());
+        self.connections.push(connection);
+    }
+
+    fn close(&mut self, index: usize) {
+        let connection = self.connections.remove(index);
+        info!("Client {} disconnected", connection.id);
+        self.locks.retain(|_, holder| *holder != connection.id);
+    }
+
+    /// Handle available data from a connection, returning `false` if it
+    /// should be closed
+    fn read_connection(&mut self, index: usize) -> bool {
+        let mut data = [0; 4096];
+        let count = match (&self.connections[index].stream).read(&mut data) {
+            Ok(0) => return false,
+            Ok(count) => count,
+            Err(err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::Interrupted =>
+            {
+                return true
+            }
+            Err(err) => {
+                error!("Failed to read from client: {}", err);
+                return false;
+            }
+        };
+        self.connections[index]
+            .buffer
+            .extend_from_slice(&data[..count]);
+
+        while let Some(end) = self.connections[index]
+            .buffer
+            .iter()
+            .position(|b| *b == b'\n')
+        {
+            let line = self.connections[index]
+                .buffer
+                .drain(..=end)
+                .collect::<Vec<_>>();
+            if !self.handle_line(index, &String::from_utf8_lossy(&line)) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    fn handle_line(&mut self, index: usize, line: &str) -> bool {
+        let (id, command) = match parse_request(line) {
+            Some(request) => request,
+            None => return true,
+        };
+
+        let connection_id = self.connections[index].id;
+        let mut keep_open = true;
+        let response = command.and_then(|command| match command {
+            DaemonCommand::lock { board } => {
+                self.lock(connection_id, board).map(DaemonResponse::lock)
+            }
+            DaemonCommand::unlock { board } => self
+                .unlock(connection_id, board)
+                .map(DaemonResponse::unlock),
+            // Only close this connection, rather than stopping the server
+            DaemonCommand::exit {} => {
+                keep_open = false;
+                Ok(DaemonResponse::exit(()))
+            }
+            command => self.dispatch(connection_id, command),
+        });
+
+        let connection = &mut self.connections[index];
+        connection.send(&DaemonMessage::Reply(DaemonReply { id, response }));
+        keep_open && !connection.closed
+    }
+
+    fn dispatch(
+        &mut self,
+        connection_id: u64,
+        command: DaemonCommand,
+    ) -> Result<DaemonResponse, BackendError> {
+        if let Some(board) = command.board_written() {
+            if self
+                .locks
+                .get(&board)
+                .map_or(false, |i| *i != connection_id)
+            {
+                return Err(BackendError::Locked);
+            }
+        }
+
+        let is_refresh = matches!(command, DaemonCommand::refresh {});
+        let old_boards = if is_refresh {
+            self.daemon.boards().unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+
+        let response = self.daemon.dispatch_command_to_method(command);
+
+        if is_refresh {
+            let new_boards = self.daemon.boards().unwrap_or_default();
+            for board in &old_boards {
+                if !new_boards.contains(board) {
+                    self.locks.remove(board);
+                    self.broadcast(connection_id, DaemonEvent::BoardRemoved(*board));
+                }
+            }
+            for board in &new_boards {
+                if !old_boards.contains(board) {
+                    self.broadcast(connection_id, DaemonEvent::BoardAdded(*board));
+                }
+            }
+        }
+
+        response
+    }
+
+    /// Send event to every client except the one that caused it, which sees
+    /// the change in the reply to its own request
+    fn broadcast(&mut self, connection_id: u64, event: DaemonEvent) {
+        let message = DaemonMessage::Event(event);
+        for connection in &mut self.connections {
+            if connection.id != connection_id && !connection.closed {
+                connection.send(&message);
+            }
+        }
+    }
+
+    fn lock(&mut self, connection_id: u64, board: BoardId) -> Result<(), BackendError> {
+        match self.locks.get(&board) {
+            Some(holder) if *holder != connection_id => Err(BackendError::Locked),
+            _ => {
+                self.locks.insert(board, connection_id);
+                Ok(())
+            }
+        }
+    }
+
+    fn unlock(&mut self, connection_id: u64, board: BoardId) -> Result<(), BackendError> {
+        match self.locks.get(&board) {
+            Some(holder) if *holder != connection_id => Err(BackendError::Locked),
+            _ => {
+                self.locks.remove(&board);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DaemonDummy, DaemonRequest};
+    use std::{
+        io::{BufRead, BufReader},
+        process, thread,
+        time::Duration,
+    };
+
+    fn request(id: u64) -> Vec<u8> {
+        let request = DaemonRequest {
+            id,
+            command: DaemonCommand::boards {},
+        };
+        let mut line = Vec::new();
+        write_line(&mut line, &request).unwrap();
+        line
+    }
+
+    #[test]
+    fn client_not_reading() {
+        let path = env::temp_dir().join(format!("configurator-test-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let daemon = DaemonDummy::new(vec!["system76/launch_1".to_string()]);
+        thread::spawn(move || SocketServer::new(daemon, listener).run());
+
+        // Send requests without ever reading replies, until disconnected
+        let mut stuck = UnixStream::connect(&path).unwrap();
+        stuck
+            .set_write_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        let err = (0..)
+            .find_map(|id| stuck.write_all(&request(id)).err())
+            .unwrap();
+        assert!(
+            err.kind() == io::ErrorKind::BrokenPipe || err.kind() == io::ErrorKind::ConnectionReset,
+            "{:?}",
+            err
+        );
+
+        // Other clients are still served
+        let client = UnixStream::connect(&path).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        (&client).write_all(&request(0)).unwrap();
+        let mut lines = BufReader::new(&client).lines();
+        serde_json::from_str::<DaemonHello>(&lines.next().unwrap().unwrap()).unwrap();
+        let reply = lines.next().unwrap().unwrap();
+        match serde_json::from_str::<DaemonMessage>(&reply).unwrap() {
+            DaemonMessage::Reply(reply) => assert_eq!(reply.id, 0),
+            DaemonMessage::Event(_) => panic!("expected reply"),
+        }
+
+        let _ = fs::remove_file(&path);
+    }
+}
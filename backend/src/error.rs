@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt, io};
+
+use crate::fl;
+
+/// Error from a board, or from the daemon communicating with it
+///
+/// This is sent from the daemon to clients in `DaemonReply`, so callers can
+/// tell why a command failed, not just that it did.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "t", content = "c")]
+pub enum BackendError {
+    /// Board is not connected, such as because it was unplugged
+    Disconnected,
+    /// Board is locked by another client of a shared daemon
+    Locked,
+    /// Command, named in the error, isn't supported by the daemon or board
+    Unsupported(String),
+    /// LED index isn't valid for the board
+    InvalidIndex(u8),
+    /// Argument, such as a scancode name, isn't valid for the board
+    InvalidArgument(String),
+    /// Board did not respond in time
+    Timeout,
+    /// Board returned an error or unexpected data
+    Protocol(String),
+    /// Failed to communicate with the board or daemon
+    Io(String),
+    Other(String),
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Disconnected => write!(f, "{}", fl!("no-board")),
+            Self::Locked => write!(f, "Board is locked by another client"),
+            Self::Unsupported(command) => write!(f, "'{}' is not supported", command),
+            Self::InvalidIndex(index) => write!(f, "Invalid LED index {:#04x}", index),
+            Self::InvalidArgument(err) => write!(f, "{}", err),
+            Self::Timeout => write!(f, "Timed out waiting for board"),
+            Self::Protocol(err) => write!(f, "Protocol error: {}", err),
+            Self::Io(err) => write!(f, "I/O error: {}", err),
+            Self::Other(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for BackendError {}
+
+impl From<io::Error> for BackendError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::TimedOut => Self::Timeout,
+            _ => Self::Io(err.to_string()),
+        }
+    }
+}
+
+impl From<ectool::Error> for BackendError {
+    fn from(err: ectool::Error) -> Self {
+        match err {
+            ectool::Error::Timeout => Self::Timeout,
+            ectool::Error::Protocol(code) => Self::Protocol(format!("EC returned {}", code)),
+            ectool::Error::Io(err) => err.into(),
+            ectool::Error::Hid(err) => Self::Io(err.to_string()),
+            err => Self::Protocol(format!("{:?}", err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backend_error_serde() {
+        for err in &[
+            BackendError::Disconnected,
+            BackendError::InvalidIndex(0xf5),
+            BackendError::Unsupported("nelson".to_string()),
+        ] {
+            let json = serde_json::to_string(err).unwrap();
+            assert_eq!(&serde_json::from_str::<BackendError>(&json).unwrap(), err);
+        }
+    }
+
+    #[test]
+    fn backend_error_io_timeout() {
+        let err = io::Error::new(io::ErrorKind::TimedOut, "timed out");
+        assert_eq!(BackendError::from(err), BackendError::Timeout);
+    }
+}
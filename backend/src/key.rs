@@ -1,7 +1,7 @@
 use glib::prelude::*;
 use std::cell::Cell;
 
-use crate::{Board, Daemon, Hs, PhysicalLayoutKey, Rect, Rgb};
+use crate::{BackendError, Board, Daemon, Hs, KeyLegend, PhysicalLayoutKey, Rect, Rgb, Scancode};
 
 #[derive(Debug)]
 pub struct Key {
@@ -12,8 +12,15 @@ pub struct Key {
     pub logical_name: String,
     /// Physical position and size
     pub physical: Rect,
+    /// Second physical rectangle, for keys that aren't rectangular
+    pub physical2: Option<Rect>,
+    /// Rotation in degrees clockwise, around `rotation_origin`
+    pub rotation: f64,
+    pub rotation_origin: (f64, f64),
     /// Physical key name (what is printed on the keycap)
     pub physical_name: String,
+    /// Legends printed on the keycap, with their positions
+    pub legends: Vec<KeyLegend>,
     /// Electrical mapping (output, input)
     pub electrical: (u8, u8),
     /// Electrical name (output, input)
@@ -36,6 +43,7 @@ impl Key {
         daemon: &dyn Daemon,
         board: &Board,
         physical_key: &PhysicalLayoutKey,
+        scancodes: &[u16],
     ) -> Self {
         let logical = physical_key.logical;
         let logical_name = physical_key.logical_name();
@@ -48,12 +56,7 @@ impl Key {
         debug!("  Logical: {:?}", logical);
         debug!("  Logical Name: {}", logical_name);
 
-        let electrical = *board
-            .layout()
-            .layout
-            .get(logical_name.as_str())
-            //.expect("Failed to find electrical mapping");
-            .unwrap_or(&(0, 0));
+        let electrical = board.layout().electrical(&logical_name);
         debug!("  Electrical: {:?}", electrical);
 
         let leds = board
@@ -71,28 +74,22 @@ impl Key {
             led_name.push_str(&led.to_string());
         }
 
-        let mut scancodes = Vec::new();
-        for layer in 0..board.layout().meta.num_layers {
-            debug!("  Layer {}", layer);
-            let scancode = match daemon.keymap_get(board.board(), layer, electrical.0, electrical.1)
-            {
-                Ok(value) => value,
-                Err(err) => {
-                    error!("Failed to read scancode: {:?}", err);
-                    0
-                }
-            };
-            debug!("    Scancode: {:04X}", scancode);
-            debug!(
-                "    Scancode Name: {:?}",
-                board.layout().scancode_to_name(scancode)
-            );
-
-            scancodes.push(Cell::new(scancode));
-        }
+        let scancodes = scancodes
+            .iter()
+            .enumerate()
+            .map(|(layer, scancode)| {
+                debug!("  Layer {}", layer);
+                debug!("    Scancode: {:04X}", scancode);
+                debug!(
+                    "    Scancode Name: {:?}",
+                    board.layout().scancode_to_name(*scancode)
+                );
+                Cell::new(*scancode)
+            })
+            .collect();
 
         let mut led_color = None;
-        if board.layout().meta.has_mode && leds.len() > 0 {
+        if board.capabilities().per_key_led && !leds.is_empty() {
             match daemon.color(board.board(), leds[0]) {
                 Ok((0, 0, 0)) => {}
                 Ok((r, g, b)) => led_color = Some(Rgb::new(r, g, b).to_hs_lossy()),
@@ -105,7 +102,11 @@ impl Key {
             logical,
             logical_name,
             physical,
+            physical2: physical_key.physical2,
+            rotation: physical_key.rotation,
+            rotation_origin: physical_key.rotation_origin,
             physical_name,
+            legends: physical_key.legends.clone(),
             electrical,
             electrical_name: format!("{}, {}", electrical.0, electrical.1),
             leds,
@@ -129,7 +130,7 @@ impl Key {
         self.led_color.get()
     }
 
-    pub async fn set_color(&self, color: Option<Hs>) -> Result<(), String> {
+    pub async fn set_color(&self, color: Option<Hs>) -> Result<(), BackendError> {
         let board = self.board();
         let Rgb { r, g, b } = color.map_or(Rgb::new(0, 0, 0), Hs::to_rgb);
         for index in &self.leds {
@@ -153,12 +154,27 @@ impl Key {
         Some((scancode, scancode_name))
     }
 
-    pub async fn set_scancode(&self, layer: usize, scancode_name: &str) -> Result<(), String> {
+    /// Scancode on `layer`, if it's one the layout has
+    pub fn scancode(&self, layer: usize) -> Option<Scancode> {
+        let scancode = self.scancodes.get(layer)?.get();
+        self.board().layout().scancode_by_value(scancode).cloned()
+    }
+
+    pub async fn set_scancode(
+        &self,
+        layer: usize,
+        scancode_name: &str,
+    ) -> Result<(), BackendError> {
         let board = self.board();
         let scancode = board
             .layout()
             .scancode_from_name(scancode_name)
-            .ok_or_else(|| format!("Unable to find scancode '{}'", scancode_name))?;
+            .ok_or_else(|| {
+                BackendError::InvalidArgument(format!(
+                    "Unable to find scancode '{}'",
+                    scancode_name
+                ))
+            })?;
         board
             .thread_client()
             .keymap_set(
@@ -173,4 +189,9 @@ impl Key {
         board.emit_by_name("keymap-changed", &[]).unwrap();
         Ok(())
     }
+
+    /// Update cached scancode, after it was set on the board
+    pub(crate) fn scancode_changed(&self, layer: usize, scancode: u16) {
+        self.scancodes[layer].set(scancode);
+    }
 }
@@ -1,8 +1,10 @@
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::collections::HashMap;
+use serde_json::{Map, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
 use std::io::{Read, Write};
 
-use crate::Hs;
+use crate::{Hs, Mode};
 
 mod hs_serde {
     use super::*;
@@ -50,10 +52,56 @@ pub struct KeyMapLayer {
     pub color: Hs,
 }
 
+/// Upgrades from each version of the keymap format to the next, starting
+/// with version 1. A new version must add its upgrade here.
+const MIGRATIONS: &[fn(&mut Map<String, Value>)] = &[migrate_v1_to_v2];
+
+/// Version 2 adds metadata, which is unknown for older keymaps
+fn migrate_v1_to_v2(keymap: &mut Map<String, Value>) {
+    for field in &["name", "description", "created", "firmware_version"] {
+        keymap.insert(field.to_string(), Value::Null);
+    }
+}
+
+/// Upgrade a keymap of any supported version to `KeyMap::VERSION`
+fn migrate(mut value: Value) -> Result<Value, String> {
+    let keymap = value
+        .as_object_mut()
+        .ok_or_else(|| "keymap is not an object".to_string())?;
+    let version = keymap
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| "keymap has no version".to_string())?;
+    if version > u64::from(KeyMap::VERSION) {
+        return Err(format!(
+            "keymap version {} is from a newer Configurator version",
+            version
+        ));
+    } else if version == 0 {
+        return Err("keymap version 0 is not valid".to_string());
+    }
+
+    for migration in &MIGRATIONS[version as usize - 1..] {
+        migration(keymap);
+    }
+    keymap.insert("version".to_string(), Value::from(KeyMap::VERSION));
+    Ok(value)
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct KeyMap {
     pub model: String,
+    /// Layout variant of the board, such as `iso`, or `None` for its base layout
+    #[serde(default)]
+    pub variant: Option<String>,
     pub version: u8,
+    /// Name given by the user
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /// Time of export, in seconds since the Unix epoch
+    pub created: Option<u64>,
+    /// Firmware version of the board the keymap was exported from
+    pub firmware_version: Option<String>,
     pub map: HashMap<String, Vec<String>>,
     #[serde(with = "hs_map_serde")]
     pub key_leds: HashMap<String, Option<Hs>>,
@@ -61,14 +109,23 @@ pub struct KeyMap {
 }
 
 impl KeyMap {
+    /// Version of the format written, which older keymaps are migrated to
+    pub const VERSION: u8 = MIGRATIONS.len() as u8 + 1;
+
+    /// Parse layout from json value, migrating it from an older version
+    pub fn from_value(value: Value) -> serde_json::Result<Self> {
+        let value = migrate(value).map_err(serde::de::Error::custom)?;
+        serde_json::from_value(value)
+    }
+
     /// Parse layout from json file
     pub fn from_reader<R: Read>(rdr: R) -> serde_json::Result<Self> {
-        serde_json::from_reader(rdr)
+        Self::from_value(serde_json::from_reader(rdr)?)
     }
 
     /// Parse layout from json string
     pub fn from_str(s: &str) -> serde_json::Result<Self> {
-        serde_json::from_str(s)
+        Self::from_value(serde_json::from_str(s)?)
     }
 
     /// Write layout to json file, pretty printed
@@ -80,4 +137,306 @@ impl KeyMap {
     pub fn to_string_pretty(&self) -> String {
         serde_json::to_string_pretty(self).unwrap()
     }
+
+    /// Changes needed to go from `self` to `other`
+    pub fn diff(&self, other: &KeyMap) -> KeyMapDiff {
+        let mut diff = KeyMapDiff {
+            model: changed(self.model.clone(), other.model.clone()),
+            variant: changed(self.variant.clone(), other.variant.clone()),
+            num_layers: changed(self.layers.len(), other.layers.len()),
+            ..KeyMapDiff::default()
+        };
+
+        let keys = self
+            .map
+            .keys()
+            .chain(other.map.keys())
+            .collect::<BTreeSet<_>>();
+        for key in keys {
+            let old = self.map.get(key).map_or(&[][..], |x| x.as_slice());
+            let new = other.map.get(key).map_or(&[][..], |x| x.as_slice());
+            for layer in 0..old.len().max(new.len()) {
+                let old = old.get(layer).cloned();
+                let new = new.get(layer).cloned();
+                if old != new {
+                    diff.scancodes.push(ScancodeChange {
+                        key: key.clone(),
+                        layer,
+                        old,
+                        new,
+                    });
+                }
+            }
+        }
+
+        let keys = self
+            .key_leds
+            .keys()
+            .chain(other.key_leds.keys())
+            .collect::<BTreeSet<_>>();
+        for key in keys {
+            let old = self.key_leds.get(key).cloned();
+            let new = other.key_leds.get(key).cloned();
+            if old != new {
+                diff.key_leds.push(KeyLedChange {
+                    key: key.clone(),
+                    old,
+                    new,
+                });
+            }
+        }
+
+        for (layer, (old, new)) in self.layers.iter().zip(other.layers.iter()).enumerate() {
+            let change = LayerChange {
+                layer,
+                mode: changed(old.mode, new.mode),
+                brightness: changed(old.brightness, new.brightness),
+                color: changed(old.color, new.color),
+            };
+            if change.mode.is_some() || change.brightness.is_some() || change.color.is_some() {
+                diff.layers.push(change);
+            }
+        }
+
+        diff
+    }
+}
+
+fn changed<T: PartialEq>(old: T, new: T) -> Option<(T, T)> {
+    if old != new {
+        Some((old, new))
+    } else {
+        None
+    }
+}
+
+/// Scancode of a key on one layer, with `None` if it isn't in the keymap
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScancodeChange {
+    pub key: String,
+    pub layer: usize,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// LED color of a key, with `None` if it isn't in the keymap
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyLedChange {
+    pub key: String,
+    pub old: Option<Option<Hs>>,
+    pub new: Option<Option<Hs>>,
+}
+
+/// Settings of a `KeyMapLayer`, with `None` for those that are unchanged
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerChange {
+    pub layer: usize,
+    pub mode: Option<(Option<(u8, u8)>, Option<(u8, u8)>)>,
+    pub brightness: Option<(i32, i32)>,
+    pub color: Option<(Hs, Hs)>,
+}
+
+/// Differences between two `KeyMap`s, from `KeyMap::diff`
+///
+/// Layers are numbered from 0, but displayed numbered from 1.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeyMapDiff {
+    pub model: Option<(String, String)>,
+    /// Layout variant, with `None` for the base layout
+    pub variant: Option<(Option<String>, Option<String>)>,
+    /// Number of layers, if they differ. Layers only in one keymap aren't in
+    /// `layers`.
+    pub num_layers: Option<(usize, usize)>,
+    pub scancodes: Vec<ScancodeChange>,
+    pub key_leds: Vec<KeyLedChange>,
+    pub layers: Vec<LayerChange>,
+}
+
+impl KeyMapDiff {
+    pub fn is_empty(&self) -> bool {
+        self.model.is_none()
+            && self.variant.is_none()
+            && self.num_layers.is_none()
+            && self.scancodes.is_empty()
+            && self.key_leds.is_empty()
+            && self.layers.is_empty()
+    }
+}
+
+fn fmt_scancode(scancode: &Option<String>) -> &str {
+    match scancode {
+        Some(name) if name.is_empty() => "NONE",
+        Some(name) => name,
+        None => "(missing)",
+    }
+}
+
+fn fmt_variant(variant: &Option<String>) -> &str {
+    variant.as_deref().unwrap_or("default")
+}
+
+fn fmt_color(color: Option<Hs>) -> String {
+    match color {
+        Some(hs) => {
+            let (h, s) = hs.to_ints();
+            format!("hue {}, saturation {}", h, s)
+        }
+        None => "off".to_string(),
+    }
+}
+
+fn fmt_key_led(color: &Option<Option<Hs>>) -> String {
+    match color {
+        Some(color) => fmt_color(*color),
+        None => "(missing)".to_string(),
+    }
+}
+
+fn fmt_mode(mode: Option<(u8, u8)>) -> String {
+    match mode {
+        Some((index, speed)) => match Mode::from_index(index) {
+            Some(mode) => format!("{} (speed {})", mode.id, speed),
+            None => format!("{} (speed {})", index, speed),
+        },
+        None => "(none)".to_string(),
+    }
+}
+
+impl fmt::Display for KeyMapDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some((old, new)) = &self.model {
+            writeln!(f, "model: {} -> {}", old, new)?;
+        }
+        if let Some((old, new)) = &self.variant {
+            let (old, new) = (fmt_variant(old), fmt_variant(new));
+            writeln!(f, "variant: {} -> {}", old, new)?;
+        }
+        if let Some((old, new)) = self.num_layers {
+            writeln!(f, "layers: {} -> {}", old, new)?;
+        }
+        for change in &self.scancodes {
+            writeln!(
+                f,
+                "{}, layer {}: {} -> {}",
+                change.key,
+                change.layer + 1,
+                fmt_scancode(&change.old),
+                fmt_scancode(&change.new)
+            )?;
+        }
+        for change in &self.key_leds {
+            writeln!(
+                f,
+                "{} LED: {} -> {}",
+                change.key,
+                fmt_key_led(&change.old),
+                fmt_key_led(&change.new)
+            )?;
+        }
+        for change in &self.layers {
+            if let Some((old, new)) = change.mode {
+                let (old, new) = (fmt_mode(old), fmt_mode(new));
+                writeln!(f, "layer {} mode: {} -> {}", change.layer + 1, old, new)?;
+            }
+            if let Some((old, new)) = change.brightness {
+                writeln!(
+                    f,
+                    "layer {} brightness: {} -> {}",
+                    change.layer + 1,
+                    old,
+                    new
+                )?;
+            }
+            if let Some((old, new)) = change.color {
+                let (old, new) = (fmt_color(Some(old)), fmt_color(Some(new)));
+                writeln!(f, "layer {} color: {} -> {}", change.layer + 1, old, new)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap() -> KeyMap {
+        let mut map = HashMap::new();
+        map.insert("K1".to_string(), vec!["A".to_string(), "B".to_string()]);
+        KeyMap {
+            model: "system76/launch_1".to_string(),
+            variant: None,
+            version: KeyMap::VERSION,
+            name: None,
+            description: None,
+            created: None,
+            firmware_version: None,
+            map,
+            key_leds: HashMap::new(),
+            layers: vec![KeyMapLayer {
+                mode: Some((0, 128)),
+                brightness: 100,
+                color: Hs::new(0., 1.),
+            }],
+        }
+    }
+
+    #[test]
+    fn keymap_diff() {
+        let old = keymap();
+        assert!(old.diff(&old).is_empty());
+
+        let mut new = keymap();
+        new.map.get_mut("K1").unwrap()[1] = "C".to_string();
+        new.layers[0].brightness = 50;
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff.scancodes,
+            vec![ScancodeChange {
+                key: "K1".to_string(),
+                layer: 1,
+                old: Some("B".to_string()),
+                new: Some("C".to_string()),
+            }]
+        );
+        assert!(diff.key_leds.is_empty());
+        assert_eq!(diff.layers.len(), 1);
+        assert_eq!(diff.layers[0].brightness, Some((100, 50)));
+        assert_eq!(diff.layers[0].mode, None);
+    }
+
+    #[test]
+    fn keymap_diff_layers_variant() {
+        let old = keymap();
+        let mut new = keymap();
+        new.variant = Some("de".to_string());
+        for _ in 0..3 {
+            new.layers.push(new.layers[0].clone());
+        }
+        let diff = old.diff(&new);
+        assert!(!diff.is_empty());
+        assert_eq!(diff.variant, Some((None, Some("de".to_string()))));
+        assert_eq!(diff.num_layers, Some((1, 4)));
+        assert!(diff.layers.is_empty());
+        assert_eq!(diff.to_string(), "variant: default -> de\nlayers: 1 -> 4\n");
+        assert_eq!(new.diff(&old).num_layers, Some((4, 1)));
+    }
+
+    #[test]
+    fn keymap_migrate() {
+        let v1 = r#"{
+            "model": "system76/launch_1",
+            "version": 1,
+            "map": { "K1": ["A", "B"] },
+            "key_leds": {},
+            "layers": []
+        }"#;
+        let keymap = KeyMap::from_str(v1).unwrap();
+        assert_eq!(keymap.version, KeyMap::VERSION);
+        assert_eq!(keymap.name, None);
+        assert_eq!(keymap.map["K1"], vec!["A", "B"]);
+
+        let newer = v1.replace(r#""version": 1"#, r#""version": 255"#);
+        assert!(KeyMap::from_str(&newer).is_err());
+    }
 }
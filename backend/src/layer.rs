@@ -1,7 +1,16 @@
 use glib::clone::Downgrade;
 use std::cell::Cell;
 
-use crate::{Board, Daemon, Hs, Mode, Rgb};
+use crate::{BackendError, Board, Daemon, Hs, LayerLeds, Mode, Rgb};
+
+/// Convert color from the daemon, which is RGB for index 0xff
+fn color_to_hs(index: u8, color: (u8, u8, u8)) -> Hs {
+    if index == 0xff {
+        Rgb::new(color.0, color.1, color.2).to_hs_lossy()
+    } else {
+        Hs::from_ints(color.0, color.1)
+    }
+}
 
 #[derive(Debug)]
 pub struct Layer {
@@ -20,7 +29,7 @@ impl Layer {
         } else {
             0xff
         };
-        let mode = if board.layout().meta.has_mode {
+        let mode = if board.capabilities().modes {
             daemon
                 .mode(board.board(), layer)
                 .map(Some)
@@ -39,13 +48,7 @@ impl Layer {
             });
         let color = daemon
             .color(board.board(), index)
-            .map(|color| {
-                if index == 0xff {
-                    Rgb::new(color.0, color.1, color.2).to_hs_lossy()
-                } else {
-                    Hs::from_ints(color.0, color.1)
-                }
-            })
+            .map(|color| color_to_hs(index, color))
             .unwrap_or_else(|err| {
                 error!("error getting layer color: {}", err);
                 Hs::new(0., 0.)
@@ -64,13 +67,47 @@ impl Layer {
         self.board.upgrade().unwrap()
     }
 
+    /// LED index used by the daemon for this layer
+    pub(crate) fn index(&self) -> u8 {
+        self.index
+    }
+
+    /// Update brightness, after the daemon reports it changed
+    pub(crate) fn brightness_changed(&self, brightness: i32) {
+        self.brightness.set(brightness);
+    }
+
+    /// Update color, after the daemon reports it changed
+    pub(crate) fn color_changed(&self, color: (u8, u8, u8)) {
+        self.color.set(color_to_hs(self.index, color));
+    }
+
+    /// Update cached settings from values read from the board again, returning
+    /// which of brightness, color, and mode differed
+    pub(crate) fn leds_refreshed(&self, leds: &LayerLeds) -> (bool, bool, bool) {
+        let brightness_changed = self.brightness() != leds.brightness;
+        if brightness_changed {
+            self.brightness_changed(leds.brightness);
+        }
+        // Compare in the daemon's format, since conversion to `Hs` is lossy
+        let color_changed = self.daemon_color(self.color()) != leds.color;
+        if color_changed {
+            self.color_changed(leds.color);
+        }
+        let mode_changed = leds.mode.is_some() && self.mode.get() != leds.mode;
+        if mode_changed {
+            self.mode.set(leds.mode);
+        }
+        (brightness_changed, color_changed, mode_changed)
+    }
+
     /// Get the current mode and speed. `None` if not supported by board.
     pub fn mode(&self) -> Option<(&'static Mode, u8)> {
         let (index, speed) = self.mode.get()?;
         Some((Mode::from_index(index)?, speed))
     }
 
-    pub async fn set_mode(&self, mode: &Mode, speed: u8) -> Result<(), String> {
+    pub async fn set_mode(&self, mode: &Mode, speed: u8) -> Result<(), BackendError> {
         let board = self.board();
         board
             .thread_client()
@@ -86,7 +123,7 @@ impl Layer {
         self.brightness.get()
     }
 
-    pub async fn set_brightness(&self, brightness: i32) -> Result<(), String> {
+    pub async fn set_brightness(&self, brightness: i32) -> Result<(), BackendError> {
         let board = self.board();
         board
             .thread_client()
@@ -102,15 +139,20 @@ impl Layer {
         self.color.get()
     }
 
-    pub async fn set_color(&self, hs: Hs) -> Result<(), String> {
-        let board = self.board();
-        let color = if self.index == 0xff {
+    /// Convert color to the format used by the daemon for this layer's index
+    fn daemon_color(&self, hs: Hs) -> (u8, u8, u8) {
+        if self.index == 0xff {
             let Rgb { r, g, b } = hs.to_rgb();
             (r, g, b)
         } else {
             let (h, s) = hs.to_ints();
             (h, s, 0)
-        };
+        }
+    }
+
+    pub async fn set_color(&self, hs: Hs) -> Result<(), BackendError> {
+        let board = self.board();
+        let color = self.daemon_color(hs);
         board
             .thread_client()
             .set_color(board.board(), self.index, color)
@@ -0,0 +1,145 @@
+use std::collections::HashSet;
+
+use super::{Layout, PhysicalLayoutKey};
+use crate::{KeyMap, Rect};
+
+/// Result of converting a keymap to the layout of another board
+#[derive(Clone, Debug)]
+pub struct KeyMapConversion {
+    pub keymap: KeyMap,
+    /// Number of keys of the original keymap placed on the board
+    pub placed: usize,
+    /// Keys of the original keymap that don't have a match on the board
+    pub unplaced: Vec<String>,
+    /// Scancodes that aren't supported by the board, as `(key, layer, scancode)`,
+    /// which are left as the board's default
+    pub unsupported: Vec<(String, usize, String)>,
+}
+
+/// Center of `rect`, relative to the size of the whole keyboard
+fn relative_center(rect: &Rect, bounds: &Rect) -> (f64, f64) {
+    (
+        (rect.x + rect.w / 2. - bounds.x) / bounds.w,
+        (bounds.y - (rect.y - rect.h / 2.)) / bounds.h,
+    )
+}
+
+/// Rectangle containing every key. `y` is the top, with rows going down from it.
+fn bounds(keys: &[PhysicalLayoutKey]) -> Rect {
+    let min_x = keys
+        .iter()
+        .map(|k| k.physical.x)
+        .fold(f64::INFINITY, f64::min);
+    let max_x = keys
+        .iter()
+        .map(|k| k.physical.x + k.physical.w)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let max_y = keys
+        .iter()
+        .map(|k| k.physical.y)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let min_y = keys
+        .iter()
+        .map(|k| k.physical.y - k.physical.h)
+        .fold(f64::INFINITY, f64::min);
+    Rect::new(min_x, max_y, max_x - min_x, max_y - min_y)
+}
+
+impl Layout {
+    /// Convert `keymap`, made for a board with layout `from`, to this layout
+    ///
+    /// Keys are matched by the name printed on them. When several keys have
+    /// the same name, such as the two shift keys, the one closest in position
+    /// is used. Between layouts of the same keyboard, such as variants of it,
+    /// keys with the same electrical mapping are matched first, whatever is
+    /// printed on them. Keys that can't be placed keep this layout's default.
+    pub fn convert_keymap(&self, from: &Layout, keymap: &KeyMap) -> KeyMapConversion {
+        let from_bounds = bounds(&from.physical.keys);
+        let to_bounds = bounds(&self.physical.keys);
+
+        let mut converted = self.default.clone();
+        converted.name = keymap.name.clone();
+        converted.description = keymap.description.clone();
+        converted.created = keymap.created;
+        converted.firmware_version = keymap.firmware_version.clone();
+        converted.layers = self
+            .default
+            .layers
+            .iter()
+            .enumerate()
+            .map(|(i, default)| keymap.layers.get(i).unwrap_or(default).clone())
+            .collect();
+
+        let same_matrix = self.meta.keyboard.is_some() && self.meta.keyboard == from.meta.keyboard;
+
+        let mut placed = HashSet::new();
+        let mut unplaced = Vec::new();
+        let mut unsupported = Vec::new();
+        for from_key in &from.physical.keys {
+            let logical_name = from_key.logical_name();
+            let from_center = relative_center(&from_key.physical, &from_bounds);
+
+            let electrical = from.layout.get(&logical_name).filter(|_| same_matrix);
+            let to_key = electrical
+                .and_then(|electrical| {
+                    self.physical
+                        .keys
+                        .iter()
+                        .find(|k| self.layout.get(&k.logical_name()) == Some(electrical))
+                })
+                .or_else(|| {
+                    self.physical
+                        .keys
+                        .iter()
+                        .filter(|k| k.physical_name == from_key.physical_name)
+                        .filter(|k| !placed.contains(&k.logical_name()))
+                        .min_by(|a, b| {
+                            let distance = |key: &PhysicalLayoutKey| {
+                                let center = relative_center(&key.physical, &to_bounds);
+                                (center.0 - from_center.0).powi(2)
+                                    + (center.1 - from_center.1).powi(2)
+                            };
+                            distance(a).partial_cmp(&distance(b)).unwrap()
+                        })
+                });
+            let to_name = match to_key {
+                Some(to_key) => to_key.logical_name(),
+                None => {
+                    if keymap.map.contains_key(&logical_name) {
+                        unplaced.push(logical_name);
+                    }
+                    continue;
+                }
+            };
+
+            if let Some(scancodes) = keymap.map.get(&logical_name) {
+                if let Some(to_scancodes) = converted.map.get_mut(&to_name) {
+                    for (layer, (scancode, to_scancode)) in
+                        scancodes.iter().zip(to_scancodes.iter_mut()).enumerate()
+                    {
+                        if scancode.is_empty() {
+                            continue;
+                        } else if self.scancode_from_name(scancode).is_some() {
+                            *to_scancode = scancode.clone();
+                        } else {
+                            unsupported.push((logical_name.clone(), layer, scancode.clone()));
+                        }
+                    }
+                }
+            }
+            if self.leds.contains_key(&to_name) {
+                if let Some(color) = keymap.key_leds.get(&logical_name) {
+                    converted.key_leds.insert(to_name.clone(), *color);
+                }
+            }
+            placed.insert(to_name);
+        }
+
+        KeyMapConversion {
+            keymap: converted,
+            placed: placed.len(),
+            unplaced,
+            unsupported,
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::{error::Error, fmt, io, path::PathBuf};
+
+/// Error loading a layout
+#[derive(Debug)]
+pub enum LayoutError {
+    /// No layout exists for the board
+    NotFound(String),
+    /// The layout has no variant with this name
+    UnknownVariant(String),
+    /// A layout file couldn't be read
+    Io { path: PathBuf, err: io::Error },
+    /// A layout file is malformed
+    Parse {
+        /// File name, such as `physical.json`
+        file: String,
+        /// Location of the invalid value in the file, if known, such as `K1A`
+        /// or `[3][2]`
+        field: Option<String>,
+        err: String,
+    },
+}
+
+impl LayoutError {
+    pub(crate) fn parse<E: fmt::Display>(file: &str, field: Option<String>, err: E) -> Self {
+        Self::Parse {
+            file: file.to_string(),
+            field,
+            err: err.to_string(),
+        }
+    }
+
+    /// Report a parse error as being in a file of `variant`
+    pub(crate) fn in_variant(self, variant: &str) -> Self {
+        match self {
+            Self::Parse { file, field, err } => Self::Parse {
+                file: format!("variants/{}/{}", variant, file),
+                field,
+                err,
+            },
+            err => err,
+        }
+    }
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotFound(board) => write!(f, "no layout for '{}'", board),
+            Self::UnknownVariant(variant) => write!(f, "no layout variant '{}'", variant),
+            Self::Io { path, err } => write!(f, "failed to read {}: {}", path.display(), err),
+            Self::Parse {
+                file,
+                field: Some(field),
+                err,
+            } => write!(f, "invalid {} at '{}': {}", file, field, err),
+            Self::Parse {
+                file,
+                field: None,
+                err,
+            } => write!(f, "invalid {}: {}", file, err),
+        }
+    }
+}
+
+impl Error for LayoutError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io { err, .. } => Some(err),
+            _ => None,
+        }
+    }
+}
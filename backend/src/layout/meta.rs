@@ -6,7 +6,7 @@ fn num_layers_default() -> u8 {
 }
 
 /// Metadata for keyboard
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct Meta {
     /// Display name for keyboard
     pub display_name: String,
@@ -24,4 +24,10 @@ pub struct Meta {
     #[serde(default = "num_layers_default")]
     pub num_layers: u8,
     pub pressed_color: Rgb,
+    /// Name of the directory in `layouts/keyboards` with the rest of the layout
+    #[serde(default)]
+    pub keyboard: Option<String>,
+    /// Board whose `default.json` is used, for a board without one of its own
+    #[serde(default)]
+    pub default_board: Option<String>,
 }
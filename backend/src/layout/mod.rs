@@ -1,26 +1,51 @@
-use std::{collections::HashMap, fs, path::Path};
+use serde::de::{self, DeserializeOwned, Deserializer, IgnoredAny, MapAccess};
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    env, fmt, fs,
+    path::{Path, PathBuf},
+};
 
+mod convert;
+mod error;
 mod meta;
 mod physical_layout;
+mod validate;
+mod variant;
+pub use self::convert::KeyMapConversion;
+pub use self::error::LayoutError;
 pub use self::meta::Meta;
+pub use self::validate::LayoutIssue;
+pub use physical_layout::KeyLegend;
 pub(crate) use physical_layout::{PhysicalLayout, PhysicalLayoutKey};
+pub(crate) use variant::{save_variant, saved_variant};
 
-use crate::KeyMap;
+use crate::{KeyMap, Scancode};
 
 pub struct Layout {
     /// Metadata for keyboard
     pub meta: Meta,
     /// Default keymap for this keyboard
     pub default: KeyMap,
-    keymap: HashMap<String, u16>,
+    /// Scancodes supported by the keyboard, by name
+    scancodes: HashMap<String, Scancode>,
     scancode_names: HashMap<u16, String>,
     pub(crate) physical: PhysicalLayout,
     pub(crate) layout: HashMap<String, (u8, u8)>,
+    /// Logical names of keys in the order of `layout.json`, which is the order
+    /// of arguments to the firmware's `LAYOUT` macro
+    pub(crate) layout_order: Vec<String>,
     pub(crate) leds: HashMap<String, Vec<u8>>,
+    /// Variant of the keyboard, such as `iso`, or `None` for the base layout
+    pub variant: Option<String>,
+    /// Variants this layout can be switched to with `with_variant`
+    pub variants: Vec<String>,
+    /// Directory of the keyboard, for layouts loaded at runtime
+    keyboard_dir: Option<PathBuf>,
 }
 
 macro_rules! keyboards {
-    ($( ($board:expr, $keyboard:expr) ),* $(,)?) => {
+    ($( ($board:expr, $keyboard:expr, $default_board:expr) ),* $(,)?) => {
         fn layout_data(board: &str) -> Option<(&'static str, &'static str, &'static str, &'static str, &'static str, &'static str)> {
             match board {
                 $(
@@ -28,7 +53,7 @@ macro_rules! keyboards {
                     let meta_json =
                         include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../layouts/", $board, "/meta.json"));
                     let default_json =
-                        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../layouts/", $board, "/default.json"));
+                        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../layouts/", $default_board, "/default.json"));
                     let keymap_json =
                         include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../layouts/keyboards/", $keyboard, "/keymap.json"));
                     let layout_json =
@@ -62,90 +87,294 @@ impl Layout {
         layout_json: &str,
         leds_json: &str,
         physical_json: &str,
-    ) -> Self {
-        let meta = serde_json::from_str(meta_json).unwrap();
-        let default = KeyMap::from_str(default_json).unwrap();
-        let (keymap, scancode_names) = parse_keymap_json(keymap_json);
-        let layout = serde_json::from_str(layout_json).unwrap();
-        let leds = serde_json::from_str(leds_json).unwrap();
-        let physical = PhysicalLayout::from_str(physical_json);
-        Self {
+    ) -> Result<Self, LayoutError> {
+        let meta = serde_json::from_str(meta_json)
+            .map_err(|err| LayoutError::parse("meta.json", None, err))?;
+        let default = KeyMap::from_str(default_json)
+            .map_err(|err| LayoutError::parse("default.json", None, err))?;
+        let (scancodes, scancode_names) = parse_keymap_json(keymap_json)?;
+        let layout = parse_json_map("layout.json", layout_json)?;
+        let layout_order = parse_json_keys("layout.json", layout_json)?;
+        let leds = parse_json_map("leds.json", leds_json)?;
+        let physical = PhysicalLayout::from_str(physical_json)?;
+        Ok(Self {
             meta,
             default,
-            keymap,
+            scancodes,
             scancode_names,
             physical,
             layout,
+            layout_order,
             leds,
-        }
+            variant: None,
+            variants: Vec::new(),
+            keyboard_dir: None,
+        })
     }
 
-    #[allow(dead_code)]
-    pub fn from_dir<P: AsRef<Path>>(dir: P) -> Self {
+    /// Load a layout from a board directory
+    ///
+    /// `dir` must contain `meta.json` and `default.json`, unless `meta.json`
+    /// names a `default_board` in the same tree. The other files are read from
+    /// `dir` as well if it contains them, otherwise from `keyboards/<keyboard>`
+    /// in the same tree, like the `layouts` directory of this repository.
+    /// Variants are in the `variants` directory next to `physical.json`.
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> Result<Self, LayoutError> {
         let dir = dir.as_ref();
 
-        let meta_json =
-            fs::read_to_string(dir.join("meta.json")).expect("Failed to load meta.json");
-        let default_json =
-            fs::read_to_string(dir.join("default.json")).expect("Failed to load default.json");
-        let keymap_json =
-            fs::read_to_string(dir.join("keymap.json")).expect("Failed to load keymap.json");
-        let layout_json =
-            fs::read_to_string(dir.join("layout.json")).expect("Failed to load layout.json");
-        let leds_json =
-            fs::read_to_string(dir.join("leds.json")).expect("Failed to load leds.json");
-        let physical_json =
-            fs::read_to_string(dir.join("physical.json")).expect("Failed to load physical.json");
-
-        Self::from_data(
+        let meta_json = read_layout_file(&dir.join("meta.json"))?;
+        let meta: Meta = serde_json::from_str(&meta_json)
+            .map_err(|err| LayoutError::parse("meta.json", None, err))?;
+        // Board directories are `<vendor>/<model>`
+        let layouts_dir = dir.join("../..");
+        let default_json = match &meta.default_board {
+            Some(board) => read_layout_file(&layouts_dir.join(board).join("default.json"))?,
+            None => read_layout_file(&dir.join("default.json"))?,
+        };
+
+        let keyboard_dir = if dir.join("keymap.json").exists() {
+            dir.to_path_buf()
+        } else {
+            let keyboard = meta.keyboard.ok_or_else(|| {
+                LayoutError::parse(
+                    "meta.json",
+                    Some("keyboard".to_string()),
+                    "required, since the board directory has no keymap.json",
+                )
+            })?;
+            layouts_dir.join("keyboards").join(keyboard)
+        };
+
+        let keymap_json = read_layout_file(&keyboard_dir.join("keymap.json"))?;
+        let layout_json = read_layout_file(&keyboard_dir.join("layout.json"))?;
+        let leds_json = read_layout_file(&keyboard_dir.join("leds.json"))?;
+        let physical_json = read_layout_file(&keyboard_dir.join("physical.json"))?;
+
+        let mut layout = Self::from_data(
             &meta_json,
             &default_json,
             &keymap_json,
             &layout_json,
             &leds_json,
             &physical_json,
-        )
+        )?;
+        if layout.meta.default_board.is_some() {
+            if let (Some(vendor), Some(model)) =
+                (dir.parent().and_then(Path::file_name), dir.file_name())
+            {
+                layout.default.model =
+                    format!("{}/{}", vendor.to_string_lossy(), model.to_string_lossy());
+            }
+        }
+        layout.variants = variant::dir_variants(&keyboard_dir);
+        layout.keyboard_dir = Some(keyboard_dir);
+        Ok(layout)
     }
 
-    pub fn from_board(board: &str) -> Option<Self> {
-        layout_data(board).map(
-            |(meta_json, default_json, keymap_json, layout_json, leds_json, physical_json)| {
-                Self::from_data(
+    /// Load the layout for `board`
+    ///
+    /// Layouts installed in `layout_dirs()` take priority over the built-in
+    /// ones. If one fails to load, the error is logged and the built-in layout
+    /// is used instead, if there is one.
+    pub fn from_board(board: &str) -> Result<Self, LayoutError> {
+        let mut runtime_err = None;
+        if let Some(dir) = runtime_layout_dir(board) {
+            match Self::from_dir(&dir) {
+                Ok(layout) => {
+                    info!("Loaded layout for '{}' from {}", board, dir.display());
+                    return Ok(layout);
+                }
+                Err(err) => {
+                    error!("Failed to load layout for '{}': {}", board, err);
+                    runtime_err = Some(err);
+                }
+            }
+        }
+
+        match layout_data(board) {
+            Some((meta_json, default_json, keymap_json, layout_json, leds_json, physical_json)) => {
+                let mut layout = Self::from_data(
                     meta_json,
                     default_json,
                     keymap_json,
                     layout_json,
                     leds_json,
                     physical_json,
-                )
-            },
-        )
+                )?;
+                if layout.meta.default_board.is_some() {
+                    layout.default.model = board.to_string();
+                }
+                if let Some(keyboard) = &layout.meta.keyboard {
+                    layout.variants = variant::builtin_variants(keyboard);
+                }
+                Ok(layout)
+            }
+            None => Err(runtime_err.unwrap_or_else(|| LayoutError::NotFound(board.to_string()))),
+        }
     }
 
-    /// Get the scancode number corresponding to a name
+    /// Scancodes supported by the keyboard, in no particular order
+    pub fn scancodes(&self) -> impl Iterator<Item = &Scancode> {
+        self.scancodes.values()
+    }
+
+    /// Get a scancode by name
+    pub fn scancode(&self, name: &str) -> Option<&Scancode> {
+        self.scancodes.get(name)
+    }
+
+    /// Get a scancode by its numerical value
+    pub fn scancode_by_value(&self, value: u16) -> Option<&Scancode> {
+        self.scancode(self.scancode_names.get(&value)?)
+    }
+
+    /// Get the name corresponding to a scancode number
     pub fn scancode_to_name(&self, scancode: u16) -> Option<&str> {
         self.scancode_names.get(&scancode).map(String::as_str)
     }
 
-    /// Get the name corresponding to a scancode number
+    /// Get the scancode number corresponding to a name
     pub fn scancode_from_name(&self, name: &str) -> Option<u16> {
-        self.keymap.get(name).copied()
+        self.scancode(name).map(|scancode| scancode.value)
+    }
+
+    /// Get the electrical mapping (output, input) of a key by logical name
+    pub(crate) fn electrical(&self, logical_name: &str) -> (u8, u8) {
+        self.layout.get(logical_name).copied().unwrap_or((0, 0))
+    }
+}
+
+/// Directories searched for layouts at runtime, from highest to lowest priority
+///
+/// These are `system76-keyboard-configurator/layouts` in `$XDG_DATA_HOME`
+/// and each of `$XDG_DATA_DIRS`.
+pub fn layout_dirs() -> Vec<PathBuf> {
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")));
+    let data_dirs = env::var_os("XDG_DATA_DIRS")
+        .filter(|x| !x.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".into());
+
+    data_home
+        .into_iter()
+        .chain(env::split_paths(&data_dirs))
+        .map(|dir| dir.join("system76-keyboard-configurator/layouts"))
+        .collect()
+}
+
+/// Names of board layouts installed in `layout_dirs()`
+pub fn runtime_layouts() -> Vec<String> {
+    let mut boards = Vec::new();
+    for dir in layout_dirs() {
+        let vendors = match fs::read_dir(&dir) {
+            Ok(vendors) => vendors,
+            Err(_) => continue,
+        };
+        for vendor in vendors.filter_map(Result::ok) {
+            let vendor_name = vendor.file_name().to_string_lossy().into_owned();
+            if vendor_name == "keyboards" {
+                continue;
+            }
+            let models = match fs::read_dir(vendor.path()) {
+                Ok(models) => models,
+                Err(_) => continue,
+            };
+            for model in models.filter_map(Result::ok) {
+                if model.path().join("meta.json").exists() {
+                    let board = format!("{}/{}", vendor_name, model.file_name().to_string_lossy());
+                    if !boards.contains(&board) {
+                        boards.push(board);
+                    }
+                }
+            }
+        }
+    }
+    boards.sort();
+    boards
+}
+
+fn runtime_layout_dir(board: &str) -> Option<PathBuf> {
+    layout_dirs()
+        .into_iter()
+        .map(|dir| dir.join(board))
+        .find(|dir| dir.join("meta.json").exists())
+}
+
+fn read_layout_file(path: &Path) -> Result<String, LayoutError> {
+    fs::read_to_string(path).map_err(|err| LayoutError::Io {
+        path: path.to_path_buf(),
+        err,
+    })
+}
+
+/// Parse a json object, reporting the key of the first invalid value
+fn parse_json_map<T: DeserializeOwned>(
+    file: &str,
+    json: &str,
+) -> Result<HashMap<String, T>, LayoutError> {
+    let map: Map<String, Value> =
+        serde_json::from_str(json).map_err(|err| LayoutError::parse(file, None, err))?;
+    map.into_iter()
+        .map(|(key, value)| match serde_json::from_value(value) {
+            Ok(value) => Ok((key, value)),
+            Err(err) => Err(LayoutError::parse(file, Some(key), err)),
+        })
+        .collect()
+}
+
+/// Keys of a json object, in the order they appear in it
+struct JsonKeys(Vec<String>);
+
+impl<'de> serde::Deserialize<'de> for JsonKeys {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = JsonKeys;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("an object")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<JsonKeys, A::Error> {
+                let mut keys = Vec::new();
+                while let Some((key, IgnoredAny)) = map.next_entry::<String, IgnoredAny>()? {
+                    keys.push(key);
+                }
+                Ok(JsonKeys(keys))
+            }
+        }
+
+        deserializer.deserialize_map(Visitor)
     }
 }
 
-fn parse_keymap_json(keymap_json: &str) -> (HashMap<String, u16>, HashMap<u16, String>) {
+/// Parse the keys of a json object, keeping their order
+fn parse_json_keys(file: &str, json: &str) -> Result<Vec<String>, LayoutError> {
+    serde_json::from_str(json)
+        .map(|JsonKeys(keys)| keys)
+        .map_err(|err| LayoutError::parse(file, None, err))
+}
+
+fn parse_keymap_json(
+    keymap_json: &str,
+) -> Result<(HashMap<String, Scancode>, HashMap<u16, String>), LayoutError> {
+    let mut scancodes = HashMap::new();
     let mut scancode_names = HashMap::new();
-    let keymap: HashMap<String, u16> = serde_json::from_str(keymap_json).unwrap();
-    for (scancode_name, scancode) in &keymap {
-        scancode_names.insert(*scancode, scancode_name.clone());
+    let keymap: HashMap<String, u16> = parse_json_map("keymap.json", keymap_json)?;
+    for (scancode_name, value) in keymap {
+        scancode_names.insert(value, scancode_name.clone());
+        scancodes.insert(scancode_name.clone(), Scancode::new(&scancode_name, value));
     }
-    (keymap, scancode_names)
+    Ok((scancodes, scancode_names))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::{collections::HashSet, fs, io};
+    use std::{collections::HashSet, fs, io, iter};
 
     #[test]
     fn layout_from_board() {
@@ -154,13 +383,91 @@ mod tests {
         }
     }
 
+    #[test]
+    fn layout_from_dir() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../layouts/system76/launch_1");
+        let layout = Layout::from_dir(dir).unwrap();
+        assert_eq!(layout.meta.keyboard.as_deref(), Some("system76/launch_1"));
+        assert!(Layout::from_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/../layouts")).is_err());
+    }
+
+    #[test]
+    fn layout_errors() {
+        let field = |res: Result<_, LayoutError>| match res {
+            Err(LayoutError::Parse { file, field, .. }) => (file, field),
+            _ => panic!("expected parse error"),
+        };
+        assert_eq!(
+            field(parse_json_map::<(u8, u8)>(
+                "layout.json",
+                r#"{"K00": [0, 1], "K01": [0]}"#
+            )),
+            ("layout.json".to_string(), Some("K01".to_string()))
+        );
+        assert_eq!(
+            field(PhysicalLayout::from_str(
+                r#"[{"name": "", "author": ""}, ["Esc", {"w": "x"}]]"#
+            )),
+            ("physical.json".to_string(), Some("[1][1]".to_string()))
+        );
+        assert_eq!(
+            field(PhysicalLayout::from_str(r#"[["Esc"]]"#)),
+            ("physical.json".to_string(), None)
+        );
+        assert!(matches!(
+            Layout::from_board("system76/nonexistent"),
+            Err(LayoutError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn layout_valid() {
+        for i in layouts() {
+            let variants = Layout::from_board(i).unwrap().variants;
+            for variant in iter::once(None).chain(variants.iter().map(|x| Some(x.as_str()))) {
+                let layout = Layout::from_board_variant(i, variant).unwrap();
+                if let Err(issues) = layout.validate() {
+                    let issues = issues.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+                    panic!(
+                        "Invalid layout for {} ({:?}):\n{}",
+                        i,
+                        variant,
+                        issues.join("\n")
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn layout_validate_issues() {
+        let mut layout = Layout::from_board("system76/launch_1").unwrap();
+        layout.layout.remove("K00");
+        layout
+            .layout
+            .insert("K02".to_string(), layout.layout["K01"]);
+        layout.leds.insert("KZZ".to_string(), vec![0xf0]);
+        layout.default.map.insert("KZY".to_string(), Vec::new());
+        let issues = layout.validate().unwrap_err();
+        assert!(issues.contains(&LayoutIssue::MissingElectrical("K00".to_string())));
+        assert!(issues.contains(&LayoutIssue::DuplicateElectrical(
+            "K01".to_string(),
+            "K02".to_string(),
+            layout.layout["K01"]
+        )));
+        assert!(issues.contains(&LayoutIssue::OrphanLed("KZZ".to_string())));
+        assert!(issues.contains(&LayoutIssue::LedOutOfRange("KZZ".to_string(), 0xf0)));
+        assert!(issues.contains(&LayoutIssue::UnknownDefaultKey("KZY".to_string())));
+        assert_eq!(issues.len(), 5);
+    }
+
     #[test]
     fn default_keys_exist() {
         for i in layouts() {
             let mut missing = HashSet::new();
             let layout = Layout::from_board(i).unwrap();
             for j in layout.default.map.values().flatten() {
-                if layout.keymap.keys().find(|x| x == &j).is_none() {
+                if layout.scancode(j).is_none() {
                     missing.insert(j.to_owned());
                 }
             }
@@ -168,11 +475,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn scancodes_have_meta() {
+        for i in layouts() {
+            let layout = Layout::from_board(i).unwrap();
+            let mut unknown = layout
+                .scancodes()
+                .map(|x| x.name.as_str())
+                .filter(|x| !Scancode::is_known(x))
+                .collect::<Vec<_>>();
+            unknown.sort_unstable();
+            assert_eq!(
+                unknown,
+                Vec::<&str>::new(),
+                "Missing in scancodes.json for {}",
+                i
+            );
+        }
+    }
+
     #[test]
     fn qmk_has_ec_keycodes() {
         let layout_ec = Layout::from_board("system76/darp6").unwrap();
         let layout_qmk = Layout::from_board("system76/launch_1").unwrap();
-        for k in layout_ec.keymap.keys() {
+        for k in layout_ec.scancodes.keys() {
             if k == "KBD_COLOR"
                 || k == "KBD_BKL"
                 || k == "TOUCHPAD"
@@ -184,8 +510,53 @@ mod tests {
             {
                 continue;
             }
-            assert_eq!(layout_qmk.keymap.keys().find(|x| x == &k), Some(k));
+            assert_eq!(layout_qmk.scancodes.keys().find(|x| x == &k), Some(k));
+        }
+    }
+
+    #[test]
+    fn convert_keymap() {
+        let darp6 = Layout::from_board("system76/darp6").unwrap();
+        let conversion = darp6.convert_keymap(&darp6, &darp6.default);
+        assert_eq!(conversion.keymap.map, darp6.default.map);
+        assert!(conversion.unplaced.is_empty());
+
+        // Mark keys of darp6, to find where they end up
+        let mut keymap = darp6.default.clone();
+        for (key, scancode) in &[
+            ("K00", "A"), // Esc
+            ("K40", "B"), // Left Shift
+            ("K4B", "C"), // Right Shift
+            ("K3C", "D"), // Enter
+            ("K4G", "E"), // Numpad Enter
+        ] {
+            keymap.map.get_mut(*key).unwrap()[0] = scancode.to_string();
         }
+
+        let galp5 = Layout::from_board("system76/galp5").unwrap();
+        let conversion = galp5.convert_keymap(&darp6, &keymap);
+        assert_eq!(conversion.keymap.map.len(), galp5.default.map.len());
+        assert_eq!(conversion.keymap.map["K00"][0], "A");
+        // Keys with the same name are matched by position
+        assert_eq!(conversion.keymap.map["K40"][0], "B");
+        assert_eq!(conversion.keymap.map["K4B"][0], "C");
+        assert_eq!(conversion.keymap.map["K3C"][0], "D");
+        // galp5 has no numpad, and its only Enter is taken
+        assert!(conversion.unplaced.contains(&"K4G".to_string()));
+        assert!(conversion.unplaced.contains(&"K1E".to_string())); // Num Lock
+    }
+
+    #[test]
+    fn default_board() {
+        let darp6 = Layout::from_board("system76/darp6").unwrap();
+        let generic = Layout::from_board("system76/generic").unwrap();
+        assert_eq!(generic.default.model, "system76/generic");
+        assert_eq!(generic.default.map, darp6.default.map);
+
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../layouts/system76/generic");
+        let generic = Layout::from_dir(dir).unwrap();
+        assert_eq!(generic.default.model, "system76/generic");
+        assert_eq!(generic.default.map, darp6.default.map);
     }
 
     #[test]
@@ -1,9 +1,27 @@
 /// Serde based deserialization for physical.json
 /// From http://www.keyboard-layout-editor.com
 use serde::Deserialize;
-use std::char;
+use serde_json::Value;
+use std::{char, fmt};
 
-use crate::{Rect, Rgb};
+use crate::{LayoutError, Rect, Rgb};
+
+/// Position of each legend of a key, by alignment (`a`) and index of the legend
+/// in the key's name. `-1` if the legend isn't shown with that alignment.
+///
+/// Matches `labelMap` in keyboard-layout-editor.
+const LABEL_MAP: [[i8; 12]; 8] = [
+    [0, 6, 2, 8, 9, 11, 3, 5, 1, 4, 7, 10],
+    [1, 7, -1, -1, 9, 11, 4, -1, -1, -1, -1, 10],
+    [3, -1, 5, -1, 9, 11, -1, -1, 4, -1, -1, 10],
+    [4, -1, -1, -1, 9, 11, -1, -1, -1, -1, -1, 10],
+    [0, 6, 2, 8, 10, -1, 3, 5, 1, 4, 7, -1],
+    [1, 7, -1, -1, 10, -1, 4, -1, -1, -1, -1, -1],
+    [3, -1, 5, -1, 10, -1, -1, -1, 4, -1, -1, -1],
+    [4, -1, -1, -1, 10, -1, -1, -1, -1, -1, -1, -1],
+];
+
+const DEFAULT_ALIGN: usize = 4;
 
 pub(crate) struct PhysicalLayout {
     pub meta: PhysicalLayoutMeta,
@@ -11,70 +29,237 @@ pub(crate) struct PhysicalLayout {
 }
 
 impl PhysicalLayout {
-    pub fn from_str(physical_json: &str) -> Self {
-        let json = serde_json::from_str::<PhysicalLayoutJson>(physical_json).unwrap();
+    pub fn from_str(physical_json: &str) -> Result<Self, LayoutError> {
+        let parse_err = |field: Option<String>, err: &dyn fmt::Display| {
+            LayoutError::parse("physical.json", field, err)
+        };
+
+        let entries = serde_json::from_str::<Vec<Value>>(physical_json)
+            .map_err(|err| parse_err(None, &err))?;
 
         let mut keys = Vec::new();
 
         let mut row_i = 0;
         let mut col_i = 0;
-        let mut physical = Rect::new(0.0, 0.0, 1.0, 1.0);
-        let mut background_color = Rgb::new(0xcc, 0xcc, 0xcc);
         let mut meta = None;
 
-        for entry in json.0 {
-            match entry {
-                PhysicalLayoutEntry::Meta(data) => {
+        // Position in keyboard-layout-editor units, with y going down
+        let (mut x, mut y) = (0.0, 0.0);
+        let (mut w, mut h) = (1.0, 1.0);
+        let mut secondary = SecondaryRect::default();
+        let mut decal = false;
+        // Properties that apply until changed
+        let mut rotation = Rotation::default();
+        let mut background_color = Rgb::new(0xcc, 0xcc, 0xcc);
+        let mut text_colors = Vec::new();
+        let mut align = DEFAULT_ALIGN;
+
+        for (entry_i, entry) in entries.into_iter().enumerate() {
+            let row = match entry {
+                Value::Object(_) => {
+                    let data = serde_json::from_value::<PhysicalLayoutMeta>(entry)
+                        .map_err(|err| parse_err(Some(format!("[{}]", entry_i)), &err))?;
                     meta = Some(data);
+                    continue;
                 }
-                PhysicalLayoutEntry::Row(row) => {
-                    for i in &row.0 {
-                        match i {
-                            PhysicalKeyEnum::Meta(meta) => {
-                                debug!("Key metadata {:?}", meta);
-                                physical.x += meta.x;
-                                physical.y -= meta.y;
-                                physical.w = meta.w.unwrap_or(physical.w);
-                                physical.h = meta.h.unwrap_or(physical.h);
-                                background_color = meta.c.unwrap_or(background_color);
+                Value::Array(row) => row,
+                _ => {
+                    return Err(parse_err(
+                        Some(format!("[{}]", entry_i)),
+                        &"expected metadata object or row array",
+                    ))
+                }
+            };
+
+            // Logical names have a single base 36 digit for row and column
+            if row_i >= 36 {
+                return Err(parse_err(None, &"more than 36 rows"));
+            }
+
+            for (key_i, i) in row.into_iter().enumerate() {
+                let field = || Some(format!("[{}][{}]", entry_i, key_i));
+                match i {
+                    Value::String(name) => {
+                        // Decals are labels, not keys
+                        if !decal {
+                            if col_i >= 36 {
+                                return Err(parse_err(field(), &"more than 36 keys in row"));
                             }
-                            PhysicalKeyEnum::Name(name) => {
-                                keys.push(PhysicalLayoutKey {
-                                    logical: (row_i as u8, col_i as u8),
-                                    physical,
-                                    physical_name: name.clone(),
-                                    background_color,
-                                });
 
-                                physical.x += physical.w;
+                            keys.push(PhysicalLayoutKey {
+                                logical: (row_i as u8, col_i as u8),
+                                physical: Rect::new(x, -y, w, h),
+                                physical2: secondary.rect(x, y, w, h),
+                                rotation: rotation.angle,
+                                rotation_origin: (rotation.x, -rotation.y),
+                                legends: legends(&name, align, &text_colors),
+                                physical_name: name,
+                                background_color,
+                            });
+
+                            col_i += 1;
+                        }
 
-                                physical.w = 1.0;
-                                physical.h = 1.0;
+                        x += w;
 
-                                col_i += 1;
+                        w = 1.0;
+                        h = 1.0;
+                        secondary = SecondaryRect::default();
+                        decal = false;
+                    }
+                    Value::Object(_) => {
+                        let meta = serde_json::from_value::<PhysicalKeyMeta>(i)
+                            .map_err(|err| parse_err(field(), &err))?;
+                        debug!("Key metadata {:?}", meta);
+                        if let Some(r) = meta.r {
+                            rotation.angle = r;
+                        }
+                        // Setting the rotation origin starts a new cluster of keys
+                        if let Some(rx) = meta.rx {
+                            rotation.x = rx;
+                            x = rotation.x;
+                            y = rotation.y;
+                        }
+                        if let Some(ry) = meta.ry {
+                            rotation.y = ry;
+                            x = rotation.x;
+                            y = rotation.y;
+                        }
+                        x += meta.x;
+                        y += meta.y;
+                        w = meta.w.unwrap_or(w);
+                        h = meta.h.unwrap_or(h);
+                        secondary = SecondaryRect {
+                            x: meta.x2.or(secondary.x),
+                            y: meta.y2.or(secondary.y),
+                            w: meta.w2.or(secondary.w),
+                            h: meta.h2.or(secondary.h),
+                        };
+                        decal = meta.d.unwrap_or(decal);
+                        if let Some(c) = &meta.c {
+                            background_color = parse_color(c)
+                                .ok_or_else(|| parse_err(field(), &"invalid color 'c'"))?;
+                        }
+                        if let Some(t) = &meta.t {
+                            text_colors = t
+                                .split('\n')
+                                .map(|color| match color {
+                                    "" => Ok(None),
+                                    _ => parse_color(color).map(Some).ok_or(()),
+                                })
+                                .collect::<Result<_, _>>()
+                                .map_err(|()| parse_err(field(), &"invalid color 't'"))?;
+                        }
+                        if let Some(a) = meta.a {
+                            if a >= LABEL_MAP.len() {
+                                return Err(parse_err(field(), &"invalid alignment 'a'"));
                             }
+                            align = a;
                         }
                     }
-
-                    physical.x = 0.0;
-                    physical.y -= 1.0;
-
-                    col_i = 0;
-                    row_i += 1;
+                    _ => return Err(parse_err(field(), &"expected key name or metadata")),
                 }
             }
+
+            x = rotation.x;
+            y += 1.0;
+
+            col_i = 0;
+            row_i += 1;
         }
 
-        let meta = meta.expect("No layout meta");
+        let meta = meta.ok_or_else(|| parse_err(None, &"no layout metadata"))?;
 
-        Self { keys, meta }
+        Ok(Self { keys, meta })
     }
 }
 
+#[derive(Default)]
+struct Rotation {
+    /// Clockwise, in degrees
+    angle: f64,
+    x: f64,
+    y: f64,
+}
+
+/// Second rectangle of a key, relative to the first, like for ISO enter
+#[derive(Default)]
+struct SecondaryRect {
+    x: Option<f64>,
+    y: Option<f64>,
+    w: Option<f64>,
+    h: Option<f64>,
+}
+
+impl SecondaryRect {
+    /// The rectangle, if any of its properties are set
+    fn rect(&self, x: f64, y: f64, w: f64, h: f64) -> Option<Rect> {
+        if self.x.is_none() && self.y.is_none() && self.w.is_none() && self.h.is_none() {
+            return None;
+        }
+        Some(Rect::new(
+            x + self.x.unwrap_or(0.0),
+            -(y + self.y.unwrap_or(0.0)),
+            self.w.unwrap_or(w),
+            self.h.unwrap_or(h),
+        ))
+    }
+}
+
+/// Parse `#rrggbb` or `#rgb` color
+fn parse_color(s: &str) -> Option<Rgb> {
+    if s.len() == 4 && s.starts_with('#') {
+        let mut long = String::from("#");
+        for c in s[1..].chars() {
+            long.push(c);
+            long.push(c);
+        }
+        Rgb::parse(&long)
+    } else {
+        Rgb::parse(s)
+    }
+}
+
+/// Split a key's name into its legends, with positions based on `align`
+fn legends(name: &str, align: usize, text_colors: &[Option<Rgb>]) -> Vec<KeyLegend> {
+    let default_color = text_colors.first().copied().flatten();
+    name.split('\n')
+        .enumerate()
+        .filter(|(_, text)| !text.is_empty())
+        .filter_map(|(i, text)| {
+            let position = *LABEL_MAP[align].get(i)?;
+            if position < 0 {
+                return None;
+            }
+            Some(KeyLegend {
+                position: position as usize,
+                text: text.to_string(),
+                color: text_colors.get(i).copied().flatten().or(default_color),
+            })
+        })
+        .collect()
+}
+
+/// Legend printed on a keycap
+#[derive(Clone, Debug)]
+pub struct KeyLegend {
+    /// Position, as in keyboard-layout-editor. `0` to `8` are the top, center,
+    /// and bottom rows of the keycap, each from left to right, and `9` to
+    /// `11` are on its front.
+    pub position: usize,
+    pub text: String,
+    /// Text color, if set by the layout
+    pub color: Option<Rgb>,
+}
+
 pub(crate) struct PhysicalLayoutKey {
     pub logical: (u8, u8),
     pub physical: Rect,
+    pub physical2: Option<Rect>,
+    pub rotation: f64,
+    pub rotation_origin: (f64, f64),
     pub physical_name: String,
+    pub legends: Vec<KeyLegend>,
     pub background_color: Rgb,
 }
 
@@ -88,32 +273,12 @@ impl PhysicalLayoutKey {
     }
 }
 
-#[derive(Debug, Deserialize)]
-struct PhysicalLayoutJson(Vec<PhysicalLayoutEntry>);
-
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
-enum PhysicalLayoutEntry {
-    Meta(PhysicalLayoutMeta),
-    Row(PhysicalRow),
-}
-
 #[derive(Debug, Deserialize)]
 pub(crate) struct PhysicalLayoutMeta {
     pub name: String,
     pub author: String,
 }
 
-#[derive(Debug, Deserialize)]
-struct PhysicalRow(Vec<PhysicalKeyEnum>);
-
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
-enum PhysicalKeyEnum {
-    Name(String),
-    Meta(PhysicalKeyMeta),
-}
-
 #[derive(Debug, Deserialize)]
 struct PhysicalKeyMeta {
     #[serde(default)]
@@ -122,5 +287,57 @@ struct PhysicalKeyMeta {
     y: f64,
     w: Option<f64>,
     h: Option<f64>,
-    c: Option<Rgb>,
+    x2: Option<f64>,
+    y2: Option<f64>,
+    w2: Option<f64>,
+    h2: Option<f64>,
+    r: Option<f64>,
+    rx: Option<f64>,
+    ry: Option<f64>,
+    c: Option<String>,
+    t: Option<String>,
+    a: Option<usize>,
+    d: Option<bool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn physical_layout_kle() {
+        let json = r##"[
+            {"name": "", "author": ""},
+            [{"a": 5, "t": "#fff\n#000"}, "!\n1", {"w": 1.25, "w2": 1.5, "h": 2, "x2": -0.25}, "Enter"],
+            [{"d": true}, "decal", {"r": 15, "rx": 1, "ry": 2, "y": -1}, "A"],
+            ["B"]
+        ]"##;
+        let layout = PhysicalLayout::from_str(json).unwrap();
+        let names = layout
+            .keys
+            .iter()
+            .map(|k| k.logical_name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["K00", "K01", "K10", "K20"]);
+
+        let legends = &layout.keys[0].legends;
+        assert_eq!(legends.len(), 2);
+        assert_eq!((legends[0].position, legends[0].text.as_str()), (1, "!"));
+        assert_eq!((legends[1].position, legends[1].text.as_str()), (7, "1"));
+        assert_eq!(legends[1].color.unwrap().r, 0);
+
+        let enter = &layout.keys[1];
+        assert_eq!((enter.physical.x, enter.physical.w), (1.0, 1.25));
+        let physical2 = enter.physical2.unwrap();
+        assert_eq!((physical2.x, physical2.w, physical2.h), (0.75, 1.5, 2.0));
+
+        let a = &layout.keys[2];
+        assert_eq!(a.rotation, 15.0);
+        assert_eq!(a.rotation_origin, (1.0, -2.0));
+        assert_eq!((a.physical.x, a.physical.y), (1.0, -1.0));
+        assert!(a.physical2.is_none());
+
+        let b = &layout.keys[3];
+        assert_eq!((b.physical.x, b.physical.y), (1.0, -2.0));
+    }
 }
@@ -0,0 +1,107 @@
+use std::{collections::HashMap, fmt};
+
+use super::Layout;
+
+/// LED indices from this one on address layers, rather than keys
+const LAYER_LED_START: u8 = 0xf0;
+
+/// Inconsistency between the files of a layout
+#[derive(Clone, Debug, PartialEq)]
+pub enum LayoutIssue {
+    /// Key in `physical.json` has no electrical mapping in `layout.json`
+    MissingElectrical(String),
+    /// Two keys have the same electrical mapping in `layout.json`
+    DuplicateElectrical(String, String, (u8, u8)),
+    /// Key in `leds.json` isn't in `physical.json`
+    OrphanLed(String),
+    /// LED index in `leds.json` is reserved for layers
+    LedOutOfRange(String, u8),
+    /// Two keys have the same LED index in `leds.json`
+    DuplicateLed(String, String, u8),
+    /// Key in `default.json` isn't in `physical.json`
+    UnknownDefaultKey(String),
+}
+
+impl fmt::Display for LayoutIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingElectrical(key) => write!(f, "{} has no electrical mapping", key),
+            Self::DuplicateElectrical(a, b, (output, input)) => write!(
+                f,
+                "{} and {} are both mapped to {}, {}",
+                a, b, output, input
+            ),
+            Self::OrphanLed(key) => write!(f, "LEDs for {}, which isn't a key", key),
+            Self::LedOutOfRange(key, led) => write!(f, "LED {} of {} is out of range", led, key),
+            Self::DuplicateLed(a, b, led) => write!(f, "{} and {} both have LED {}", a, b, led),
+            Self::UnknownDefaultKey(key) => {
+                write!(f, "default keymap has {}, which isn't a key", key)
+            }
+        }
+    }
+}
+
+fn sorted_keys<V>(map: &HashMap<String, V>) -> Vec<&String> {
+    let mut keys = map.keys().collect::<Vec<_>>();
+    keys.sort();
+    keys
+}
+
+impl Layout {
+    /// Check that the files of this layout are consistent with each other
+    pub fn validate(&self) -> Result<(), Vec<LayoutIssue>> {
+        let mut issues = Vec::new();
+
+        let names = self
+            .physical
+            .keys
+            .iter()
+            .map(|key| key.logical_name())
+            .collect::<Vec<_>>();
+
+        let mut electricals = HashMap::new();
+        for name in &names {
+            match self.layout.get(name) {
+                Some(electrical) => {
+                    if let Some(other) = electricals.insert(*electrical, name) {
+                        issues.push(LayoutIssue::DuplicateElectrical(
+                            other.clone(),
+                            name.clone(),
+                            *electrical,
+                        ));
+                    }
+                }
+                None => issues.push(LayoutIssue::MissingElectrical(name.clone())),
+            }
+        }
+
+        let mut leds = HashMap::new();
+        for name in sorted_keys(&self.leds) {
+            if !names.contains(name) {
+                issues.push(LayoutIssue::OrphanLed(name.clone()));
+            }
+            for led in &self.leds[name] {
+                if *led >= LAYER_LED_START {
+                    issues.push(LayoutIssue::LedOutOfRange(name.clone(), *led));
+                } else if let Some(other) = leds.insert(*led, name) {
+                    issues.push(LayoutIssue::DuplicateLed(other.clone(), name.clone(), *led));
+                }
+            }
+        }
+
+        let default_keys = sorted_keys(&self.default.map)
+            .into_iter()
+            .chain(sorted_keys(&self.default.key_leds));
+        for name in default_keys {
+            if !names.contains(name) {
+                issues.push(LayoutIssue::UnknownDefaultKey(name.clone()));
+            }
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(issues)
+        }
+    }
+}
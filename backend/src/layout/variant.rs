@@ -0,0 +1,300 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    parse_json_keys, parse_json_map, read_layout_file, Layout, LayoutError, PhysicalLayout,
+};
+use crate::KeyMap;
+
+macro_rules! variants {
+    ($( ($keyboard:expr, $variant:expr, $file:expr) ),* $(,)?) => {
+        /// Files of built-in layout variants, as `(keyboard, variant, file, contents)`
+        const VARIANT_FILES: &[(&str, &str, &str, &str)] = &[
+            $(
+            (
+                $keyboard,
+                $variant,
+                $file,
+                include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../layouts/keyboards/", $keyboard, "/variants/", $variant, "/", $file)),
+            ),
+            )*
+        ];
+    };
+}
+
+// Calls the `variants!` macro
+include!(concat!(env!("OUT_DIR"), "/variants.rs"));
+
+/// Files of a layout variant, replacing those of the base layout. Only
+/// `physical.json` is required.
+struct VariantData {
+    physical_json: String,
+    layout_json: Option<String>,
+    leds_json: Option<String>,
+    labels_json: Option<String>,
+    default_json: Option<String>,
+}
+
+impl VariantData {
+    fn builtin(keyboard: &str, variant: &str) -> Option<Self> {
+        let file = |name: &str| {
+            VARIANT_FILES
+                .iter()
+                .find(|(k, v, f, _)| *k == keyboard && *v == variant && *f == name)
+                .map(|(_, _, _, contents)| contents.to_string())
+        };
+        Some(Self {
+            physical_json: file("physical.json")?,
+            layout_json: file("layout.json"),
+            leds_json: file("leds.json"),
+            labels_json: file("labels.json"),
+            default_json: file("default.json"),
+        })
+    }
+
+    fn from_dir(dir: &Path) -> Result<Self, LayoutError> {
+        let file = |name: &str| {
+            let path = dir.join(name);
+            if path.exists() {
+                read_layout_file(&path).map(Some)
+            } else {
+                Ok(None)
+            }
+        };
+        Ok(Self {
+            physical_json: read_layout_file(&dir.join("physical.json"))?,
+            layout_json: file("layout.json")?,
+            leds_json: file("leds.json")?,
+            labels_json: file("labels.json")?,
+            default_json: file("default.json")?,
+        })
+    }
+}
+
+/// Variants of a built-in keyboard
+pub(super) fn builtin_variants(keyboard: &str) -> Vec<String> {
+    VARIANT_FILES
+        .iter()
+        .filter(|(k, _, f, _)| *k == keyboard && *f == "physical.json")
+        .map(|(_, v, _, _)| v.to_string())
+        .collect()
+}
+
+/// Variants in the `variants` directory of a keyboard directory
+pub(super) fn dir_variants(keyboard_dir: &Path) -> Vec<String> {
+    let entries = match fs::read_dir(keyboard_dir.join("variants")) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut variants = entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().join("physical.json").exists())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    variants.sort();
+    variants
+}
+
+impl Layout {
+    /// Load the layout for `board`, with `variant` if it isn't `None`
+    pub fn from_board_variant(board: &str, variant: Option<&str>) -> Result<Self, LayoutError> {
+        let layout = Self::from_board(board)?;
+        match variant {
+            Some(variant) => layout.with_variant(variant),
+            None => Ok(layout),
+        }
+    }
+
+    /// Replace the physical layout, and any other files the variant has, with
+    /// those of `variant`, which must be one of `self.variants`
+    ///
+    /// If the variant has no `default.json`, the default keymap is converted
+    /// from the base layout, matching keys by their electrical mapping.
+    pub fn with_variant(self, variant: &str) -> Result<Self, LayoutError> {
+        if !self.variants.iter().any(|x| x == variant) {
+            return Err(LayoutError::UnknownVariant(variant.to_string()));
+        }
+        let data = match (&self.keyboard_dir, &self.meta.keyboard) {
+            (Some(dir), _) => VariantData::from_dir(&dir.join("variants").join(variant))?,
+            (None, Some(keyboard)) => VariantData::builtin(keyboard, variant)
+                .ok_or_else(|| LayoutError::UnknownVariant(variant.to_string()))?,
+            (None, None) => return Err(LayoutError::UnknownVariant(variant.to_string())),
+        };
+
+        let in_variant = |err: LayoutError| err.in_variant(variant);
+        let physical = PhysicalLayout::from_str(&data.physical_json).map_err(in_variant)?;
+        let (layout, layout_order) = match &data.layout_json {
+            Some(json) => (
+                parse_json_map("layout.json", json).map_err(in_variant)?,
+                parse_json_keys("layout.json", json).map_err(in_variant)?,
+            ),
+            None => (self.layout.clone(), self.layout_order.clone()),
+        };
+        let leds = match &data.leds_json {
+            Some(json) => parse_json_map("leds.json", json).map_err(in_variant)?,
+            None => self.leds.clone(),
+        };
+        let labels: HashMap<String, String> = match &data.labels_json {
+            Some(json) => parse_json_map("labels.json", json).map_err(in_variant)?,
+            None => HashMap::new(),
+        };
+        let mut scancodes = self.scancodes.clone();
+        for (name, label) in labels {
+            if let Some(scancode) = scancodes.get_mut(&name) {
+                scancode.label = label;
+            }
+        }
+        let default = match &data.default_json {
+            Some(json) => Some(
+                KeyMap::from_str(json)
+                    .map_err(|err| LayoutError::parse("default.json", None, err))
+                    .map_err(in_variant)?,
+            ),
+            None => None,
+        };
+
+        let mut variant_layout = Self {
+            meta: self.meta.clone(),
+            default: self.default.clone(),
+            scancodes,
+            scancode_names: self.scancode_names.clone(),
+            physical,
+            layout,
+            layout_order,
+            leds,
+            variant: Some(variant.to_string()),
+            variants: self.variants.clone(),
+            keyboard_dir: self.keyboard_dir.clone(),
+        };
+        variant_layout.default = match default {
+            Some(default) => default,
+            None => {
+                // Keys only in the variant have no scancode by default
+                let num_layers = self.meta.num_layers as usize;
+                variant_layout.default.map = variant_layout
+                    .physical
+                    .keys
+                    .iter()
+                    .map(|key| (key.logical_name(), vec!["NONE".to_string(); num_layers]))
+                    .collect();
+                variant_layout.default.key_leds.clear();
+                variant_layout.convert_keymap(&self, &self.default).keymap
+            }
+        };
+        variant_layout.default.variant = Some(variant.to_string());
+        Ok(variant_layout)
+    }
+}
+
+/// File storing the variant chosen for each board model
+fn variants_config_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_home.join("system76-keyboard-configurator/variants.json"))
+}
+
+fn read_variants_config(path: &Path) -> HashMap<String, String> {
+    match fs::read_to_string(path) {
+        Ok(json) => serde_json::from_str(&json).unwrap_or_else(|err| {
+            error!("Failed to parse {}: {}", path.display(), err);
+            HashMap::new()
+        }),
+        Err(_) => HashMap::new(),
+    }
+}
+
+/// Layout variant chosen for `board`, if any
+pub(crate) fn saved_variant(board: &str) -> Option<String> {
+    read_variants_config(&variants_config_path()?).remove(board)
+}
+
+/// Store the layout variant chosen for `board`, or the base layout for `None`
+pub(crate) fn save_variant(board: &str, variant: Option<&str>) -> Result<(), String> {
+    let path = variants_config_path().ok_or_else(|| "No config directory".to_string())?;
+    let mut variants = read_variants_config(&path);
+    match variant {
+        Some(variant) => variants.insert(board.to_string(), variant.to_string()),
+        None => variants.remove(board),
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|err| format!("Failed to create {}: {}", parent.display(), err))?;
+    }
+    let json = serde_json::to_string_pretty(&variants).unwrap();
+    fs::write(&path, json).map_err(|err| format!("Failed to write {}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_variant() {
+        let layouts = concat!(env!("CARGO_MANIFEST_DIR"), "/../layouts");
+        let dir = env::temp_dir().join(format!("layout_variant_{}", std::process::id()));
+        let variant_dir = dir.join("variants/de");
+        fs::create_dir_all(&variant_dir).unwrap();
+        for file in &["meta.json", "default.json"] {
+            fs::copy(
+                format!("{}/system76/launch_1/{}", layouts, file),
+                dir.join(file),
+            )
+            .unwrap();
+        }
+        for file in &["keymap.json", "layout.json", "leds.json"] {
+            let path = format!("{}/keyboards/system76/launch_1/{}", layouts, file);
+            fs::copy(path, dir.join(file)).unwrap();
+        }
+        let physical_json = fs::read_to_string(format!(
+            "{}/keyboards/system76/launch_1/physical.json",
+            layouts
+        ))
+        .unwrap();
+        fs::write(dir.join("physical.json"), &physical_json).unwrap();
+        // Y and Z are swapped on German keyboards
+        let physical_json = physical_json
+            .replace("\"Y\"", "\"_\"")
+            .replace("\"Z\"", "\"Y\"")
+            .replace("\"_\"", "\"Z\"");
+        fs::write(variant_dir.join("physical.json"), physical_json).unwrap();
+        fs::write(variant_dir.join("labels.json"), r#"{"Y": "Z", "Z": "Y"}"#).unwrap();
+
+        let base = Layout::from_dir(&dir).unwrap();
+        assert_eq!(base.variants, ["de"]);
+        assert_eq!(base.scancode("Y").unwrap().label, "Y");
+
+        let de = Layout::from_dir(&dir).unwrap().with_variant("de").unwrap();
+        assert_eq!(de.variant.as_deref(), Some("de"));
+        assert_eq!(de.scancode("Y").unwrap().label, "Z");
+        // Keys are matched by electrical mapping, not the legend on them
+        assert_eq!(de.default.map, base.default.map);
+        assert_eq!(de.default.variant.as_deref(), Some("de"));
+
+        assert!(matches!(
+            Layout::from_dir(&dir).unwrap().with_variant("jis"),
+            Err(LayoutError::UnknownVariant(_))
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn builtin_variant() {
+        let base = Layout::from_board("system76/launch_1").unwrap();
+        assert_eq!(base.variants, ["de"]);
+
+        let de = Layout::from_board_variant("system76/launch_1", Some("de")).unwrap();
+        assert_eq!(de.variant.as_deref(), Some("de"));
+        assert_eq!(de.scancode("Y").unwrap().label, "Z");
+        assert_eq!(de.scancode("SEMICOLON").unwrap().label, "Ö");
+        // Only legends differ, so keys keep their electrical mapping and defaults
+        assert_eq!(de.physical.keys.len(), base.physical.keys.len());
+        assert_eq!(de.layout, base.layout);
+        assert_eq!(de.default.map, base.default.map);
+    }
+}
@@ -17,9 +17,11 @@ extern crate log;
 mod backend;
 mod benchmark;
 mod board;
+mod capabilities;
 mod color;
 mod daemon;
 mod deref_cell;
+mod error;
 mod key;
 mod keymap;
 mod layer;
@@ -28,10 +30,13 @@ mod localize;
 mod matrix;
 mod mode;
 mod nelson;
+mod qmk;
 mod rect;
+mod scancode;
 
 use crate::daemon::*;
 pub use crate::{
-    backend::*, benchmark::*, board::*, color::*, deref_cell::*, key::*, keymap::*, layer::*,
-    layout::*, localize::*, matrix::*, mode::*, nelson::*, rect::*,
+    backend::*, benchmark::*, board::*, capabilities::*, color::*, deref_cell::*, error::*, key::*,
+    keymap::*, layer::*, layout::*, localize::*, matrix::*, mode::*, nelson::*, qmk::*, rect::*,
+    scancode::*,
 };
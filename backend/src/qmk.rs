@@ -0,0 +1,418 @@
+//! Conversion between `KeyMap` and QMK keymaps, as `keymap.json` from QMK
+//! Configurator or `keymap.c`
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::io::{Read, Write};
+
+use crate::{BackendError, KeyMap, Layout};
+
+include!("qmk_mapping.rs");
+
+/// Short aliases of QMK keycodes, without `KC_`, as defined in `keycode.h`
+const QMK_ALIASES: &[(&str, &str)] = &[
+    ("TRNS", "TRANSPARENT"),
+    ("ENT", "ENTER"),
+    ("ESC", "ESCAPE"),
+    ("BSPC", "BSPACE"),
+    ("SPC", "SPACE"),
+    ("MINS", "MINUS"),
+    ("EQL", "EQUAL"),
+    ("LBRC", "LBRACKET"),
+    ("RBRC", "RBRACKET"),
+    ("BSLS", "BSLASH"),
+    ("NUHS", "NONUS_HASH"),
+    ("SCLN", "SCOLON"),
+    ("QUOT", "QUOTE"),
+    ("GRV", "GRAVE"),
+    ("COMM", "COMMA"),
+    ("SLSH", "SLASH"),
+    ("NUBS", "NONUS_BSLASH"),
+    ("CAPS", "CAPSLOCK"),
+    ("PSCR", "PSCREEN"),
+    ("SLCK", "SCROLLLOCK"),
+    ("PAUS", "PAUSE"),
+    ("BRK", "PAUSE"),
+    ("INS", "INSERT"),
+    ("DEL", "DELETE"),
+    ("PGDN", "PGDOWN"),
+    ("RGHT", "RIGHT"),
+    ("NLCK", "NUMLOCK"),
+    ("PSLS", "KP_SLASH"),
+    ("PAST", "KP_ASTERISK"),
+    ("PMNS", "KP_MINUS"),
+    ("PPLS", "KP_PLUS"),
+    ("PENT", "KP_ENTER"),
+    ("P1", "KP_1"),
+    ("P2", "KP_2"),
+    ("P3", "KP_3"),
+    ("P4", "KP_4"),
+    ("P5", "KP_5"),
+    ("P6", "KP_6"),
+    ("P7", "KP_7"),
+    ("P8", "KP_8"),
+    ("P9", "KP_9"),
+    ("P0", "KP_0"),
+    ("PDOT", "KP_DOT"),
+    ("PCMM", "KP_COMMA"),
+    ("PEQL", "KP_EQUAL"),
+    ("APP", "APPLICATION"),
+    ("LCTL", "LCTRL"),
+    ("LSFT", "LSHIFT"),
+    ("LOPT", "LALT"),
+    ("LCMD", "LGUI"),
+    ("LWIN", "LGUI"),
+    ("RCTL", "RCTRL"),
+    ("RSFT", "RSHIFT"),
+    ("ROPT", "RALT"),
+    ("ALGR", "RALT"),
+    ("RCMD", "RGUI"),
+    ("RWIN", "RGUI"),
+    ("MUTE", "AUDIO_MUTE"),
+    ("VOLU", "AUDIO_VOL_UP"),
+    ("VOLD", "AUDIO_VOL_DOWN"),
+    ("MNXT", "MEDIA_NEXT_TRACK"),
+    ("MPRV", "MEDIA_PREV_TRACK"),
+    ("MSTP", "MEDIA_STOP"),
+    ("MPLY", "MEDIA_PLAY_PAUSE"),
+    ("SLEP", "SYSTEM_SLEEP"),
+];
+
+fn lookup<'a>(table: &[(&'a str, &'a str)], name: &str) -> Option<&'a str> {
+    table.iter().find(|(k, _)| *k == name).map(|(_, v)| *v)
+}
+
+fn reverse_lookup<'a>(table: &[(&'a str, &'a str)], name: &str) -> Option<&'a str> {
+    table.iter().find(|(_, v)| *v == name).map(|(k, _)| *k)
+}
+
+/// Scancode name for a QMK keycode, such as `BKSP` for `KC_BSPC`
+pub fn qmk_to_scancode_name(keycode: &str) -> String {
+    let keycode = match keycode.trim() {
+        "_______" => "KC_TRNS",
+        "XXXXXXX" => "KC_NO",
+        keycode => keycode,
+    };
+    let name = match keycode.strip_prefix("KC_") {
+        Some(name) => lookup(QMK_ALIASES, name).unwrap_or(name),
+        None => keycode,
+    };
+    lookup(QMK_MAPPING, name).unwrap_or(name).to_string()
+}
+
+/// QMK keycode for a scancode name, such as `KC_BSPACE` for `BKSP`
+pub fn scancode_name_to_qmk(name: &str) -> String {
+    let name = reverse_lookup(QMK_MAPPING, name).unwrap_or(name);
+    // Only basic keycodes have the `KC_` prefix
+    if name.contains('(') || name.starts_with("RGB_") || name == "RESET" {
+        name.to_string()
+    } else {
+        format!("KC_{}", name)
+    }
+}
+
+/// Keymap as exported by QMK Configurator
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct QmkKeyMap {
+    pub keyboard: String,
+    #[serde(default = "QmkKeyMap::default_keymap")]
+    pub keymap: String,
+    #[serde(default = "QmkKeyMap::default_layout")]
+    pub layout: String,
+    /// Keycodes of each layer, in the order of the `LAYOUT` macro's arguments
+    pub layers: Vec<Vec<String>>,
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub notes: String,
+}
+
+impl QmkKeyMap {
+    fn default_keymap() -> String {
+        "default".to_string()
+    }
+
+    fn default_layout() -> String {
+        "LAYOUT".to_string()
+    }
+
+    /// Parse `keymap.json` from QMK Configurator
+    pub fn from_reader<R: Read>(rdr: R) -> serde_json::Result<Self> {
+        serde_json::from_reader(rdr)
+    }
+
+    /// Parse `keymap.json` from QMK Configurator, as a string
+    pub fn from_str(s: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(s)
+    }
+
+    /// Write `keymap.json`, for QMK Configurator
+    pub fn to_writer_pretty<W: Write>(&self, wtr: W) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(wtr, self)
+    }
+
+    /// Parse the layers of `keymaps` in a `keymap.c`
+    ///
+    /// Only the arguments of each `LAYOUT` macro are read, so the source
+    /// is not preprocessed; keycodes defined by the keymap are not supported.
+    pub fn from_c(keyboard: &str, source: &str) -> Result<Self, BackendError> {
+        let source = strip_comments(source);
+        let mut layers = Vec::new();
+        let mut rest = source.as_str();
+        while let Some(start) = rest.find("LAYOUT") {
+            rest = &rest[start..];
+            let open = rest
+                .find('(')
+                .ok_or_else(|| invalid("LAYOUT without arguments".to_string()))?;
+            let (args, len) = macro_args(&rest[open + 1..])?;
+            layers.push(args);
+            rest = &rest[open + 1 + len..];
+        }
+        if layers.is_empty() {
+            return Err(invalid("No LAYOUT found in keymap".to_string()));
+        }
+        Ok(Self {
+            keyboard: keyboard.to_string(),
+            keymap: Self::default_keymap(),
+            layout: Self::default_layout(),
+            layers,
+            author: String::new(),
+            notes: String::new(),
+        })
+    }
+
+    /// Write a `keymap.c` defining the layers
+    pub fn to_c<W: Write>(&self, mut wtr: W) -> std::io::Result<()> {
+        writeln!(wtr, "#include QMK_KEYBOARD_H")?;
+        writeln!(wtr)?;
+        writeln!(
+            wtr,
+            "const uint16_t PROGMEM keymaps[][MATRIX_ROWS][MATRIX_COLS] = {{"
+        )?;
+        for (i, layer) in self.layers.iter().enumerate() {
+            writeln!(wtr, "    [{}] = {}(", i, self.layout)?;
+            for (j, chunk) in layer.chunks(8).enumerate() {
+                let comma = if (j + 1) * 8 < layer.len() { "," } else { "" };
+                writeln!(wtr, "        {}{}", chunk.join(", "), comma)?;
+            }
+            writeln!(wtr, "    ),")?;
+        }
+        writeln!(wtr, "}};")
+    }
+}
+
+fn strip_comments(source: &str) -> String {
+    let mut stripped = String::with_capacity(source.len());
+    let mut rest = source;
+    loop {
+        let line = rest.find("//");
+        let block = rest.find("/*");
+        let (start, end) = match (line, block) {
+            (Some(l), Some(b)) if l < b => (l, "\n"),
+            (Some(l), None) => (l, "\n"),
+            (_, Some(b)) => (b, "*/"),
+            (None, None) => break,
+        };
+        stripped.push_str(&rest[..start]);
+        rest = &rest[start + 2..];
+        match rest.find(end) {
+            Some(i) => rest = &rest[i + end.len()..],
+            None => rest = "",
+        }
+        stripped.push(' ');
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+/// Split macro arguments at top-level commas, returning them and the length
+/// consumed, including the closing parenthesis
+fn macro_args(source: &str) -> Result<(Vec<String>, usize), BackendError> {
+    let mut args = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in source.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            ',' | ')' if depth == 0 => {
+                let arg: String = source[start..i].split_whitespace().collect();
+                if !arg.is_empty() {
+                    args.push(arg);
+                }
+                start = i + 1;
+                if c == ')' {
+                    return Ok((args, i + 1));
+                }
+            }
+            _ => {}
+        }
+    }
+    Err(invalid("Unterminated LAYOUT in keymap".to_string()))
+}
+
+/// Error for a keymap that can't be parsed or converted
+fn invalid(err: String) -> BackendError {
+    BackendError::InvalidArgument(err)
+}
+
+impl KeyMap {
+    /// Convert from QMK keymap, using `layout` for the keyboard
+    ///
+    /// Keycodes are in the order of the `LAYOUT` macro, as in the layout's
+    /// `layout.json`. LED settings are taken from the layout's default keymap.
+    pub fn from_qmk(qmk: &QmkKeyMap, layout: &Layout) -> Result<Self, BackendError> {
+        let keys = &layout.layout_order;
+        let num_layers = layout.meta.num_layers as usize;
+        if qmk.layers.len() > num_layers {
+            return Err(invalid(format!(
+                "Keymap has {} layers, but {} supports {}",
+                qmk.layers.len(),
+                qmk.keyboard,
+                num_layers
+            )));
+        }
+
+        let mut keymap = layout.default.clone();
+        keymap.model = qmk.keyboard.clone();
+        keymap.name = Some(qmk.keymap.clone());
+        if !qmk.notes.is_empty() {
+            keymap.description = Some(qmk.notes.clone());
+        }
+        keymap.created = None;
+        keymap.firmware_version = None;
+
+        let mut unsupported = BTreeSet::new();
+        for (layer, keycodes) in qmk.layers.iter().enumerate() {
+            if keycodes.len() != keys.len() {
+                return Err(invalid(format!(
+                    "Layer {} has {} keys, but {} has {}",
+                    layer,
+                    keycodes.len(),
+                    qmk.keyboard,
+                    keys.len()
+                )));
+            }
+            for (key, keycode) in keys.iter().zip(keycodes.iter()) {
+                let name = qmk_to_scancode_name(keycode);
+                if layout.scancode_from_name(&name).is_none() {
+                    unsupported.insert(keycode.clone());
+                    continue;
+                }
+                if let Some(scancodes) = keymap.map.get_mut(key) {
+                    if let Some(scancode) = scancodes.get_mut(layer) {
+                        *scancode = name;
+                    }
+                }
+            }
+        }
+
+        if !unsupported.is_empty() {
+            let unsupported = unsupported.into_iter().collect::<Vec<_>>();
+            return Err(invalid(format!(
+                "Unsupported keycodes: {}",
+                unsupported.join(", ")
+            )));
+        }
+        Ok(keymap)
+    }
+
+    /// Convert to QMK keymap, using `layout` for the keyboard
+    ///
+    /// Keycodes are in the order of the `LAYOUT` macro, as in `from_qmk`.
+    pub fn to_qmk(&self, layout: &Layout) -> QmkKeyMap {
+        let num_layers = layout.meta.num_layers as usize;
+        let layers = (0..num_layers)
+            .map(|layer| {
+                layout
+                    .layout_order
+                    .iter()
+                    .map(|key| {
+                        self.map
+                            .get(key)
+                            .and_then(|scancodes| scancodes.get(layer))
+                            .map_or_else(|| "KC_NO".to_string(), |x| scancode_name_to_qmk(x))
+                    })
+                    .collect()
+            })
+            .collect();
+        QmkKeyMap {
+            keyboard: self.model.clone(),
+            keymap: self.name.clone().unwrap_or_else(QmkKeyMap::default_keymap),
+            layout: QmkKeyMap::default_layout(),
+            layers,
+            author: String::new(),
+            notes: self.description.clone().unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn qmk_keycodes() {
+        assert_eq!(qmk_to_scancode_name("KC_BSPC"), "BKSP");
+        assert_eq!(qmk_to_scancode_name("KC_BSPACE"), "BKSP");
+        assert_eq!(qmk_to_scancode_name("_______"), "ROLL_OVER");
+        assert_eq!(qmk_to_scancode_name("MO(1)"), "FN");
+        assert_eq!(qmk_to_scancode_name("KC_A"), "A");
+        assert_eq!(scancode_name_to_qmk("BKSP"), "KC_BSPACE");
+        assert_eq!(scancode_name_to_qmk("FN"), "MO(1)");
+        assert_eq!(scancode_name_to_qmk("RESET"), "RESET");
+    }
+
+    #[test]
+    fn qmk_round_trip() {
+        let layout = Layout::from_board("system76/launch_1").unwrap();
+        let qmk = layout.default.to_qmk(&layout);
+        let keymap = KeyMap::from_qmk(&qmk, &layout).unwrap();
+        assert_eq!(keymap.map, layout.default.map);
+
+        let mut c = Vec::new();
+        qmk.to_c(&mut c).unwrap();
+        let from_c = QmkKeyMap::from_c(&qmk.keyboard, &String::from_utf8(c).unwrap()).unwrap();
+        assert_eq!(from_c.layers, qmk.layers);
+    }
+
+    #[test]
+    fn qmk_layout_order() {
+        let layouts = concat!(env!("CARGO_MANIFEST_DIR"), "/../layouts");
+        let file = |path: &str| std::fs::read_to_string(format!("{}/{}", layouts, path)).unwrap();
+        let keyboard = "keyboards/system76/launch_1";
+        let layout_json = file(&format!("{}/layout.json", keyboard));
+        let layout = |layout_json: &str| {
+            Layout::from_data(
+                &file("system76/launch_1/meta.json"),
+                &file("system76/launch_1/default.json"),
+                &file(&format!("{}/keymap.json", keyboard)),
+                layout_json,
+                &file(&format!("{}/leds.json", keyboard)),
+                &file(&format!("{}/physical.json", keyboard)),
+            )
+            .unwrap()
+        };
+
+        // The default layer of the Launch's `keymap.c` starts with Esc, F1, F2
+        let base = layout(&layout_json);
+        let qmk = base.default.to_qmk(&base);
+        assert_eq!(&qmk.layers[0][..3], &["KC_ESCAPE", "KC_F1", "KC_F2"]);
+
+        // Keycodes follow `layout.json`, not the physical layout
+        let json: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(&layout_json).unwrap();
+        let reversed = json
+            .iter()
+            .rev()
+            .map(|(key, value)| format!("{:?}: {}", key, value))
+            .collect::<Vec<_>>();
+        let reversed = layout(&format!("{{{}}}", reversed.join(", ")));
+        let mut layer = qmk.layers[0].clone();
+        layer.reverse();
+        assert_eq!(reversed.default.to_qmk(&reversed).layers[0], layer);
+        let keymap = KeyMap::from_qmk(&qmk, &reversed).unwrap();
+        assert_eq!(keymap.map["K00"][0], "RIGHT");
+        assert_eq!(keymap.map["K5B"][0], "ESC");
+    }
+}
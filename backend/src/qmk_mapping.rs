@@ -0,0 +1,73 @@
+// Shared by the backend's QMK conversion and the `layouts` tool, with
+// `include!`, so both map keycodes the same way.
+
+/// QMK keycodes, without `KC_`, that have a different scancode name in the
+/// Configurator
+const QMK_MAPPING: &[(&str, &str)] = &[
+    ("APPLICATION", "APP"),
+    ("AUDIO_MUTE", "MUTE"),
+    ("AUDIO_VOL_DOWN", "VOLUME_DOWN"),
+    ("AUDIO_VOL_UP", "VOLUME_UP"),
+    ("BSLASH", "BACKSLASH"),
+    ("BSPACE", "BKSP"),
+    ("CAPSLOCK", "CAPS"),
+    ("DELETE", "DEL"),
+    ("DOT", "PERIOD"),
+    ("EQUAL", "EQUALS"),
+    ("ESCAPE", "ESC"),
+    ("GRAVE", "TICK"),
+    ("KP_0", "NUM_0"),
+    ("KP_1", "NUM_1"),
+    ("KP_2", "NUM_2"),
+    ("KP_3", "NUM_3"),
+    ("KP_4", "NUM_4"),
+    ("KP_5", "NUM_5"),
+    ("KP_6", "NUM_6"),
+    ("KP_7", "NUM_7"),
+    ("KP_8", "NUM_8"),
+    ("KP_9", "NUM_9"),
+    ("KP_ASTERISK", "NUM_ASTERISK"),
+    ("KP_COMMA", "NUM_COMMA"),
+    ("KP_DOT", "NUM_PERIOD"),
+    ("KP_ENTER", "NUM_ENTER"),
+    ("KP_EQUAL", "NUM_EQUALS"),
+    ("KP_MINUS", "NUM_MINUS"),
+    ("KP_PLUS", "NUM_PLUS"),
+    ("KP_SLASH", "NUM_SLASH"),
+    ("LALT", "LEFT_ALT"),
+    ("LBRACKET", "BRACE_OPEN"),
+    ("LCTRL", "LEFT_CTRL"),
+    ("LGUI", "LEFT_SUPER"),
+    ("LSHIFT", "LEFT_SHIFT"),
+    ("NO", "NONE"),
+    ("MEDIA_NEXT_TRACK", "MEDIA_NEXT"),
+    ("MEDIA_PLAY_PAUSE", "PLAY_PAUSE"),
+    ("MEDIA_PREV_TRACK", "MEDIA_PREV"),
+    ("NUMLOCK", "NUM_LOCK"),
+    ("PGDOWN", "PGDN"),
+    ("PSCREEN", "PRINT_SCREEN"),
+    ("RALT", "RIGHT_ALT"),
+    ("RBRACKET", "BRACE_CLOSE"),
+    ("RCTRL", "RIGHT_CTRL"),
+    ("RGB_TOG", "KBD_TOGGLE"),
+    ("RGB_VAD", "KBD_DOWN"),
+    ("RGB_VAI", "KBD_UP"),
+    ("RGUI", "RIGHT_SUPER"),
+    ("RSHIFT", "RIGHT_SHIFT"),
+    ("SCOLON", "SEMICOLON"),
+    ("SCROLLLOCK", "SCROLL_LOCK"),
+    ("SYSTEM_SLEEP", "SUSPEND"),
+    ("TRANSPARENT", "ROLL_OVER"),
+    ("TG(0)", "LAYER_TOGGLE_1"),
+    ("TG(1)", "LAYER_TOGGLE_2"),
+    ("TG(2)", "LAYER_TOGGLE_3"),
+    ("TG(3)", "LAYER_TOGGLE_4"),
+    ("TO(0)", "LAYER_SWITCH_1"),
+    ("TO(1)", "LAYER_SWITCH_2"),
+    ("TO(2)", "LAYER_SWITCH_3"),
+    ("TO(3)", "LAYER_SWITCH_4"),
+    ("MO(0)", "LAYER_ACCESS_1"),
+    ("MO(1)", "FN"),
+    ("MO(2)", "LAYER_ACCESS_3"),
+    ("MO(3)", "LAYER_ACCESS_4"),
+];
@@ -0,0 +1,146 @@
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Kind of action a scancode performs
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScancodeCategory {
+    Alphabet,
+    Number,
+    Modifier,
+    Action,
+    Function,
+    Numpad,
+    Symbol,
+    Navigation,
+    Media,
+    /// Laptop controls, like display brightness or airplane mode
+    Control,
+    /// Keyboard backlight controls
+    Led,
+    /// Accesses, switches to, or toggles a layer
+    Layer,
+    Mouse,
+    International,
+    Other,
+}
+
+/// USB HID usage sent by a scancode
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(from = "(u16, u16)")]
+pub struct HidUsage {
+    /// Usage page, such as `0x07` for keyboard keys or `0x0C` for consumer controls
+    pub page: u16,
+    pub id: u16,
+}
+
+impl From<(u16, u16)> for HidUsage {
+    fn from((page, id): (u16, u16)) -> Self {
+        Self { page, id }
+    }
+}
+
+/// Metadata of a scancode in `layouts/scancodes.json`
+#[derive(Debug, Deserialize)]
+struct ScancodeMeta {
+    name: String,
+    category: ScancodeCategory,
+    label: String,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    hid_usage: Option<HidUsage>,
+    #[serde(default)]
+    layer: Option<u8>,
+}
+
+static SCANCODE_META: Lazy<HashMap<String, ScancodeMeta>> = Lazy::new(|| {
+    let json = include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../layouts/scancodes.json"
+    ));
+    serde_json::from_str::<Vec<ScancodeMeta>>(json)
+        .expect("Failed to parse scancodes.json")
+        .into_iter()
+        .map(|meta| (meta.name.clone(), meta))
+        .collect()
+});
+
+/// Scancode supported by a keyboard
+#[derive(Clone, Debug)]
+pub struct Scancode {
+    /// Name, as used in `keymap.json` and keymaps, such as `LEFT_CTRL`
+    pub name: String,
+    /// Value used by the keyboard's firmware
+    pub value: u16,
+    pub category: ScancodeCategory,
+    /// Label shown in the picker and on keys, which may have several lines
+    pub label: String,
+    pub description: Option<String>,
+    /// Usage the keyboard sends, if it sends one over USB HID
+    pub hid_usage: Option<HidUsage>,
+    /// Layer the scancode accesses, switches to, or toggles, starting at 0
+    pub layer: Option<u8>,
+}
+
+impl Scancode {
+    /// Scancode with metadata from `layouts/scancodes.json`
+    ///
+    /// Scancodes missing from it are labeled with their name, in
+    /// `ScancodeCategory::Other`.
+    pub fn new(name: &str, value: u16) -> Self {
+        match SCANCODE_META.get(name) {
+            Some(meta) => Self {
+                name: name.to_string(),
+                value,
+                category: meta.category,
+                label: meta.label.clone(),
+                description: meta.description.clone(),
+                hid_usage: meta.hid_usage,
+                layer: meta.layer,
+            },
+            None => Self {
+                name: name.to_string(),
+                value,
+                category: ScancodeCategory::Other,
+                label: name.to_string(),
+                description: None,
+                hid_usage: None,
+                layer: None,
+            },
+        }
+    }
+
+    /// Whether `layouts/scancodes.json` has metadata for the scancode `name`
+    pub fn is_known(name: &str) -> bool {
+        SCANCODE_META.contains_key(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scancode_meta() {
+        let fn_ = Scancode::new("FN", 0x5101);
+        assert_eq!(fn_.category, ScancodeCategory::Layer);
+        assert_eq!(fn_.layer, Some(1));
+        assert_eq!(fn_.value, 0x5101);
+
+        let mute = Scancode::new("MUTE", 0xa8);
+        assert_eq!(mute.category, ScancodeCategory::Media);
+        assert_eq!(
+            mute.hid_usage,
+            Some(HidUsage {
+                page: 0x0c,
+                id: 0xe2
+            })
+        );
+
+        let unknown = Scancode::new("NOT_A_SCANCODE", 0);
+        assert_eq!(unknown.category, ScancodeCategory::Other);
+        assert_eq!(unknown.label, "NOT_A_SCANCODE");
+    }
+}
@@ -0,0 +1,568 @@
+#[macro_use]
+extern crate log;
+
+use backend::{Backend, Board, Hs, Key, KeyMap, KeyMapDiff, Layer, Layout, Mode, QmkKeyMap, Rgb};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::{
+    env,
+    fs::File,
+    io::{self, Read, Write},
+    process,
+};
+
+fn app() -> App<'static, 'static> {
+    let format_arg = Arg::with_name("format")
+        .long("format")
+        .short("f")
+        .takes_value(true)
+        .possible_values(&["json", "qmk-json", "qmk-c"])
+        .default_value("json")
+        .help("Keymap file format; QMK Configurator `keymap.json`, or QMK `keymap.c`");
+
+    let layer_arg = Arg::with_name("layer")
+        .long("layer")
+        .short("l")
+        .takes_value(true)
+        .value_name("LAYER")
+        .help("Only change this layer, instead of all layers");
+
+    App::new("keyboard-configurator-cli")
+        .about("Configure System76 keyboards from the command line")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(
+            Arg::with_name("board")
+                .long("board")
+                .short("b")
+                .takes_value(true)
+                .value_name("INDEX")
+                .global(true)
+                .help("Keyboard to configure, as numbered by `list`"),
+        )
+        .arg(
+            Arg::with_name("fake")
+                .long("fake")
+                .takes_value(true)
+                .value_name("MODELS")
+                .global(true)
+                .help("Use fake keyboards of the comma separated models, for testing"),
+        )
+        .subcommand(SubCommand::with_name("list").about("List connected keyboards"))
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Export keymap and LED settings as JSON")
+                .arg(format_arg.clone())
+                .arg(Arg::with_name("FILE").help("File to write; standard output if omitted")),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Import keymap and LED settings from JSON")
+                .arg(format_arg)
+                .arg(
+                    Arg::with_name("FILE")
+                        .required(true)
+                        .help("File to read; `-` for standard input"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Show changes importing a keymap would make, or between two keymaps")
+                .arg(
+                    Arg::with_name("FILE")
+                        .required(true)
+                        .help("File to read; `-` for standard input"),
+                )
+                .arg(Arg::with_name("OTHER").help("File to compare with, instead of the board")),
+        )
+        .subcommand(SubCommand::with_name("reset").about("Reset keymap and LEDs to defaults"))
+        .subcommand(
+            SubCommand::with_name("set-key")
+                .about("Set the scancode of a key on a layer")
+                .arg(
+                    Arg::with_name("KEY")
+                        .required(true)
+                        .help("Logical name of key, such as K0A"),
+                )
+                .arg(Arg::with_name("LAYER").required(true).help("Layer number"))
+                .arg(
+                    Arg::with_name("SCANCODE")
+                        .required(true)
+                        .help("Scancode name, such as ESC"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("set-color")
+                .about("Set LED color of layers or a single key")
+                .arg(layer_arg.clone())
+                .arg(
+                    Arg::with_name("key")
+                        .long("key")
+                        .short("k")
+                        .takes_value(true)
+                        .value_name("KEY")
+                        .conflicts_with("layer")
+                        .help("Set color of this key instead, for per-key mode"),
+                )
+                .arg(
+                    Arg::with_name("COLOR")
+                        .required(true)
+                        .help("Color as #rrggbb, or `none` to clear a key's color"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("set-brightness")
+                .about("Set LED brightness")
+                .arg(layer_arg.clone())
+                .arg(Arg::with_name("BRIGHTNESS").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("set-mode")
+                .about("Set LED mode, such as SOLID_COLOR or CYCLE_ALL")
+                .arg(layer_arg)
+                .arg(Arg::with_name("MODE").required(true))
+                .arg(Arg::with_name("SPEED").help("Animation speed, from 0 to 255")),
+        )
+}
+
+#[cfg(target_os = "linux")]
+fn daemon() -> Result<Backend, String> {
+    if unsafe { libc::geteuid() == 0 } {
+        info!("Already running as root");
+        Backend::new()
+    } else {
+        Backend::new_socket().or_else(|err| {
+            info!("{}; spawning daemon with pkexec", err);
+            Backend::new_pkexec()
+        })
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn daemon() -> Result<Backend, String> {
+    Backend::new()
+}
+
+fn parse_index(name: &str, value: &str, len: usize) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(index) if index < len => Ok(index),
+        _ => Err(format!(
+            "Invalid {} '{}', expected 0 to {}",
+            name,
+            value,
+            len.saturating_sub(1)
+        )),
+    }
+}
+
+/// Parse a color argument; `None` clears the color of a key
+fn parse_color(value: &str) -> Result<Option<Hs>, String> {
+    if value.eq_ignore_ascii_case("none") {
+        Ok(None)
+    } else {
+        Rgb::parse(value)
+            .map(|rgb| Some(rgb.to_hs_lossy()))
+            .ok_or_else(|| format!("Invalid color '{}', expected #rrggbb", value))
+    }
+}
+
+fn select_board(boards: &[Board], index: Option<&str>) -> Result<&Board, String> {
+    match (boards.len(), index) {
+        (0, _) => Err("No keyboard found".to_string()),
+        (_, Some(index)) => Ok(&boards[parse_index("board", index, boards.len())?]),
+        (1, None) => Ok(&boards[0]),
+        (_, None) => Err("Multiple keyboards found; choose one with --board".to_string()),
+    }
+}
+
+fn find_key<'a>(board: &'a Board, name: &str) -> Result<&'a Key, String> {
+    board
+        .keys()
+        .iter()
+        .find(|key| key.logical_name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| format!("No key named '{}' on {}", name, board.model()))
+}
+
+/// Layers selected by `--layer`, or all layers
+fn find_layers<'a>(board: &'a Board, matches: &ArgMatches) -> Result<Vec<&'a Layer>, String> {
+    let layers = board.layers();
+    match matches.value_of("layer") {
+        Some(layer) => Ok(vec![&layers[parse_index("layer", layer, layers.len())?]]),
+        None => Ok(layers.iter().collect()),
+    }
+}
+
+/// Read keymap in the QMK `format`, for `board`
+fn read_qmk_keymap(board: &Board, path: &str, format: &str) -> Result<KeyMap, String> {
+    let mut data = String::new();
+    let res = if path == "-" {
+        io::stdin().read_to_string(&mut data)
+    } else {
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut data))
+            .map_err(|err| io::Error::new(err.kind(), format!("'{}': {}", path, err)))
+    };
+    res.map_err(|err| format!("Failed to read keymap: {}", err))?;
+
+    let qmk = if format == "qmk-c" {
+        QmkKeyMap::from_c(board.model(), &data).map_err(|err| err.to_string())?
+    } else {
+        QmkKeyMap::from_str(&data).map_err(|err| format!("Failed to parse keymap: {}", err))?
+    };
+    let layout = Layout::from_board(&qmk.keyboard).map_err(|err| err.to_string())?;
+    KeyMap::from_qmk(&qmk, &layout).map_err(|err| err.to_string())
+}
+
+/// Write keymap of `board` in the QMK `format`
+fn write_qmk_keymap(board: &Board, path: Option<&str>, format: &str) -> Result<(), String> {
+    let qmk = board.export_keymap().to_qmk(board.layout());
+    let mut data = Vec::new();
+    if format == "qmk-c" {
+        qmk.to_c(&mut data).map_err(|err| err.to_string())
+    } else {
+        qmk.to_writer_pretty(&mut data)
+            .map(|()| data.push(b'\n'))
+            .map_err(|err| err.to_string())
+    }
+    .map_err(|err| format!("Failed to export keymap: {}", err))?;
+
+    match path {
+        Some(path) => File::create(path).and_then(|mut file| file.write_all(&data)),
+        None => io::stdout().write_all(&data),
+    }
+    .map_err(|err| format!("Failed to write keymap: {}", err))
+}
+
+fn read_keymap(path: &str) -> Result<KeyMap, String> {
+    let res = if path == "-" {
+        KeyMap::from_reader(io::stdin())
+    } else {
+        let file = File::open(path).map_err(|err| format!("Failed to open '{}': {}", path, err))?;
+        KeyMap::from_reader(file)
+    };
+    res.map_err(|err| format!("Failed to parse keymap: {}", err))
+}
+
+fn write_keymap(keymap: &KeyMap, path: Option<&str>) -> Result<(), String> {
+    match path {
+        Some(path) => {
+            let file = File::create(path)
+                .map_err(|err| format!("Failed to create '{}': {}", path, err))?;
+            keymap.to_writer_pretty(file)
+        }
+        None => keymap.to_writer_pretty(io::stdout()).map(|()| println!()),
+    }
+    .map_err(|err| format!("Failed to export keymap: {}", err))
+}
+
+/// Convert keymap from another model, warning about keys that don't fit
+fn convert_keymap(board: &Board, keymap: &KeyMap) -> Result<KeyMap, String> {
+    let conversion = board
+        .convert_keymap(keymap)
+        .map_err(|err| err.to_string())?;
+    eprintln!("Converting keymap from {}", keymap.model);
+    for key in &conversion.unplaced {
+        eprintln!("Warning: {} has no matching key", key);
+    }
+    for (key, layer, scancode) in &conversion.unsupported {
+        eprintln!(
+            "Warning: {} is not supported, for {} on layer {}",
+            scancode, key, layer
+        );
+    }
+    Ok(conversion.keymap)
+}
+
+fn print_diff(diff: &KeyMapDiff) {
+    if diff.is_empty() {
+        println!("No changes");
+    } else {
+        print!("{}", diff);
+    }
+}
+
+async fn apply_keymap(board: &Board, keymap: &KeyMap) -> Result<(), String> {
+    let report = board.apply_keymap(keymap).await;
+    if report.error.is_none() {
+        eprintln!(
+            "Changed {} scancodes, {} key LEDs, and {} layers",
+            report.scancodes, report.key_leds, report.layers
+        );
+    } else if report.rolled_back {
+        eprintln!("Restored previous keymap");
+    }
+    report.error_message().map_or(Ok(()), Err)
+}
+
+async fn set_key(board: &Board, matches: &ArgMatches<'_>) -> Result<(), String> {
+    let key = find_key(board, matches.value_of("KEY").unwrap())?;
+    let layer = parse_index(
+        "layer",
+        matches.value_of("LAYER").unwrap(),
+        board.layout().meta.num_layers as usize,
+    )?;
+    key.set_scancode(layer, matches.value_of("SCANCODE").unwrap())
+        .await
+        .map_err(|err| err.to_string())?;
+    Ok(())
+}
+
+async fn set_color(board: &Board, matches: &ArgMatches<'_>) -> Result<(), String> {
+    let color = parse_color(matches.value_of("COLOR").unwrap())?;
+    if let Some(name) = matches.value_of("key") {
+        let key = find_key(board, name)?;
+        if key.leds.is_empty() {
+            return Err(format!("Key '{}' has no LED", key.logical_name));
+        }
+        key.set_color(color).await.map_err(|err| err.to_string())?;
+        Ok(())
+    } else {
+        let color = color.ok_or_else(|| "Layers require a color".to_string())?;
+        for layer in find_layers(board, matches)? {
+            layer
+                .set_color(color)
+                .await
+                .map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+async fn set_brightness(board: &Board, matches: &ArgMatches<'_>) -> Result<(), String> {
+    let value = matches.value_of("BRIGHTNESS").unwrap();
+    let brightness = match value.parse::<i32>() {
+        Ok(brightness) if brightness >= 0 && brightness <= board.max_brightness() => brightness,
+        _ => {
+            return Err(format!(
+                "Invalid brightness '{}', expected 0 to {}",
+                value,
+                board.max_brightness()
+            ))
+        }
+    };
+    for layer in find_layers(board, matches)? {
+        layer
+            .set_brightness(brightness)
+            .await
+            .map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+
+async fn set_mode(board: &Board, matches: &ArgMatches<'_>) -> Result<(), String> {
+    let id = matches.value_of("MODE").unwrap();
+    let mode = Mode::from_id(&id.to_uppercase()).ok_or_else(|| {
+        let ids = Mode::all().iter().map(|i| i.id).collect::<Vec<_>>();
+        format!("Unknown mode '{}', expected one of {}", id, ids.join(", "))
+    })?;
+    let speed = match matches.value_of("SPEED") {
+        Some(speed) => Some(
+            speed
+                .parse::<u8>()
+                .map_err(|_| format!("Invalid speed '{}', expected 0 to 255", speed))?,
+        ),
+        None => None,
+    };
+    for layer in find_layers(board, matches)? {
+        let current_speed = match layer.mode() {
+            Some((_, speed)) => speed,
+            None => return Err(format!("{} does not support LED modes", board.model())),
+        };
+        layer
+            .set_mode(mode, speed.unwrap_or(current_speed))
+            .await
+            .map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+
+async fn run_command(board: &Board, name: &str, matches: &ArgMatches<'_>) -> Result<(), String> {
+    match name {
+        "export" => match matches.value_of("format").unwrap() {
+            "json" => write_keymap(&board.export_keymap(), matches.value_of("FILE")),
+            format => write_qmk_keymap(board, matches.value_of("FILE"), format),
+        },
+        "import" => {
+            let path = matches.value_of("FILE").unwrap();
+            let mut keymap = match matches.value_of("format").unwrap() {
+                "json" => read_keymap(path)?,
+                format => read_qmk_keymap(board, path, format)?,
+            };
+            if !board.matches_keymap(&keymap) {
+                keymap = convert_keymap(board, &keymap)?;
+            }
+            apply_keymap(board, &keymap).await
+        }
+        "reset" => apply_keymap(board, &board.layout().default).await,
+        "set-key" => set_key(board, matches).await,
+        "set-color" => set_color(board, matches).await,
+        "set-brightness" => set_brightness(board, matches).await,
+        "set-mode" => set_mode(board, matches).await,
+        _ => unreachable!(),
+    }?;
+
+    // Persist LED changes on boards that support it
+    board.led_save().await.map_err(|err| err.to_string())?;
+    Ok(())
+}
+
+fn run(matches: &ArgMatches) -> Result<(), String> {
+    let (name, matches) = match matches.subcommand() {
+        (name, Some(matches)) => (name, matches),
+        _ => unreachable!(),
+    };
+
+    // Comparing two files doesn't need a board
+    if let ("diff", Some(other)) = (name, matches.value_of("OTHER")) {
+        let keymap = read_keymap(matches.value_of("FILE").unwrap())?;
+        print_diff(&keymap.diff(&read_keymap(other)?));
+        return Ok(());
+    }
+
+    let backend = match matches.value_of("fake") {
+        Some(models) => Backend::new_dummy(models.split(',').map(str::to_string).collect()),
+        None => daemon(),
+    }?;
+
+    let context = glib::MainContext::default();
+    context
+        .block_on(backend.refresh_future())
+        .map_err(|err| err.to_string())?;
+    // Dispatch `board-added` signals
+    while context.iteration(false) {}
+    let boards = backend.boards();
+
+    if name == "list" {
+        for (i, board) in boards.iter().enumerate() {
+            println!("{}: {} {}", i, board.model(), board.version());
+        }
+        return Ok(());
+    }
+
+    let board = select_board(&boards, matches.value_of("board"))?;
+
+    if name == "diff" {
+        let keymap = read_keymap(matches.value_of("FILE").unwrap())?;
+        print_diff(&board.diff_keymap(&keymap).map_err(|err| err.to_string())?);
+        return Ok(());
+    }
+
+    context.block_on(run_command(board, name, matches))
+}
+
+fn main() {
+    env_logger::Builder::from_env(
+        env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "warn"),
+    )
+    .format_timestamp(None)
+    .format_module_path(false)
+    .init();
+
+    // Used when spawned by `Backend::new_pkexec`, or as a service
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--daemon" => backend::run_daemon(),
+            #[cfg(target_os = "linux")]
+            "--daemon-socket" => backend::run_daemon_socket(),
+            _ => {}
+        }
+    }
+
+    if let Err(err) = run(&app().get_matches()) {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn run_fake(args: &[&str]) -> Result<(), String> {
+        let mut argv = vec!["cli", "--fake", "system76/launch_1"];
+        argv.extend_from_slice(args);
+        run(&app().get_matches_from_safe(argv).unwrap())
+    }
+
+    #[test]
+    fn parse_args() {
+        let matches = app()
+            .get_matches_from_safe(vec![
+                "cli",
+                "set-color",
+                "--layer",
+                "1",
+                "#ff0000",
+                "-b",
+                "0",
+            ])
+            .unwrap();
+        let (name, matches) = matches.subcommand();
+        let matches = matches.unwrap();
+        assert_eq!(name, "set-color");
+        assert_eq!(matches.value_of("layer"), Some("1"));
+        assert_eq!(matches.value_of("board"), Some("0"));
+        assert_eq!(matches.value_of("COLOR"), Some("#ff0000"));
+
+        assert!(app()
+            .get_matches_from_safe(vec![
+                "cli",
+                "set-color",
+                "--layer",
+                "0",
+                "--key",
+                "K00",
+                "none"
+            ])
+            .is_err());
+        assert!(app()
+            .get_matches_from_safe(vec!["cli", "set-key", "K00"])
+            .is_err());
+    }
+
+    #[test]
+    fn parse_values() {
+        assert_eq!(parse_color("none"), Ok(None));
+        assert_eq!(
+            parse_color("#ff0000"),
+            Ok(Some(Rgb::new(255, 0, 0).to_hs_lossy()))
+        );
+        assert!(parse_color("red").is_err());
+        assert_eq!(parse_index("layer", "3", 4), Ok(3));
+        assert!(parse_index("layer", "4", 4).is_err());
+        assert!(parse_index("layer", "-1", 4).is_err());
+    }
+
+    // Each `run` iterates the default main context, so they can't be in
+    // separate tests running in parallel
+    #[test]
+    fn run_commands() {
+        let dir = env::temp_dir().join(format!("keyboard_configurator_cli_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("keymap.json");
+        let path = path.to_str().unwrap();
+
+        run_fake(&["list"]).unwrap();
+
+        run_fake(&["export", path]).unwrap();
+        let mut keymap = read_keymap(path).unwrap();
+        assert_eq!(keymap.model, "system76/launch_1");
+        assert_eq!(keymap.map["K00"].len(), 4);
+        keymap.map.get_mut("K00").unwrap()[0] = "A".to_string();
+        write_keymap(&keymap, Some(path)).unwrap();
+        run_fake(&["diff", path]).unwrap();
+        run_fake(&["import", path]).unwrap();
+
+        for format in &["qmk-json", "qmk-c"] {
+            run_fake(&["export", "--format", format, path]).unwrap();
+            run_fake(&["import", "--format", format, path]).unwrap();
+        }
+
+        run_fake(&["set-key", "K00", "1", "ESC"]).unwrap();
+        assert!(run_fake(&["set-key", "K00", "4", "ESC"]).is_err());
+        assert!(run_fake(&["set-key", "K00", "0", "NOT_A_SCANCODE"]).is_err());
+        assert!(run_fake(&["set-key", "KZZ", "0", "ESC"]).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -23,8 +23,6 @@ error-set-keymap = Fehler beim Einstellen der Tastaturbelegung
 error-set-layer-brightness = Schichthelligkeit konnte nicht eingestellt werden
 error-set-layer-color = Schichtfarbe konnte nicht eingestellt werden
 error-set-layer-mode = Schichtart konnte nicht eingestellt werden
-error-unsupported-keymap = Tastaturbelegungsdatei wird nicht unterstützt
-error-unsupported-keymap-desc = Tastaturbelegungsdatei scheint von einer neueren Konfigurator-Version zu stammen.
 
 keyboard-brightness = Helligkeit:
 keyboard-color = Farbe:
@@ -9,10 +9,12 @@ button-cancel = Cancel
 button-configure = Configure Keyboard
 button-disable = Disable
 button-import = Import
+button-ok = OK
 button-test = Test
 button-start = Start
 button-stop = Stop
 
+error-add-keyboard = Failed to add keyboard
 error-disable-key = Failed to disable key
 error-export-keymap = Failed to export keymap
 error-import-keymap = Failed to import keymap
@@ -25,17 +27,22 @@ error-set-keymap = Failed to set keymap
 error-set-layer-brightness = Failed to set layer brightness
 error-set-layer-color = Failed to set layer color
 error-set-layer-mode = Failed to set layer mode
-error-unsupported-keymap = Unsupported keymap file
-error-unsupported-keymap-desc = Keymap file appears to be from newer Configurator version.
+error-set-variant = Failed to change keyboard layout
 
 firmware-version = Firmware version {$version} does not support keymap configuration.
 
 keyboard-brightness = Brightness:
 keyboard-color = Color:
+keyboard-variant = Keyboard Layout:
 
 key-color = Key Color:
 
+keymap-converted = Keymap is for board '{$model}', and has been converted to fit this keyboard.
+keymap-diff-desc = Importing this keymap will make the following changes:
+keymap-diff-title = Import Keymap
 keymap-for-board = Keymap is for board '{$model}'
+keymap-no-changes = Importing this keymap would not change any settings.
+keymap-unplaced = Keys that could not be converted: {$keys}
 
 layer-all-brightness = Brightness (all layers):
 layer-animation-speed = Layer Animation Speed:
@@ -84,3 +91,9 @@ test-replace-switch = Replace switch
 test-spurious-keypress = Spurious keypress
 
 untitled-layout = Untitled Layout
+
+variant-de = German
+variant-default = US (ANSI)
+variant-iso = ISO
+variant-jis = Japanese (JIS)
+variant-uk = UK (ISO)
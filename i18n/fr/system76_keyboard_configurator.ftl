@@ -23,8 +23,6 @@ error-set-keymap = Échec lors de la configuration du plan de codage.
 error-set-layer-brightness = Échec lors de la configuration de la luminosité de la couche.
 error-set-layer-color = Échec lors de la configuration de la couleur de la couche.
 error-set-layer-mode = Échec lors de la configuration du mode de la couche.
-error-unsupported-keymap = Fichier contenant le plan de codage incompatible.
-error-unsupported-keymap-desc = Fichier contenant le plan de codage provient d'une version plus récente du logiciel.
 
 key-color = Couleur de la touche:
 
@@ -23,8 +23,6 @@ error-set-keymap = De indeling kan niet worden ingesteld
 error-set-layer-brightness = De helderheidslaag kan niet worden ingesteld
 error-set-layer-color = De kleurlaag kan niet worden ingesteld
 error-set-layer-mode = De laagmodus kan niet worden ingesteld
-error-unsupported-keymap = Dit bestand wordt niet ondersteund
-error-unsupported-keymap-desc = Dit bestand lijkt afkomstig uit een nieuwere toepassingsversie.
 
 firmware-version = Versie {$version} van de firmware heeft geen ondersteuning voor deze instellingen.
 
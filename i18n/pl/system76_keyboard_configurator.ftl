@@ -22,8 +22,6 @@ error-set-keymap = Błąd ustawienia mapowania klawiszy
 error-set-layer-brightness = Błąd ustawienia jasności warstwy
 error-set-layer-color = Błąd ustawienia koloru warstwy
 error-set-layer-mode = Błąd ustawienia trybu warstwy
-error-unsupported-keymap = Plik mapowania klawiszy nie jest obsługiwany
-error-unsupported-keymap-desc = Plik mapowania klawiszy może pochodzić z nowszej wersji Konfiguracji.
 
 key-color = Kolor klawisza:
 
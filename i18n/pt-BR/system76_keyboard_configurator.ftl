@@ -23,8 +23,6 @@ error-set-keymap = Falha ao definir mapa do teclado
 error-set-layer-brightness = Falha ao definir brilho da camada
 error-set-layer-color = Falha ao definir cor da camada
 error-set-layer-mode = Falha ao definir modo da camada
-error-unsupported-keymap = Arquivo do mapa do teclado não suportado
-error-unsupported-keymap-desc = Arquivo do mapa do teclado aparenta ser de uma versão mais recente do Configurador do Teclado.
 
 firmware-version = Versão de Firmware {$version} não suporta a configuração do mapa do teclado.
 
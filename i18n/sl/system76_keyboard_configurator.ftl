@@ -23,8 +23,6 @@ error-set-keymap = Ni bilo mogoče nastaviti mape tipk
 error-set-layer-brightness = Ni bilo mogoče nastaviti svetlosti plasti
 error-set-layer-color = Barve sloja ni bilo mogoče nastaviti
 error-set-layer-mode = Ni bilo mogoče nastaviti načina sloja
-error-unsupported-keymap = Nepodprta datoteka z zemljevidi tipk
-error-unsupported-keymap-desc = Zdi se, da je datoteka tipkovnice iz novejše različice programa Configurator.
 
 firmware-version = Različica vdelane programske opreme {$version} ne podpira konfiguracije tipkovnice.
 
@@ -23,8 +23,6 @@ error-set-keymap = Tuş haritası atanamadı
 error-set-layer-brightness = Katman parlaklığı atanamadı
 error-set-layer-color = Katman rengi atanamadı
 error-set-layer-mode = Katman modu atanamadı
-error-unsupported-keymap = Tuş haritası dosyası desteklenmiyor
-error-unsupported-keymap-desc = Tuş haritası dosyası daha yeni bir yapılandırıcıya ait.
 
 firmware-version = Aygıt yazılımı {$version} tuş haritası yapılandırmasını desteklemiyor.
 
@@ -236,6 +236,7 @@ impl Backlight {
     pub fn new(board: Board) -> Self {
         let max_brightness = board.max_brightness() as f64;
         let has_led_save = board.has_led_save();
+        let has_per_key_led = board.capabilities().per_key_led;
 
         let obj: Self = glib::Object::new(&[]).unwrap();
         obj.inner().board.set(board.clone());
@@ -252,12 +253,40 @@ impl Backlight {
             obj.header_func(row, before)
         ))));
 
+        if !has_per_key_led {
+            // Remove in reverse, so positions of the other modes don't change
+            for (i, mode) in Mode::all().iter().enumerate().rev() {
+                if mode.is_per_key() {
+                    obj.inner().mode_combobox.remove(i as i32);
+                }
+            }
+        }
+
         if !obj.board().layout().meta.has_per_layer {
             obj.inner()
                 .brightness_label
                 .set_label(&fl!("keyboard-brightness"));
         }
 
+        // Only poll for LED changes while the settings are shown
+        obj.connect_map(|obj| obj.board().set_watch_leds(true));
+        obj.connect_unmap(|obj| obj.board().set_watch_leds(false));
+
+        // Show changes made outside the configurator, such as with Fn keys or
+        // the firmware's own LED keys
+        obj.board()
+            .connect_brightness_changed(clone!(@weak obj => move || {
+                obj.set_layer(obj.inner().layer.get())
+            }));
+        obj.board()
+            .connect_color_changed(clone!(@weak obj => move || {
+                obj.set_layer(obj.inner().layer.get())
+            }));
+        obj.board()
+            .connect_mode_changed(clone!(@weak obj => move || {
+                obj.set_layer(obj.inner().layer.get())
+            }));
+
         if has_led_save {
             glib::timeout_add_seconds_local(
                 10,
@@ -302,16 +331,17 @@ impl Backlight {
     fn filter_func(&self, row: &gtk::ListBoxRow) -> bool {
         let inner = self.inner();
         let layout = inner.board.layout();
+        let has_mode = inner.board.capabilities().modes;
         if row == &*inner.mode_row {
-            layout.meta.has_mode
+            has_mode
         } else if row == &*inner.speed_row {
-            layout.meta.has_mode && self.mode().has_speed
+            has_mode && self.mode().has_speed
         } else if row == &*inner.color_row {
-            layout.meta.has_color && (!layout.meta.has_mode || self.mode().has_hue)
+            layout.meta.has_color && (!has_mode || self.mode().has_hue)
         } else if row == &*inner.saturation_row {
             !self.mode().has_hue && !self.mode().is_disabled()
         } else if row == &*inner.brightness_row {
-            layout.meta.has_brightness && (!layout.meta.has_mode || !self.mode().is_disabled())
+            layout.meta.has_brightness && (!has_mode || !self.mode().is_disabled())
         } else {
             true
         }
@@ -59,7 +59,14 @@ impl ApplicationImpl for ConfiguratorAppInner {
             let value: String = opt.get().unwrap();
 
             if &value == "all" {
-                backend::layouts().iter().map(|s| s.to_string()).collect()
+                let mut board_names: Vec<String> =
+                    backend::layouts().iter().map(|s| s.to_string()).collect();
+                for board in backend::runtime_layouts() {
+                    if !board_names.contains(&board) {
+                        board_names.push(board);
+                    }
+                }
+                board_names
             } else {
                 value.split(',').map(str::to_string).collect()
             }
@@ -1,20 +1,17 @@
 use crate::fl;
 use cascade::cascade;
-use futures::{prelude::*, stream::FuturesUnordered};
 use glib::clone;
 use glib::object::WeakRef;
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
 use std::{
     cell::{Cell, RefCell},
-    collections::HashMap,
     fs::File,
-    pin::Pin,
     str,
 };
 
 use crate::{show_error_dialog, Backlight, KeyboardLayer, MainWindow, Page, Picker, Testing};
-use backend::{Board, DerefCell, KeyMap, Layout, Mode};
+use backend::{Board, DerefCell, KeyMap, Layout, Scancode};
 use widgets::SelectedKeys;
 
 #[derive(Default)]
@@ -189,20 +186,20 @@ impl Keyboard {
             keyboard.inner().testing.set(None);
         }
 
-        stack.add_titled(
-            &cascade! {
-                gtk::Box::new(gtk::Orientation::Vertical, 32);
-                ..add(&cascade! {
-                    gtk::Label::new(Some(&fl!("stack-keymap-desc")));
-                    ..set_line_wrap(true);
-                    ..set_max_width_chars(100);
-                    ..set_halign(gtk::Align::Center);
-                });
-                ..add(&*keyboard.inner().picker_box);
-            },
-            "keymap",
-            &fl!("stack-keymap"),
-        );
+        let keymap_box = cascade! {
+            gtk::Box::new(gtk::Orientation::Vertical, 32);
+            ..add(&cascade! {
+                gtk::Label::new(Some(&fl!("stack-keymap-desc")));
+                ..set_line_wrap(true);
+                ..set_max_width_chars(100);
+                ..set_halign(gtk::Align::Center);
+            });
+        };
+        if !board.layout().variants.is_empty() {
+            keymap_box.add(&keyboard.variant_box(&board));
+        }
+        keymap_box.add(&*keyboard.inner().picker_box);
+        stack.add_titled(&keymap_box, "keymap", &fl!("stack-keymap"));
 
         let backlight = cascade! {
             Backlight::new(board.clone());
@@ -249,6 +246,39 @@ impl Keyboard {
         KeyboardInner::from_instance(self)
     }
 
+    /// Selector for the layout variant of the board, like ISO or JIS
+    fn variant_box(&self, board: &Board) -> gtk::Box {
+        let combo = gtk::ComboBoxText::new();
+        combo.append(None, &variant_display_name(None));
+        for variant in &board.layout().variants {
+            combo.append(Some(variant), &variant_display_name(Some(variant)));
+        }
+        match &board.layout().variant {
+            Some(variant) => combo.set_active_id(Some(variant)),
+            None => combo.set_active(Some(0)),
+        };
+        combo.connect_changed(clone!(@weak self as self_, @strong board => move |combo| {
+            let variant = combo.get_active_id();
+            let board = board.clone();
+            glib::MainContext::default().spawn_local(clone!(@weak self_ => async move {
+                // The board is removed and added again with the new layout
+                if let Err(err) = board.set_variant(variant.as_deref()).await {
+                    error!("{}: {}", fl!("error-set-variant"), err);
+                    if let Some(window) = self_.window() {
+                        show_error_dialog(&window, &fl!("error-set-variant"), err);
+                    }
+                }
+            }));
+        }));
+
+        cascade! {
+            gtk::Box::new(gtk::Orientation::Horizontal, 8);
+            ..set_halign(gtk::Align::Center);
+            ..add(&gtk::Label::new(Some(&fl!("keyboard-variant"))));
+            ..add(&combo);
+        }
+    }
+
     pub fn action_group(&self) -> &gio::ActionGroup {
         self.inner().action_group.upcast_ref()
     }
@@ -287,8 +317,8 @@ impl Keyboard {
         &self.inner().layer_stack
     }
 
-    pub fn has_scancode(&self, scancode_name: &str) -> bool {
-        self.layout().scancode_from_name(scancode_name).is_some()
+    pub fn scancode(&self, scancode_name: &str) -> Option<&Scancode> {
+        self.layout().scancode(scancode_name)
     }
 
     pub async fn keymap_set(&self, key_index: usize, layer: usize, scancode_name: &str) {
@@ -307,17 +337,108 @@ impl Keyboard {
     }
 
     pub fn import_keymap(&self, keymap: KeyMap) {
-        // TODO: Ideally don't want this function to be O(Keys^2)
+        let mut notes = Vec::new();
+        let keymap = if !self.board().matches_keymap(&keymap) {
+            match self.board().convert_keymap(&keymap) {
+                Ok(conversion) => {
+                    notes.push(fl!("keymap-converted", model = keymap.model.clone()));
+                    let mut unplaced = conversion.unplaced;
+                    unplaced.extend(conversion.unsupported.into_iter().map(
+                        |(key, layer, scancode)| format!("{} ({}, {})", key, layer + 1, scancode),
+                    ));
+                    if !unplaced.is_empty() {
+                        notes.push(fl!("keymap-unplaced", keys = unplaced.join(", ")));
+                    }
+                    conversion.keymap
+                }
+                Err(err) => {
+                    error!("{}: {}", fl!("error-import-keymap"), err);
+                    show_error_dialog(
+                        &self.window().unwrap(),
+                        &fl!("error-import-keymap"),
+                        fl!("keymap-for-board", model = keymap.model),
+                    );
+                    return;
+                }
+            }
+        } else {
+            keymap
+        };
 
-        if keymap.model != self.board().model() {
-            show_error_dialog(
-                &self.window().unwrap(),
-                &fl!("error-import-keymap"),
-                fl!("keymap-for-board", model = keymap.model),
+        let diff = match self.board().diff_keymap(&keymap) {
+            Ok(diff) => diff,
+            Err(err) => {
+                error!("{}: {}", fl!("error-import-keymap"), err);
+                if let Some(window) = self.window() {
+                    show_error_dialog(&window, &fl!("error-import-keymap"), err);
+                }
+                return;
+            }
+        };
+        let cancel = fl!("button-cancel");
+        let import = fl!("button-import");
+        let ok = fl!("button-ok");
+        // With nothing to import, only show why, such as conversion notes
+        let buttons = if diff.is_empty() {
+            vec![(ok.as_str(), gtk::ResponseType::Ok)]
+        } else {
+            vec![
+                (cancel.as_str(), gtk::ResponseType::Cancel),
+                (import.as_str(), gtk::ResponseType::Accept),
+            ]
+        };
+        let dialog = cascade! {
+            gtk::Dialog::with_buttons(
+                Some(&fl!("keymap-diff-title")),
+                self.window().as_ref(),
+                gtk::DialogFlags::MODAL | gtk::DialogFlags::USE_HEADER_BAR,
+                &buttons,
             );
-            return;
+            ..set_default_response(buttons.last().unwrap().1);
+            ..connect_response(clone!(@weak self as self_ => move |dialog, response| {
+                if response == gtk::ResponseType::Accept {
+                    self_.apply_keymap(keymap.clone());
+                }
+                dialog.close();
+            }));
+        };
+
+        let content = dialog.get_content_area();
+        for note in &notes {
+            content.add(&cascade! {
+                gtk::Label::new(Some(note));
+                ..set_line_wrap(true);
+                ..set_max_width_chars(60);
+            });
+        }
+        if diff.is_empty() {
+            content.add(&gtk::Label::new(Some(&fl!("keymap-no-changes"))));
+        } else {
+            let label = cascade! {
+                gtk::Label::new(Some(&diff.to_string()));
+                ..set_halign(gtk::Align::Start);
+                ..set_valign(gtk::Align::Start);
+                ..set_selectable(true);
+                ..get_style_context().add_class("monospace");
+            };
+            let scrolled = cascade! {
+                gtk::ScrolledWindow::new::<gtk::Adjustment, gtk::Adjustment>(None, None);
+                ..set_min_content_height(300);
+                ..set_min_content_width(400);
+                ..set_vexpand(true);
+                ..add(&label);
+            };
+            content.add(&gtk::Label::new(Some(&fl!("keymap-diff-desc"))));
+            content.add(&scrolled);
         }
+        content.set_spacing(12);
+        content.set_property_margin(24);
+        content.show_all();
+
+        dialog.show();
+    }
 
+    fn apply_keymap(&self, keymap: KeyMap) {
         let self_ = self.clone();
         glib::MainContext::default().spawn_local(async move {
             let _loader = self_.get_toplevel().and_then(|x| {
@@ -327,54 +448,15 @@ impl Keyboard {
                 )
             });
 
-            // TODO: Make sure it doesn't panic with invalid json with invalid indexes?
-
-            let key_indices = self_
-                .board()
-                .keys()
-                .iter()
-                .enumerate()
-                .map(|(i, k)| (&k.logical_name, i))
-                .collect::<HashMap<_, _>>();
-
-            let futures = FuturesUnordered::<Pin<Box<dyn Future<Output = ()>>>>::new();
-
-            for (k, v) in &keymap.map {
-                for (layer, scancode_name) in v.iter().enumerate() {
-                    let n = key_indices[&k];
-                    futures.push(Box::pin(self_.keymap_set(n, layer, scancode_name)));
+            let report = self_.board().apply_keymap(&keymap).await;
+            if let Some(err) = report.error_message() {
+                error!("{}: {}", fl!("error-import-keymap"), err);
+                if let Some(window) = self_.window() {
+                    show_error_dialog(&window, &fl!("error-import-keymap"), err);
                 }
             }
 
-            for (k, hs) in &keymap.key_leds {
-                let res = self_.board().keys()[key_indices[&k]].set_color(*hs);
-                futures.push(Box::pin(async move {
-                    if let Err(err) = res.await {
-                        error!("{}: {}", fl!("error-key-led"), err);
-                    }
-                }));
-            }
-
-            for (i, keymap_layer) in keymap.layers.iter().enumerate() {
-                let layer = &self_.board().layers()[i];
-                futures.push(Box::pin(async move {
-                    if let Some((mode, speed)) = keymap_layer.mode {
-                        if let Err(err) =
-                            layer.set_mode(Mode::from_index(mode).unwrap(), speed).await
-                        {
-                            error!("{}: {}", fl!("error-set-layer-mode"), err)
-                        }
-                    }
-                    if let Err(err) = layer.set_brightness(keymap_layer.brightness).await {
-                        error!("{}: {}", fl!("error-set-layer-brightness"), err)
-                    }
-                    if let Err(err) = layer.set_color(keymap_layer.color).await {
-                        error!("{}: {}", fl!("error-set-layer-color"), err)
-                    }
-                }));
-            }
-
-            futures.collect::<()>().await;
+            self_.set_selected(self_.selected());
         });
     }
 
@@ -420,15 +502,10 @@ impl Keyboard {
 
         if chooser.run() == gtk::ResponseType::Accept {
             let path = chooser.get_filename().unwrap();
-            let keymap = self.export_keymap();
-
-            if keymap.version != 1 {
-                show_error_dialog(
-                    &self.window().unwrap(),
-                    &fl!("error-unsupported-keymap"),
-                    &fl!("error-unsupported-keymap-desc"),
-                )
-            }
+            let mut keymap = self.export_keymap();
+            keymap.name = path
+                .file_stem()
+                .map(|name| name.to_string_lossy().into_owned());
 
             match File::create(&path) {
                 Ok(file) => match keymap.to_writer_pretty(file) {
@@ -545,3 +622,15 @@ impl Keyboard {
         self.notify("selected");
     }
 }
+
+/// Display name of a layout variant, or of the base layout for `None`
+fn variant_display_name(variant: Option<&str>) -> String {
+    match variant {
+        None => fl!("variant-default"),
+        Some("iso") => fl!("variant-iso"),
+        Some("jis") => fl!("variant-jis"),
+        Some("de") => fl!("variant-de"),
+        Some("uk") => fl!("variant-uk"),
+        Some(variant) => variant.to_string(),
+    }
+}
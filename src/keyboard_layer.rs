@@ -6,6 +6,7 @@ use once_cell::unsync::OnceCell;
 use std::{
     cell::{Cell, RefCell},
     f64::consts::PI,
+    iter,
 };
 
 use crate::{Page, TestingColors};
@@ -16,6 +17,37 @@ const SCALE: f64 = 64.;
 const MARGIN: f64 = 2.;
 const RADIUS: f64 = 4.;
 const HALF_KEYBOARD_VSPACING: f64 = 16.;
+const LEGEND_PADDING: f64 = 4.;
+
+/// Scale a rectangle from layout units to pixels, without margins
+fn scale_rect(physical: &Rect) -> Rect {
+    Rect {
+        x: physical.x * SCALE,
+        y: -physical.y * SCALE,
+        w: physical.w * SCALE,
+        h: physical.h * SCALE,
+    }
+}
+
+/// Rotate `(x, y)` clockwise by `angle` degrees around `center`
+fn rotate_point(x: f64, y: f64, center: (f64, f64), angle: f64) -> (f64, f64) {
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let (dx, dy) = (x - center.0, y - center.1);
+    (
+        center.0 + dx * cos - dy * sin,
+        center.1 + dx * sin + dy * cos,
+    )
+}
+
+fn rounded_rect(cr: &cairo::Context, rect: &Rect) {
+    let Rect { x, y, w, h } = *rect;
+    cr.new_sub_path();
+    cr.arc(x + w - RADIUS, y + RADIUS, RADIUS, -0.5 * PI, 0.);
+    cr.arc(x + w - RADIUS, y + h - RADIUS, RADIUS, 0., 0.5 * PI);
+    cr.arc(x + RADIUS, y + h - RADIUS, RADIUS, 0.5 * PI, PI);
+    cr.arc(x + RADIUS, y + RADIUS, RADIUS, PI, 1.5 * PI);
+    cr.close_path();
+}
 
 #[derive(Default)]
 pub struct KeyboardLayerInner {
@@ -108,20 +140,19 @@ impl WidgetImpl for KeyboardLayerInner {
         let testing_colors = self.testing_colors.borrow();
 
         for (i, k) in widget.keys().iter().enumerate() {
-            let Rect { x, y, w, h } = widget.key_position(&k);
+            let pos = widget.key_position(&k);
+            let Rect { x, y, w, h } = pos;
 
-            let mut bg = if let Some(rgb) = testing_colors
+            let testing_color = testing_colors
                 .0
-                .get(&(k.electrical.0 as usize, k.electrical.1 as usize))
-            {
-                rgb
-            } else {
-                &k.background_color
-            }
-            .to_floats();
+                .get(&(k.electrical.0 as usize, k.electrical.1 as usize));
+            let mut bg = testing_color.unwrap_or(&k.background_color).to_floats();
+            // Legend colors from the layout are only meant for the key's background
+            let mut own_bg = testing_color.is_none();
 
             if k.pressed() {
                 bg = self.board.layout().meta.pressed_color.to_floats();
+                own_bg = false;
             }
 
             let fg = if (bg.0 + bg.1 + bg.2) / 3. >= 0.5 {
@@ -140,35 +171,82 @@ impl WidgetImpl for KeyboardLayerInner {
                 }
             }
 
-            // Rounded rectangle
-            cr.new_sub_path();
-            cr.arc(x + w - RADIUS, y + RADIUS, RADIUS, -0.5 * PI, 0.);
-            cr.arc(x + w - RADIUS, y + h - RADIUS, RADIUS, 0., 0.5 * PI);
-            cr.arc(x + RADIUS, y + h - RADIUS, RADIUS, 0.5 * PI, PI);
-            cr.arc(x + RADIUS, y + RADIUS, RADIUS, PI, 1.5 * PI);
-            cr.close_path();
-
-            cr.set_source_rgba(bg.0, bg.1, bg.2, bg_alpha);
-            cr.fill_preserve();
+            cr.save();
+            let center = widget.rotation_center(k);
+            cr.translate(center.0, center.1);
+            cr.rotate(k.rotation.to_radians());
+            cr.translate(-center.0, -center.1);
+
+            // Rounded rectangles
+            let rects = iter::once(pos)
+                .chain(widget.key_position2(k))
+                .collect::<Vec<_>>();
+            for rect in &rects {
+                rounded_rect(cr, rect);
+            }
 
-            if self.selectable.get() && widget.selected().contains(&i) {
+            let is_selected = self.selectable.get() && widget.selected().contains(&i);
+            if is_selected && rects.len() > 1 {
+                // Fill over the outline, to hide where the rectangles overlap
                 cr.set_source_rgb(selected.0, selected.1, selected.2);
                 cr.set_line_width(4.);
-                cr.stroke();
+                cr.stroke_preserve();
+                cr.set_source_rgba(bg.0, bg.1, bg.2, bg_alpha);
+                cr.fill();
+            } else {
+                cr.set_source_rgba(bg.0, bg.1, bg.2, bg_alpha);
+                cr.fill_preserve();
+                if is_selected {
+                    cr.set_source_rgb(selected.0, selected.1, selected.2);
+                    cr.set_line_width(4.);
+                    cr.stroke();
+                }
             }
-
-            // Draw label
-            let text = widget.page().get_label(k);
-            let layout = cascade! {
-                widget.create_pango_layout(Some(&text));
-                ..set_width((w * pango::SCALE as f64) as i32);
-                ..set_alignment(pango::Alignment::Center);
-            };
-            let text_height = layout.get_pixel_size().1 as f64;
             cr.new_path();
-            cr.move_to(x, y + (h - text_height) / 2.);
-            cr.set_source_rgba(fg.0, fg.1, fg.2, text_alpha);
-            pangocairo::show_layout(cr, &layout);
+
+            if widget.page() == Page::Keycaps && !k.legends.is_empty() {
+                // Draw legends in their positions
+                for legend in &k.legends {
+                    let alignment = match legend.position % 3 {
+                        0 => pango::Alignment::Left,
+                        1 => pango::Alignment::Center,
+                        _ => pango::Alignment::Right,
+                    };
+                    let layout = cascade! {
+                        widget.create_pango_layout(Some(&legend.text));
+                        ..set_width(((w - LEGEND_PADDING * 2.) * pango::SCALE as f64) as i32);
+                        ..set_alignment(alignment);
+                    };
+                    let text_height = layout.get_pixel_size().1 as f64;
+                    // Front legends are shown at the bottom
+                    let text_y = match legend.position / 3 {
+                        0 => y + LEGEND_PADDING,
+                        1 => y + (h - text_height) / 2.,
+                        _ => y + h - text_height - LEGEND_PADDING,
+                    };
+                    let color = match legend.color {
+                        Some(color) if own_bg => color.to_floats(),
+                        _ => fg,
+                    };
+                    cr.move_to(x + LEGEND_PADDING, text_y);
+                    cr.set_source_rgba(color.0, color.1, color.2, text_alpha);
+                    pangocairo::show_layout(cr, &layout);
+                }
+            } else {
+                // Draw label
+                let text = widget.page().get_label(k);
+                let layout = cascade! {
+                    widget.create_pango_layout(Some(&text));
+                    ..set_width((w * pango::SCALE as f64) as i32);
+                    ..set_alignment(pango::Alignment::Center);
+                };
+                let text_height = layout.get_pixel_size().1 as f64;
+                cr.move_to(x, y + (h - text_height) / 2.);
+                cr.set_source_rgba(fg.0, fg.1, fg.2, text_alpha);
+                pangocairo::show_layout(cr, &layout);
+            }
+
+            cr.restore();
         }
 
         Inhibit(false)
@@ -185,7 +263,7 @@ impl WidgetImpl for KeyboardLayerInner {
         let pressed = widget
             .keys()
             .iter()
-            .position(|k| widget.key_position(&k).contains(pos.0, pos.1));
+            .position(|k| widget.key_contains(k, pos.0, pos.1));
 
         if let Some(pressed) = pressed {
             let shift = evt.get_state().contains(gdk::ModifierType::SHIFT_MASK);
@@ -282,26 +360,27 @@ impl KeyboardLayer {
 
     fn wide_width(&self) -> i32 {
         self.keys_maximize(&self.inner().wide_width, |k| {
-            let pos = self.key_position_wide(k);
-            (pos.x + pos.w) as i32
+            let bounds = self.key_bounds_wide(k);
+            (bounds.x + bounds.w) as i32
         })
     }
 
     fn wide_height(&self) -> i32 {
         self.keys_maximize(&self.inner().wide_height, |k| {
-            let pos = self.key_position_wide(k);
-            (pos.y + pos.h + 4.) as i32
+            let bounds = self.key_bounds_wide(k);
+            (bounds.y + bounds.h + 4.) as i32
         })
     }
 
     fn narrow_width(&self) -> i32 {
         self.keys_maximize(&self.inner().narrow_width, |k| {
-            let mut pos = self.key_position_wide(k);
+            let pos = self.key_position_wide(k);
+            let mut bounds = self.key_bounds_wide(k);
             let width = self.wide_width() as f64 / 2.;
             if pos.x + pos.w / 2. > width {
-                pos.x -= width;
+                bounds.x -= width;
             }
-            (pos.x + pos.w) as i32
+            (bounds.x + bounds.w) as i32
         })
     }
 
@@ -310,14 +389,43 @@ impl KeyboardLayer {
     }
 
     fn key_position_wide(&self, k: &Key) -> Rect {
+        let rect = scale_rect(&k.physical);
         Rect {
-            x: (k.physical.x * SCALE) + MARGIN,
-            y: -(k.physical.y * SCALE) + MARGIN,
-            w: (k.physical.w * SCALE) - MARGIN * 2.,
-            h: (k.physical.h * SCALE) - MARGIN * 2.,
+            x: rect.x + MARGIN,
+            y: rect.y + MARGIN,
+            w: rect.w - MARGIN * 2.,
+            h: rect.h - MARGIN * 2.,
         }
     }
 
+    /// Bounding box of a key in the wide layout, including its second
+    /// rectangle and rotation
+    fn key_bounds_wide(&self, k: &Key) -> Rect {
+        let center = (k.rotation_origin.0 * SCALE, -k.rotation_origin.1 * SCALE);
+        let mut min = (f64::INFINITY, f64::INFINITY);
+        let mut max = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+        let rects = iter::once(&k.physical).chain(k.physical2.as_ref());
+        for rect in rects.map(scale_rect) {
+            let corners = [
+                (rect.x, rect.y),
+                (rect.x + rect.w, rect.y),
+                (rect.x, rect.y + rect.h),
+                (rect.x + rect.w, rect.y + rect.h),
+            ];
+            for &(x, y) in &corners {
+                let (x, y) = rotate_point(x, y, center, k.rotation);
+                min = (min.0.min(x), min.1.min(y));
+                max = (max.0.max(x), max.1.max(y));
+            }
+        }
+        Rect::new(
+            min.0 + MARGIN,
+            min.1 + MARGIN,
+            max.0 - min.0 - MARGIN * 2.,
+            max.1 - min.1 - MARGIN * 2.,
+        )
+    }
+
     fn key_position_narrow(&self, k: &Key) -> Rect {
         let mut rect = self.key_position_wide(k);
         let width = self.wide_width() as f64 / 2.;
@@ -337,4 +445,44 @@ impl KeyboardLayer {
         pos.x += (self.get_allocated_width() - width) as f64 / 2.;
         pos
     }
+
+    /// Offset of a key's position from its position in the wide layout
+    fn key_offset(&self, k: &Key) -> (f64, f64) {
+        let wide = self.key_position_wide(k);
+        let pos = self.key_position(k);
+        (pos.x - wide.x, pos.y - wide.y)
+    }
+
+    /// Position of the second rectangle of a key, if it has one
+    fn key_position2(&self, k: &Key) -> Option<Rect> {
+        let offset = self.key_offset(k);
+        k.physical2.as_ref().map(|physical2| {
+            let rect = scale_rect(physical2);
+            Rect {
+                x: rect.x + MARGIN + offset.0,
+                y: rect.y + MARGIN + offset.1,
+                w: rect.w - MARGIN * 2.,
+                h: rect.h - MARGIN * 2.,
+            }
+        })
+    }
+
+    /// Point the key is rotated around
+    fn rotation_center(&self, k: &Key) -> (f64, f64) {
+        let offset = self.key_offset(k);
+        (
+            k.rotation_origin.0 * SCALE + offset.0,
+            -k.rotation_origin.1 * SCALE + offset.1,
+        )
+    }
+
+    /// Test if `(x, y)` is a point on the key
+    fn key_contains(&self, k: &Key, x: f64, y: f64) -> bool {
+        // Rotate the point back, rather than rotating the key
+        let (x, y) = rotate_point(x, y, self.rotation_center(k), -k.rotation);
+        self.key_position(k).contains(x, y)
+            || self
+                .key_position2(k)
+                .map_or(false, |rect| rect.contains(x, y))
+    }
 }
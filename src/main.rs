@@ -37,8 +37,11 @@ fn main() {
 
     let args = env::args().collect::<Vec<_>>();
     for arg in args.iter().skip(1) {
-        if arg.as_str() == "--daemon" {
-            backend::run_daemon();
+        match arg.as_str() {
+            "--daemon" => backend::run_daemon(),
+            #[cfg(target_os = "linux")]
+            "--daemon-socket" => backend::run_daemon_socket(),
+            _ => {}
         }
     }
 
@@ -5,7 +5,9 @@ use gtk::prelude::*;
 use gtk::subclass::prelude::*;
 use std::{cell::RefCell, time::Duration};
 
-use crate::{shortcuts_window, ConfiguratorApp, Keyboard, KeyboardLayer, Page, Picker};
+use crate::{
+    shortcuts_window, show_error_dialog, ConfiguratorApp, Keyboard, KeyboardLayer, Page, Picker,
+};
 use backend::{Backend, Board, DerefCell};
 
 pub struct Loader(MainWindow, gtk::Box);
@@ -214,6 +216,9 @@ impl MainWindow {
             ..connect_board_loading_done(clone!(@weak window => move || {
                 window.inner().board_loading.borrow_mut().take();
             }));
+            ..connect_board_error(clone!(@weak window => move |err| {
+                show_error_dialog(&window, &fl!("error-add-keyboard"), err);
+            }));
             ..connect_board_added(clone!(@weak window => move |board| window.add_keyboard(board)));
             ..connect_board_removed(clone!(@weak window => move |board| window.remove_keyboard(board)));
             ..refresh();
@@ -242,14 +247,8 @@ impl MainWindow {
             backend.refresh();
         }
 
+        // Boards added or removed later are reported by the backend
         window.inner().backend.set(backend);
-        glib::timeout_add_seconds_local(
-            1,
-            clone!(@weak window => @default-return glib::Continue(false), move || {
-                window.inner().backend.refresh();
-                glib::Continue(true)
-            }),
-        );
 
         window
     }
@@ -344,6 +343,15 @@ impl MainWindow {
             keyboard_box.add(&label);
         }
 
+        board.connect_disconnected(clone!(@weak keyboard, @weak row => move || {
+            keyboard.set_sensitive(false);
+            row.set_sensitive(false);
+        }));
+        board.connect_reconnected(clone!(@weak keyboard, @weak row => move || {
+            keyboard.set_sensitive(true);
+            row.set_sensitive(true);
+        }));
+
         self.inner().stack.add(&keyboard);
         self.inner().keyboards.borrow_mut().push((keyboard, row));
 
@@ -400,8 +408,13 @@ fn daemon() -> Backend {
         info!("Already running as root");
         Backend::new()
     } else {
-        info!("Not running as root, spawning daemon with pkexec");
-        Backend::new_pkexec()
+        Backend::new_socket().or_else(|err| {
+            info!(
+                "Not running as root, and {}; spawning daemon with pkexec",
+                err
+            );
+            Backend::new_pkexec()
+        })
     }
     .expect("Failed to create server")
 }
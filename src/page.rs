@@ -1,5 +1,4 @@
 use crate::fl;
-use crate::picker::SCANCODE_LABELS;
 use backend::Key;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -62,11 +61,9 @@ impl Page {
     pub fn get_label(&self, key: &Key) -> String {
         match self {
             Page::Layer1 | Page::Layer2 | Page::Layer3 | Page::Layer4 => {
-                let scancode_name = key.get_scancode(self.layer().unwrap()).unwrap().1;
-                SCANCODE_LABELS
-                    .get(&scancode_name)
-                    .unwrap_or(&scancode_name)
-                    .into()
+                // Includes labels of the layout variant, like on a German keyboard
+                key.scancode(self.layer().unwrap())
+                    .map_or_else(String::new, |scancode| scancode.label)
             }
             Page::Keycaps => key.physical_name.clone(),
             Page::Logical => key.logical_name.clone(),
@@ -3,11 +3,10 @@ use futures::{prelude::*, stream::FuturesUnordered};
 use glib::clone;
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
-use once_cell::sync::Lazy;
 use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
 use crate::Keyboard;
-use backend::DerefCell;
+use backend::{DerefCell, Scancode};
 
 mod picker_group;
 mod picker_json;
@@ -32,16 +31,6 @@ button {
 }
 "#;
 
-pub static SCANCODE_LABELS: Lazy<HashMap<String, String>> = Lazy::new(|| {
-    let mut labels = HashMap::new();
-    for group in picker_json() {
-        for key in group.keys {
-            labels.insert(key.keysym, key.label);
-        }
-    }
-    labels
-});
-
 #[derive(Default)]
 pub struct PickerInner {
     groups: DerefCell<Vec<PickerGroup>>,
@@ -72,16 +61,13 @@ impl ObjectImpl for PickerInner {
         for json_group in picker_json() {
             let mut group = PickerGroup::new(json_group.label, json_group.cols);
 
-            for json_key in json_group.keys {
-                let key = PickerKey::new(
-                    json_key.keysym.clone(),
-                    json_key.label,
-                    json_group.width,
-                    &style_provider,
-                );
+            for name in json_group.keys {
+                // Relabeled for the layout of each keyboard in `set_keyboard`
+                let label = Scancode::new(&name, 0).label;
+                let key = PickerKey::new(name.clone(), label, json_group.width, &style_provider);
 
                 group.add_key(key.clone());
-                keys.insert(json_key.keysym, key);
+                keys.insert(name, key);
             }
 
             groups.push(group);
@@ -279,8 +265,13 @@ impl Picker {
             for group in self.inner().groups.iter() {
                 for key in group.iter_keys() {
                     // Check that scancode is available for the keyboard
-                    let visible = kb.has_scancode(&key.name);
-                    key.gtk.set_visible(visible);
+                    match kb.scancode(&key.name) {
+                        Some(scancode) => {
+                            key.label.set_text(&scancode.label);
+                            key.gtk.set_visible(true);
+                        }
+                        None => key.gtk.set_visible(false),
+                    }
                 }
             }
             kb.set_picker(Some(&self));
@@ -336,17 +327,21 @@ impl Picker {
 
 #[cfg(test)]
 mod tests {
-    use crate::*;
+    use super::picker_json::picker_json;
     use backend::{layouts, Layout};
     use std::collections::HashSet;
 
     #[test]
     fn picker_has_keys() {
+        let picker_keys = picker_json()
+            .into_iter()
+            .flat_map(|group| group.keys)
+            .collect::<HashSet<_>>();
         let mut missing = HashSet::new();
         for i in layouts() {
             let layout = Layout::from_board(i).unwrap();
             for j in layout.default.map.values().flatten() {
-                if SCANCODE_LABELS.keys().find(|x| x == &j).is_none() {
+                if !picker_keys.contains(j) {
                     missing.insert(j.to_owned());
                 }
             }
@@ -1,17 +1,12 @@
 use serde::Deserialize;
 
-#[derive(Deserialize)]
-pub struct PickerJsonKey {
-    pub keysym: String,
-    pub label: String,
-}
-
 #[derive(Deserialize)]
 pub struct PickerJsonGroup {
     pub label: String,
     pub cols: i32,
     pub width: i32,
-    pub keys: Vec<PickerJsonKey>,
+    /// Scancode names, labeled from `layouts/scancodes.json`
+    pub keys: Vec<String>,
 }
 
 pub fn picker_json() -> Vec<PickerJsonGroup> {
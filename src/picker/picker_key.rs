@@ -5,6 +5,8 @@ use std::rc::Rc;
 pub(super) struct PickerKey {
     /// Symbolic name of the key
     pub(super) name: String,
+    /// Label of the button, which depends on the keyboard's layout
+    pub(super) label: gtk::Label,
     // GTK button
     pub(super) gtk: gtk::Button,
 }
@@ -32,6 +34,10 @@ impl PickerKey {
             ..add(&label);
         };
 
-        Rc::new(Self { name, gtk: button })
+        Rc::new(Self {
+            name,
+            label,
+            gtk: button,
+        })
     }
 }
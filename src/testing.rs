@@ -1,5 +1,5 @@
 use crate::fl;
-use backend::{Board, DerefCell, NelsonKind, Rgb};
+use backend::{BackendError, Board, DerefCell, NelsonKind, Rgb};
 use cascade::cascade;
 use futures::{channel::oneshot, prelude::*, stream::FuturesUnordered};
 use glib::clone;
@@ -283,7 +283,7 @@ glib::wrapper! {
         @extends gtk::Box, gtk::Container, gtk::Widget, @implements gtk::Orientable;
 }
 
-async fn import_keymap_hack(board: &Board, keymap: &backend::KeyMap) -> Result<(), String> {
+async fn import_keymap_hack(board: &Board, keymap: &backend::KeyMap) -> Result<(), BackendError> {
     let futures = FuturesUnordered::new();
     for key in board.keys() {
         if let Some(scancodes) = keymap.map.get(&key.logical_name) {
@@ -379,8 +379,10 @@ impl Testing {
     }
 
     fn test_buttons_sensitive(&self, sensitive: bool) {
+        // Tests need the Nelson test fixture
+        let has_nelson = self.inner().board.capabilities().nelson;
         for i in 0..3 {
-            self.inner().test_buttons[i].set_sensitive(sensitive);
+            self.inner().test_buttons[i].set_sensitive(sensitive && has_nelson);
         }
         self.inner().selma_start_button.set_sensitive(sensitive);
     }
@@ -391,6 +393,11 @@ impl Testing {
         info!("Disabling test buttons");
         self.test_buttons_sensitive(false);
 
+        // Keep other clients of the daemon from changing the keymap during the test
+        if let Err(err) = testing.board.lock().await {
+            error!("Failed to lock board: {}", err);
+        }
+
         let test_label = &testing.test_labels[test_index];
 
         info!("Save and clear keymap");
@@ -470,6 +477,10 @@ impl Testing {
             error!("Failed to restore keymap: {}", err);
         }
 
+        if let Err(err) = testing.board.unlock().await {
+            error!("Failed to unlock board: {}", err);
+        }
+
         info!("Enabling test buttons");
         self.test_buttons_sensitive(true);
     }
@@ -525,8 +536,14 @@ impl Testing {
 
         info!("Disabling test buttons");
         self.test_buttons_sensitive(false);
+
         testing.selma_stop_button.set_sensitive(true);
 
+        // Keep other clients of the daemon from changing the keymap during the test
+        if let Err(err) = testing.board.lock().await {
+            error!("Failed to lock board: {}", err);
+        }
+
         info!("Save and clear keymap");
         let keymap = testing.board.export_keymap();
         {
@@ -562,6 +579,10 @@ impl Testing {
             error!("Failed to restore keymap: {}", err);
         }
 
+        if let Err(err) = testing.board.unlock().await {
+            error!("Failed to unlock board: {}", err);
+        }
+
         info!("Enabling test buttons");
         self.test_buttons_sensitive(true);
         testing.selma_stop_button.set_sensitive(false);
@@ -597,7 +618,11 @@ impl Testing {
 
     pub fn new(board: Board) -> Self {
         let obj: Self = glib::Object::new(&[]).unwrap();
+        obj.inner()
+            .bench_button
+            .set_sensitive(board.capabilities().benchmark);
         obj.inner().board.set(board);
+        obj.test_buttons_sensitive(true);
         obj.connect_bench_button();
         obj.connect_test_button_1();
         obj.connect_test_button_2();
@@ -0,0 +1,687 @@
+//! Generates the files in `layouts/` from an EC or QMK source tree
+//!
+//! Usage: `layouts [--qmk] ECDIR BOARD`, run from the root of this repository.
+//! `BOARD` may be `all` to generate every board in the source tree.
+
+use regex::Regex;
+use serde::{
+    de::{Deserializer, MapAccess, Visitor},
+    Deserialize, Serialize, Serializer,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    env, fmt,
+    fs::{self, File},
+    path::Path,
+    process::{self, Command, Stdio},
+};
+
+include!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../backend/src/qmk_mapping.rs"
+));
+
+/// Layer keycodes, which aren't in the QMK keycode enums
+const QMK_EXTRA_SCANCODES: &[&str] = &[
+    "TG(0)", "TG(1)", "TG(2)", "TG(3)", "TO(0)", "TO(1)", "TO(2)", "TO(3)", "MO(0)", "MO(1)",
+    "MO(2)", "MO(3)",
+];
+
+const EXCLUDE_BOARDS: &[&str] = &[
+    "system76/ortho_split_2u",
+    "system76/launch_test",
+    "system76/virgo",
+];
+
+/// `#define KC_ALIAS KC_KEYCODE` in QMK's `keycode.h`
+const ALIAS_RE: &str = r"^#define\s+KC_([A-Z_]*)\s+KC_([A-Z_]+]*)\s*$";
+
+/// JSON object that keeps the order of its keys, like the generated files
+#[derive(Debug, Default, PartialEq)]
+struct OrderedMap<V>(Vec<(String, V)>);
+
+impl<V> OrderedMap<V> {
+    /// Insert a value, keeping the position of an existing key
+    fn insert(&mut self, key: String, value: V) {
+        match self.0.iter_mut().find(|(k, _)| *k == key) {
+            Some(entry) => entry.1 = value,
+            None => self.0.push((key, value)),
+        }
+    }
+
+    #[cfg(test)]
+    fn get(&self, key: &str) -> Option<&V> {
+        self.0.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+}
+
+impl<V: Serialize> Serialize for OrderedMap<V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(k, v)| (k, v)))
+    }
+}
+
+impl<'de, V: Deserialize<'de>> Deserialize<'de> for OrderedMap<V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct OrderedMapVisitor<V>(std::marker::PhantomData<V>);
+
+        impl<'de, V: Deserialize<'de>> Visitor<'de> for OrderedMapVisitor<V> {
+            type Value = OrderedMap<V>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a map")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+                let mut map = OrderedMap(Vec::new());
+                while let Some((key, value)) = access.next_entry()? {
+                    map.insert(key, value);
+                }
+                Ok(map)
+            }
+        }
+
+        deserializer.deserialize_map(OrderedMapVisitor(std::marker::PhantomData))
+    }
+}
+
+/// Remove `/* */` comments
+fn strip_block_comments(source: &str) -> String {
+    Regex::new(r"(?s)/\*.*?\*/")
+        .unwrap()
+        .replace_all(source, "")
+        .into_owned()
+}
+
+fn call_preprocessor(path: &str) -> Result<String, String> {
+    let output = Command::new("gcc")
+        .arg("-E")
+        .arg(path)
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|err| format!("Failed to run gcc: {}", err))?;
+    if !output.status.success() {
+        return Err(format!("Failed to preprocess {}", path));
+    }
+    String::from_utf8(output.stdout).map_err(|err| err.to_string())
+}
+
+/// Find the values of `defines` by compiling a program that prints them
+fn evaluate_defines(includes: &[String], defines: &[String]) -> Result<Vec<i64>, String> {
+    let tmpdir = env::temp_dir().join(format!("keysym-extract-{}", process::id()));
+    fs::create_dir_all(&tmpdir).map_err(|err| err.to_string())?;
+    let source = tmpdir.join("keysym-extract.c");
+    let binary = tmpdir.join("keysym-extract");
+
+    let mut c = String::from("#include <stdio.h>\nint main() {\n");
+    for i in defines {
+        c.push_str(&format!("printf(\"%d \", {});\n", i));
+    }
+    c.push_str("}\n");
+
+    let res = fs::write(&source, c)
+        .map_err(|err| err.to_string())
+        .and_then(|()| {
+            let mut cmd = Command::new("gcc");
+            for i in includes {
+                cmd.arg("-include").arg(i);
+            }
+            let status = cmd
+                .arg("-o")
+                .arg(&binary)
+                .arg(&source)
+                .status()
+                .map_err(|err| format!("Failed to run gcc: {}", err))?;
+            if !status.success() {
+                return Err("Failed to compile scancode extraction program".to_string());
+            }
+            let output = Command::new(&binary)
+                .output()
+                .map_err(|err| format!("Failed to run scancode extraction program: {}", err))?;
+            String::from_utf8_lossy(&output.stdout)
+                .split_whitespace()
+                .map(|i| i.parse().map_err(|_| format!("Invalid scancode '{}'", i)))
+                .collect()
+        });
+
+    let _ = fs::remove_dir_all(&tmpdir);
+    res
+}
+
+/// Scancodes that aren't supported by the board's keyboard backlight
+fn excluded_scancodes(board: &str, has_brightness: bool, has_color: bool) -> Vec<&'static str> {
+    let mut excluded = vec!["INT_1", "INT_2"];
+    if has_color || board == "system76/bonw14" {
+        excluded.push("KBD_BKL");
+    } else if has_brightness {
+        excluded.push("KBD_COLOR");
+    } else {
+        excluded.extend(&["KBD_COLOR", "KBD_DOWN", "KBD_UP", "KBD_BKL", "KBD_TOGGLE"]);
+    }
+    excluded
+}
+
+/// Convert `#define` or enum name to scancode name
+fn scancode_name(define: &str, mapping: &HashMap<String, String>) -> String {
+    let mut parts = define.splitn(2, '_');
+    let name = match (parts.next(), parts.next()) {
+        (Some(prefix), Some(name)) if prefix != "RGB" => name,
+        _ => define,
+    };
+    mapping.get(name).map_or(name, String::as_str).to_string()
+}
+
+/// Extract mapping from scancode names to numbers, and the mapping from QMK
+/// keycodes to scancode names
+fn extract_scancodes(
+    ecdir: &str,
+    board: &str,
+    is_qmk: bool,
+    has_brightness: bool,
+    has_color: bool,
+) -> Result<(OrderedMap<i64>, HashMap<String, String>), String> {
+    let (includes, defines, mapping) = if is_qmk {
+        let includes = vec![
+            "stdint.h".to_string(),
+            format!("{}/tmk_core/common/keycode.h", ecdir),
+            format!("{}/quantum/quantum_keycodes.h", ecdir),
+            format!("{}/tmk_core/common/action_code.h", ecdir),
+        ];
+        let common_keymap_h = call_preprocessor(&includes[1])?;
+        let quantum_keycode_h = call_preprocessor(&includes[2])?;
+        let mut defines = Vec::new();
+        for (re, source) in &[
+            (r"    (KC_[^,\s]+)", &common_keymap_h),
+            (r"    (RGB_[^,\s]+)", &quantum_keycode_h),
+        ] {
+            let re = Regex::new(re).unwrap();
+            defines.extend(re.captures_iter(source).map(|i| i[1].to_string()));
+        }
+
+        let mut mapping = QMK_MAPPING
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>();
+        let keycode_h = fs::read_to_string(&includes[1])
+            .map_err(|err| format!("Failed to read {}: {}", includes[1], err))?;
+        let alias_re = Regex::new(ALIAS_RE).unwrap();
+        for i in keycode_h.lines().filter_map(|i| alias_re.captures(i)) {
+            let keycode = &i[2];
+            let name = mapping.get(keycode).map_or(keycode, String::as_str);
+            let name = name.to_string();
+            mapping.insert(i[1].to_string(), name);
+        }
+
+        defines.extend(QMK_EXTRA_SCANCODES.iter().map(|i| i.to_string()));
+        (includes, defines, mapping)
+    } else {
+        let path = format!("{}/src/common/include/common/keymap.h", ecdir);
+        let common_keymap_h =
+            fs::read_to_string(&path).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+        let re = Regex::new(r"#define.*((?:K_\S+)|(?:KT_FN))").unwrap();
+        let defines = re
+            .captures_iter(&common_keymap_h)
+            .map(|i| i[1].to_string())
+            .collect();
+        (vec![path], defines, HashMap::new())
+    };
+
+    let scancodes = evaluate_defines(&includes, &defines)?;
+    if scancodes.len() != defines.len() {
+        return Err("Scancode extraction program printed wrong number of values".to_string());
+    }
+
+    let mut scancode_list = OrderedMap::default();
+    for (define, scancode) in defines.iter().zip(scancodes) {
+        scancode_list.insert(scancode_name(define, &mapping), scancode);
+    }
+
+    if is_qmk {
+        scancode_list.insert("RESET".to_string(), 0x5C00);
+    } else {
+        scancode_list.insert("NONE".to_string(), 0x0000);
+    }
+
+    let excluded = excluded_scancodes(board, has_brightness, has_color);
+    scancode_list
+        .0
+        .retain(|(name, _)| !excluded.contains(&name.as_str()));
+
+    // Make sure scancodes are unique
+    let mut values = scancode_list.0.iter().map(|(_, v)| *v).collect::<Vec<_>>();
+    values.sort();
+    values.dedup();
+    if values.len() != scancode_list.0.len() {
+        return Err("Scancodes are not unique".to_string());
+    }
+
+    Ok((scancode_list, mapping))
+}
+
+/// Parse physical key names and electrical matrix from `LAYOUT` define
+fn parse_layout_define(
+    keymap_h: &str,
+    is_qmk: bool,
+) -> Result<(Vec<String>, Vec<Vec<String>>), String> {
+    let keymap_h = strip_block_comments(keymap_h);
+    let re =
+        Regex::new(r"(?s)LAYOUT\((.*?)\)[\s\\]*(\{[^{}]*(\{[^{}]*\}[^{}]*)+)[^{}]*\}").unwrap();
+    let m = re
+        .captures(&keymap_h)
+        .ok_or_else(|| "LAYOUT define not found".to_string())?;
+
+    let physical = m[1]
+        .replace(',', " ")
+        .replace('\\', "")
+        .split_whitespace()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+
+    let matrix = m[2].replace('{', "");
+    let mut rows = matrix.split('}').collect::<Vec<_>>();
+    rows.pop();
+    let physical2 = rows
+        .iter()
+        .map(|i| {
+            i.replace('\\', "")
+                .replace(',', "")
+                .split_whitespace()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    if !is_qmk && physical2.iter().any(|i| i.len() != physical2[0].len()) {
+        return Err("Rows of LAYOUT matrix have different lengths".to_string());
+    }
+
+    Ok((physical, physical2))
+}
+
+/// Parse LED indices from `LAYOUT` in `led_config`, if the board has one
+fn parse_led_config(led_c: &str, physical: &[String]) -> Result<OrderedMap<Vec<i64>>, String> {
+    let led_c = Regex::new(r"//.*").unwrap().replace_all(led_c, "");
+    let led_c = strip_block_comments(&led_c);
+
+    let mut leds = OrderedMap::default();
+    let m = match Regex::new(r"(?s)LAYOUT\((.*?)\)").unwrap().captures(&led_c) {
+        Some(m) => m,
+        None => return Ok(leds),
+    };
+    let led_indexes = m[1].replace(',', " ").replace('\\', "");
+    let led_indexes = led_indexes.split_whitespace().collect::<Vec<_>>();
+    for (i, physical_name) in physical.iter().enumerate() {
+        let index = led_indexes
+            .get(i)
+            .ok_or_else(|| format!("No LED for {}", physical_name))?;
+        let index = index
+            .parse()
+            .map_err(|_| format!("Invalid LED index '{}'", index))?;
+        leds.insert(physical_name.clone(), vec![index]);
+    }
+    Ok(leds)
+}
+
+/// Parse scancodes of each layer in `keymaps`, by physical key name
+fn parse_keymap(
+    keymap_c: &str,
+    mapping: &HashMap<String, String>,
+    physical: &[String],
+    is_qmk: bool,
+) -> Result<OrderedMap<Vec<String>>, String> {
+    // XXX for launch
+    let keymap_c = keymap_c.replace("MO(1)", "FN");
+    let keymap_c = strip_block_comments(&keymap_c);
+
+    let mut layer_scancodes = Vec::new();
+    for layer in Regex::new(r"(?s)LAYOUT\((.*?)\)")
+        .unwrap()
+        .captures_iter(&keymap_c)
+    {
+        let scancodes = layer[1]
+            .replace(',', " ")
+            .split_whitespace()
+            .map(|code| {
+                if code == "0" {
+                    return "NONE".to_string();
+                }
+                let code = code.replace("K_", "").replace("KC_", "").replace("KT_", "");
+                if is_qmk {
+                    mapping.get(&code).cloned().unwrap_or(code)
+                } else {
+                    code
+                }
+            })
+            .collect::<Vec<_>>();
+        if scancodes.len() != physical.len() {
+            return Err(format!(
+                "Layer {} has {} keys, instead of {}",
+                layer_scancodes.len(),
+                scancodes.len(),
+                physical.len()
+            ));
+        }
+        layer_scancodes.push(scancodes);
+    }
+
+    let mut keymap = OrderedMap::default();
+    for (i, physical_name) in physical.iter().enumerate() {
+        let scancodes = layer_scancodes.iter().map(|j| j[i].clone()).collect();
+        keymap.insert(physical_name.clone(), scancodes);
+    }
+    Ok(keymap)
+}
+
+/// Map each physical key name to its position in the electrical matrix
+fn layout_json(
+    physical: &[String],
+    physical2: &[Vec<String>],
+) -> Result<OrderedMap<(usize, usize)>, String> {
+    let mut layout = OrderedMap::default();
+    for p in physical {
+        let position = physical2
+            .iter()
+            .enumerate()
+            .find_map(|(x, i)| i.iter().position(|j| j == p).map(|y| (x, y)))
+            .ok_or_else(|| format!("{} not found in LAYOUT matrix", p))?;
+        layout.insert(p.clone(), position);
+    }
+    Ok(layout)
+}
+
+#[derive(Serialize)]
+struct DefaultLayer {
+    mode: Option<(u8, u8)>,
+    brightness: i32,
+    color: (u8, u8),
+}
+
+/// `default.json`, in the format of version 1 of `KeyMap`
+#[derive(Serialize)]
+struct DefaultJson<'a> {
+    model: &'a str,
+    version: u8,
+    map: OrderedMap<Vec<String>>,
+    key_leds: OrderedMap<Option<(u8, u8)>>,
+    layers: Vec<DefaultLayer>,
+}
+
+fn default_json(board: &str, keymap: OrderedMap<Vec<String>>, is_qmk: bool) -> DefaultJson {
+    let (key_leds, layers) = if is_qmk {
+        let key_leds = OrderedMap(keymap.0.iter().map(|(k, _)| (k.clone(), None)).collect());
+        let layer = |mode| DefaultLayer {
+            mode: Some((mode, 127)),
+            brightness: 176,
+            color: (142, 255),
+        };
+        (key_leds, vec![layer(7), layer(13), layer(13), layer(13)])
+    } else {
+        let layer = DefaultLayer {
+            mode: None,
+            brightness: 0,
+            color: (0, 0),
+        };
+        (OrderedMap::default(), vec![layer])
+    };
+    DefaultJson {
+        model: board,
+        version: 1,
+        map: keymap,
+        key_leds,
+        layers,
+    }
+}
+
+fn write_json<T: Serialize>(path: &str, value: &T) -> Result<(), String> {
+    let file = File::create(path).map_err(|err| format!("Failed to create {}: {}", path, err))?;
+    serde_json::to_writer_pretty(file, value)
+        .map_err(|err| format!("Failed to write {}: {}", path, err))
+}
+
+fn update_meta_json(
+    path: &str,
+    has_brightness: bool,
+    has_color: bool,
+    keyboard: &str,
+) -> Result<(), String> {
+    let mut meta = OrderedMap::<Value>::default();
+    if Path::new(path).exists() {
+        let file = File::open(path).map_err(|err| format!("Failed to open {}: {}", path, err))?;
+        meta = serde_json::from_reader(file)
+            .map_err(|err| format!("Failed to parse {}: {}", path, err))?;
+    }
+
+    meta.insert("has_brightness".to_string(), json!(has_brightness));
+    meta.insert("has_color".to_string(), json!(has_color));
+    meta.insert("keyboard".to_string(), json!(keyboard));
+
+    write_json(path, &meta)
+}
+
+fn read_source(path: &str) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|err| format!("Failed to read {}: {}", path, err))
+}
+
+fn generate_layout_dir(ecdir: &str, board: &str, is_qmk: bool) -> Result<(), String> {
+    println!("Generating layouts/{}...", board);
+
+    let mut has_brightness = true;
+    let mut has_color = true;
+
+    let (keymap_h, default_c, led_c, keyboard) = if is_qmk {
+        let name = board.rsplit('/').next().unwrap();
+        let keymap_h = read_source(&format!("{}/keyboards/{}/{}.h", ecdir, board, name))?;
+        let default_c = read_source(&format!(
+            "{}/keyboards/{}/keymaps/default/keymap.c",
+            ecdir, board
+        ))?;
+        let led_c = read_source(&format!("{}/keyboards/{}/{}.c", ecdir, board, name))?;
+        (keymap_h, default_c, led_c, board.to_string())
+    } else {
+        let board_mk = read_source(&format!("{}/src/board/{}/board.mk", ecdir, board))?;
+        let variable = |name: &str| {
+            Regex::new(&format!("(?m)^{}=(.*)$", name))
+                .unwrap()
+                .captures(&board_mk)
+                .map(|m| m[1].to_string())
+                .ok_or_else(|| format!("{} not found in board.mk", name))
+        };
+
+        let vendor = board.rsplitn(2, '/').nth(1).unwrap_or("");
+        let keyboard = format!("{}/{}", vendor, variable("KEYBOARD")?);
+
+        let kbled = variable("KBLED")?;
+        match kbled.as_str() {
+            "white_dac" => has_color = false,
+            // bonw14: Handled through USB. Can configurator support this?
+            "none" | "bonw14" => {
+                has_brightness = false;
+                has_color = false;
+            }
+            "rgb_pwm" | "oryp5" | "darp5" => {}
+            _ => return Err(format!("KBLED='{}' not handled by layouts tool", kbled)),
+        }
+
+        let keymap_h = read_source(&format!(
+            "{}/src/keyboard/{}/include/board/keymap.h",
+            ecdir, keyboard
+        ))?;
+        let default_c = read_source(&format!(
+            "{}/src/keyboard/{}/keymap/default.c",
+            ecdir, keyboard
+        ))?;
+        (keymap_h, default_c, String::new(), keyboard)
+    };
+
+    for dir in &[
+        format!("layouts/{}", board),
+        format!("layouts/keyboards/{}", keyboard),
+    ] {
+        fs::create_dir_all(dir).map_err(|err| format!("Failed to create {}: {}", dir, err))?;
+    }
+
+    let (physical, physical2) = parse_layout_define(&keymap_h, is_qmk)?;
+    let leds = parse_led_config(&led_c, &physical)?;
+    let (scancodes, mapping) = extract_scancodes(ecdir, board, is_qmk, has_brightness, has_color)?;
+    let default_keymap = parse_keymap(&default_c, &mapping, &physical, is_qmk)?;
+
+    let keyboard_dir = format!("layouts/keyboards/{}", keyboard);
+    write_json(
+        &format!("{}/layout.json", keyboard_dir),
+        &layout_json(&physical, &physical2)?,
+    )?;
+    write_json(&format!("{}/leds.json", keyboard_dir), &leds)?;
+    write_json(&format!("{}/keymap.json", keyboard_dir), &scancodes)?;
+    write_json(
+        &format!("layouts/{}/default.json", board),
+        &default_json(board, default_keymap, is_qmk),
+    )?;
+    update_meta_json(
+        &format!("layouts/{}/meta.json", board),
+        has_brightness,
+        has_color,
+        &keyboard,
+    )
+}
+
+fn run() -> Result<(), String> {
+    let mut is_qmk = false;
+    let mut args = Vec::new();
+    for arg in env::args().skip(1) {
+        if arg == "--qmk" {
+            is_qmk = true;
+        } else {
+            args.push(arg);
+        }
+    }
+    let (ecdir, board) = match args.as_slice() {
+        [ecdir, board] => (ecdir, board),
+        _ => return Err("usage: layouts [--qmk] ECDIR BOARD".to_string()),
+    };
+
+    if board != "all" {
+        return generate_layout_dir(ecdir, board, is_qmk);
+    }
+
+    let boarddir = if is_qmk {
+        format!("{}/keyboards/system76", ecdir)
+    } else {
+        format!("{}/src/board/system76", ecdir)
+    };
+    let entries =
+        fs::read_dir(&boarddir).map_err(|err| format!("Failed to read {}: {}", boarddir, err))?;
+    for entry in entries {
+        let entry = entry.map_err(|err| err.to_string())?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let board = format!("system76/{}", name);
+        if name == "common" || !entry.path().is_dir() || EXCLUDE_BOARDS.contains(&board.as_str()) {
+            continue;
+        }
+        generate_layout_dir(ecdir, &board, is_qmk)?;
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(x: &[&str]) -> Vec<String> {
+        x.iter().map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn scancode_exclusion() {
+        assert_eq!(
+            excluded_scancodes("system76/darp6", true, true),
+            ["INT_1", "INT_2", "KBD_BKL"]
+        );
+        assert_eq!(
+            excluded_scancodes("system76/bonw14", false, false),
+            ["INT_1", "INT_2", "KBD_BKL"]
+        );
+        assert_eq!(
+            excluded_scancodes("system76/galp5", true, false),
+            ["INT_1", "INT_2", "KBD_COLOR"]
+        );
+        assert_eq!(
+            excluded_scancodes("system76/galp3-c", false, false),
+            [
+                "INT_1",
+                "INT_2",
+                "KBD_COLOR",
+                "KBD_DOWN",
+                "KBD_UP",
+                "KBD_BKL",
+                "KBD_TOGGLE"
+            ]
+        );
+    }
+
+    #[test]
+    fn scancode_names() {
+        let mapping = QMK_MAPPING
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        assert_eq!(scancode_name("KC_ESCAPE", &mapping), "ESC");
+        assert_eq!(scancode_name("RGB_TOG", &mapping), "KBD_TOGGLE");
+        assert_eq!(scancode_name("MO(1)", &mapping), "FN");
+        assert_eq!(scancode_name("K_LEFT_SHIFT", &HashMap::new()), "LEFT_SHIFT");
+    }
+
+    #[test]
+    fn layout_define() {
+        let keymap_h = r"
+#define LAYOUT( \
+    K00, K01, /* comment */ \
+    K10, K11 \
+) { \
+    { K00, K01 }, \
+    { K10, K11 } \
+}
+";
+        let (physical, physical2) = parse_layout_define(keymap_h, false).unwrap();
+        assert_eq!(physical, strings(&["K00", "K01", "K10", "K11"]));
+        assert_eq!(
+            physical2,
+            vec![strings(&["K00", "K01"]), strings(&["K10", "K11"])]
+        );
+
+        let layout = layout_json(&physical, &physical2).unwrap();
+        assert_eq!(layout.get("K10"), Some(&(1, 0)));
+
+        let keymap_c = "LAYOUT(K_ESC, KT_FN, 0, K_A), /* LAYOUT(K_B) */ LAYOUT(K_F1, 0, 0, 0)";
+        let keymap = parse_keymap(keymap_c, &HashMap::new(), &physical, false).unwrap();
+        assert_eq!(keymap.get("K00"), Some(&strings(&["ESC", "F1"])));
+        assert_eq!(keymap.get("K01"), Some(&strings(&["FN", "NONE"])));
+        assert!(parse_keymap("LAYOUT(K_A)", &HashMap::new(), &physical, false).is_err());
+
+        let leds = parse_led_config("// LAYOUT(9)\nLAYOUT(3, 2, 1, 0)", &physical).unwrap();
+        assert_eq!(leds.get("K00"), Some(&vec![3]));
+        assert_eq!(
+            parse_led_config("", &physical).unwrap(),
+            OrderedMap::default()
+        );
+    }
+
+    #[test]
+    fn ordered_json() {
+        let json = r#"{"b": 1, "a": 2}"#;
+        let mut map: OrderedMap<Value> = serde_json::from_str(json).unwrap();
+        map.insert("b".to_string(), json!(3));
+        map.insert("c".to_string(), json!(4));
+        assert_eq!(
+            serde_json::to_string(&map).unwrap(),
+            r#"{"b":3,"a":2,"c":4}"#
+        );
+    }
+}
@@ -62,9 +62,13 @@ fn page(board: Board) -> gtk::Widget {
         }));
     };
 
-    // TODO detect when brightness changed in daemon
+    board.connect_brightness_changed(clone!(@weak board, @weak brightness_scale => move || {
+        brightness_scale.set_value(board.layers()[0].brightness() as f64);
+    }));
+
+    let button = KeyboardColor::new(Some(board.clone()), KeyboardColorIndex::Layer(0));
 
-    let button = KeyboardColor::new(Some(board), KeyboardColorIndex::Layer(0));
+    board.connect_color_changed(clone!(@weak button => move || button.read_color()));
 
     let listbox = cascade! {
         gtk::ListBox::new();
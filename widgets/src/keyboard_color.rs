@@ -10,7 +10,7 @@ use std::{
 };
 
 use crate::{choose_color, ColorCircle, DerefCell, SelectedKeys};
-use backend::{Board, Hs};
+use backend::{BackendError, Board, Hs};
 
 #[derive(Clone)]
 pub enum KeyboardColorIndex {
@@ -19,7 +19,7 @@ pub enum KeyboardColorIndex {
 }
 
 impl KeyboardColorIndex {
-    pub async fn set_color(&self, board: &Board, hs: Hs) -> Result<(), String> {
+    pub async fn set_color(&self, board: &Board, hs: Hs) -> Result<(), BackendError> {
         match self {
             KeyboardColorIndex::Keys(keys) => {
                 let futures = FuturesUnordered::new();
@@ -63,7 +63,7 @@ impl KeyboardColorIndex {
         &self,
         board: &Board,
         colors: &HashMap<usize, Hs>,
-    ) -> Result<(), String> {
+    ) -> Result<(), BackendError> {
         match self {
             KeyboardColorIndex::Keys(keys) => {
                 let futures = FuturesUnordered::new();
@@ -240,7 +240,9 @@ impl KeyboardColor {
         self.inner().index.borrow()
     }
 
-    fn read_color(&self) {
+    /// Read the color from the board again, such as after it was changed
+    /// outside of this widget
+    pub fn read_color(&self) {
         if let Some(board) = self.board() {
             let colors = self.index().get_color_set(&board);
             let hs = colors.iter().next().copied().unwrap_or(Hs::new(0., 0.));